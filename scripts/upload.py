@@ -1,4 +1,5 @@
 import json
+import secrets
 from redis import Redis
 from splitstream import splitfile
 
@@ -6,7 +7,29 @@ redis = Redis()
 
 redis.delete('events')
 
+seen = {}
+
+CROCKFORD = '0123456789ABCDEFGHJKMNPQRSTVWXYZ'
+
+
+def fresh_id(event_id):
+    # Keep the timestamp half of the ULID, so the event stays where it was in time.
+    return event_id[:10] + ''.join(secrets.choice(CROCKFORD) for _ in range(16))
+
+
 with open('data/db.stream', 'r') as infile:
     for event_json in splitfile(infile, format="json"):
         event = json.loads(event_json)
+        # Early on, a few IDs were given to more than one event. An exact repeat is skipped, but
+        # a different event which reused an ID is kept, under an ID of its own.
+        if event['id'] in seen:
+            if seen[event['id']] == event:
+                print('Skipping repeated event', event['id'])
+                continue
+            new_id = fresh_id(event['id'])
+            while new_id in seen:
+                new_id = fresh_id(event['id'])
+            print('Event', event['id'], 'was already used by another event. Uploading it as', new_id, event)
+            event = dict(event, id=new_id)
+        seen[event['id']] = event
         redis.xadd('events', event)
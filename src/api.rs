@@ -1,15 +1,60 @@
 use ulid::Ulid;
 use std::sync::Arc;
-use serenity::model::id::UserId;
+use std::time::Duration;
+use serenity::model::id::{ChannelId, UserId};
 use redis::Client;
 use redis::aio::Connection;
 use redis::AsyncCommands;
 use futures::lock::Mutex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use crate::exams::Question;
+use crate::questions::QuestionType;
+use crate::decks::Deck;
+use crate::stats::ExamRecord;
+use crate::profile::Profile;
+use crate::charts::Ranking;
+use crate::credit::CreditPoint;
 
+/// The actor for events which the bot raises on its own, like credit for winning a race.
+/// No profile exists for it.
+pub const SYSTEM_USER_ID: UserId = UserId(0);
+
+/// The Redis hash holding in-progress exams, keyed by user ID.
+const EXAM_CHECKPOINTS: &str = "exam_checkpoints";
+
+/// How long to wait on the GraphQL server before giving up on a request.
+const GRAPHQL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many times a mutation is sent before giving up, and how long to wait between attempts.
+/// The wait grows with each attempt.
+const COMMAND_ATTEMPTS: u32 = 3;
+const COMMAND_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+fn exam_cooldown_key(user_id: UserId, exam_name: &str) -> String {
+    format!("exam_cooldown:{}:{}", user_id, exam_name)
+}
+
+/// Why a GraphQL request failed.
+enum RequestError {
+    /// The request timed out or the connection failed, so the server may not have seen it.
+    Transport(String),
+    /// The server answered with errors. Sending the same request again gets the same answer.
+    Rejected(String),
+}
+
+/// The bot's connection to the rest of the system.
+///
+/// Fire-and-forget events are written straight to Redis.
+/// Anything which needs to be validated, changes social credit, or reads a profile goes through the
+/// GraphQL server at `GRAPHQL_URL`.
 #[derive(Clone)]
 #[non_exhaustive]
 pub struct Api {
     connection: Arc<Mutex<Connection>>,
+    http: reqwest::Client,
+    graphql_url: String,
 }
 
 impl Api {
@@ -17,9 +62,71 @@ impl Api {
         let host = std::env::var("REDIS_HOST").unwrap().to_string();
         let client = Client::open(host.clone()).unwrap();
         let connection = Arc::new(Mutex::new(client.get_async_connection().await.unwrap()));
+        let graphql_url = std::env::var("GRAPHQL_URL").unwrap();
 
         Api {
             connection,
+            http: reqwest::Client::builder().timeout(GRAPHQL_TIMEOUT).build().unwrap(),
+            graphql_url,
+        }
+    }
+
+    /// Runs a GraphQL query or mutation against the server and returns its `data`.
+    async fn graphql(&self, query: &str, variables: serde_json::Value) -> Result<serde_json::Value, String> {
+        self.request(query, variables).await.map_err(|e| match e {
+            RequestError::Transport(e) | RequestError::Rejected(e) => e,
+        })
+    }
+
+    async fn request(&self, query: &str, variables: serde_json::Value) -> Result<serde_json::Value, RequestError> {
+        let body = json!({
+            "query": query,
+            "variables": variables,
+        });
+
+        let response: serde_json::Value = self.http
+            .post(&self.graphql_url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| RequestError::Transport(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| RequestError::Transport(e.to_string()))?;
+
+        if let Some(errors) = response.get("errors") {
+            return Err(RequestError::Rejected(errors.to_string()));
+        }
+
+        Ok(response["data"].clone())
+    }
+
+    /// Runs a mutation which returns a `Command`, turning an unsuccessful command into an `Err`.
+    ///
+    /// The mutation must take an `$idempotencyKey`. The same key goes with every attempt, so if a
+    /// request times out after the server applied it, the retry is not applied a second time.
+    /// Only transport errors are retried; errors reported by the server are returned as they are.
+    async fn command(&self, mutation: &str, mut variables: serde_json::Value) -> Result<(), String> {
+        variables["idempotencyKey"] = json!(Ulid::new().to_string());
+
+        let mut attempt = 1;
+        let data = loop {
+            match self.request(mutation, variables.clone()).await {
+                Ok(data) => break data,
+                Err(RequestError::Transport(e)) if attempt < COMMAND_ATTEMPTS => {
+                    println!("Retrying command: {}", e);
+                    tokio::time::sleep(COMMAND_RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                },
+                Err(RequestError::Transport(e) | RequestError::Rejected(e)) => return Err(e),
+            }
+        };
+        let command = data.as_object().and_then(|data| data.values().next()).ok_or("Empty response")?;
+
+        if command["success"].as_bool() == Some(true) {
+            Ok(())
+        } else {
+            Err(command["error"].as_str().unwrap_or("Unknown error").to_string())
         }
     }
 
@@ -27,61 +134,63 @@ impl Api {
         &self,
         user_id: UserId,
         discord_name: String,
-    ) {
-        let mut conn = self.connection.lock().await;
-        let id = Ulid::new();
-        let () = conn.xadd("events", "*", &[
-            ("id", id.to_string()),
-            ("type", "ProfileRegistered".to_string()),
-            ("user_id", user_id.to_string()),
-            ("discord_username", discord_name.to_string()),
-        ]).await.unwrap();
-
+    ) -> Result<(), String> {
         println!("Registering:");
         println!("{:?}", user_id);
         println!("{:?}", discord_name);
-    }
 
+        self.command(
+            "mutation($userId: String!, $discordUsername: String!, $idempotencyKey: String) {
+                register(userId: $userId, discordUsername: $discordUsername, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({
+                "userId": user_id.to_string(),
+                "discordUsername": discord_name,
+            }),
+        ).await
+    }
 
     pub async fn jail(
         &self,
         to_user_id: UserId,
         by_user_id: UserId,
         reason: String,
-    ) {
-        let mut conn = self.connection.lock().await;
-        let id = Ulid::new();
-        let () = conn.xadd("events", "*", &[
-            ("type", "ComradeJailed".to_string()),
-            ("id", id.to_string()),
-            ("to_user_id", to_user_id.to_string()),
-            ("by_user_id", by_user_id.to_string()),
-            ("reason", reason.to_string()),
-        ]).await.unwrap();
-
+    ) -> Result<(), String> {
         println!("Jailing:");
         println!("{:?}", to_user_id);
         println!("{:?}", by_user_id);
         println!("{:?}", reason);
+
+        self.command(
+            "mutation($toUserId: String!, $byUserId: String!, $reason: String!, $idempotencyKey: String) {
+                jail(toUserId: $toUserId, byUserId: $byUserId, reason: $reason, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({
+                "toUserId": to_user_id.to_string(),
+                "byUserId": by_user_id.to_string(),
+                "reason": reason,
+            }),
+        ).await
     }
 
     pub async fn unjail(
         &self,
         to_user_id: UserId,
         by_user_id: UserId,
-    ) {
-        let mut conn = self.connection.lock().await;
-        let id = Ulid::new();
-        let () = conn.xadd("events", "*", &[
-            ("type", "ComradeUnjailed".to_string()),
-            ("id", id.to_string()),
-            ("to_user_id", to_user_id.to_string()),
-            ("by_user_id", by_user_id.to_string()),
-        ]).await.unwrap();
-
+    ) -> Result<(), String> {
         println!("Unjailing:");
         println!("{:?}", to_user_id);
         println!("{:?}", by_user_id);
+
+        self.command(
+            "mutation($toUserId: String!, $byUserId: String!, $idempotencyKey: String) {
+                unjail(toUserId: $toUserId, byUserId: $byUserId, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({
+                "toUserId": to_user_id.to_string(),
+                "byUserId": by_user_id.to_string(),
+            }),
+        ).await
     }
 
     pub async fn honor(
@@ -90,23 +199,24 @@ impl Api {
         by_user_id: UserId,
         amount: i32,
         reason: String,
-    ) {
-        let mut conn = self.connection.lock().await;
-        let id = Ulid::new();
-        let () = conn.xadd("events", "*", &[
-            ("type", "ComradeHonored".to_string()),
-            ("id", id.to_string()),
-            ("to_user_id", to_user_id.to_string()),
-            ("by_user_id", by_user_id.to_string()),
-            ("reason", reason.to_string()),
-            ("amount", amount.to_string()),
-        ]).await.unwrap();
-
+    ) -> Result<(), String> {
         println!("Honoring:");
         println!("{:?}", to_user_id);
         println!("{:?}", by_user_id);
         println!("{:?}", amount);
         println!("{:?}", reason);
+
+        self.command(
+            "mutation($toUserId: String!, $byUserId: String!, $amount: Int!, $reason: String!, $idempotencyKey: String) {
+                honor(toUserId: $toUserId, byUserId: $byUserId, amount: $amount, reason: $reason, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({
+                "toUserId": to_user_id.to_string(),
+                "byUserId": by_user_id.to_string(),
+                "amount": amount,
+                "reason": reason,
+            }),
+        ).await
     }
 
     pub async fn dishonor(
@@ -115,32 +225,299 @@ impl Api {
         by_user_id: UserId,
         amount: i32,
         reason: String,
-    ) {
-        let mut conn = self.connection.lock().await;
-        let id = Ulid::new();
-        let () = conn.xadd("events", "*", &[
-            ("type", "ComradeDishonored".to_string()),
-            ("id", id.to_string()),
-            ("to_user_id", to_user_id.to_string()),
-            ("by_user_id", by_user_id.to_string()),
-            ("reason", reason.to_string()),
-            ("amount", amount.to_string()),
-        ]).await.unwrap();
-
+    ) -> Result<(), String> {
         println!("Dishonoring:");
         println!("{:?}", to_user_id);
         println!("{:?}", by_user_id);
         println!("{:?}", amount);
         println!("{:?}", reason);
+
+        self.command(
+            "mutation($toUserId: String!, $byUserId: String!, $amount: Int!, $reason: String!, $idempotencyKey: String) {
+                dishonor(toUserId: $toUserId, byUserId: $byUserId, amount: $amount, reason: $reason, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({
+                "toUserId": to_user_id.to_string(),
+                "byUserId": by_user_id.to_string(),
+                "amount": amount,
+                "reason": reason,
+            }),
+        ).await
+    }
+
+    pub async fn mine(
+        &self,
+        user_id: UserId,
+        word: String,
+    ) -> Result<(), String> {
+        println!("Mining:");
+        println!("{:?}", user_id);
+        println!("{:?}", word);
+
+        self.command(
+            "mutation($userId: String!, $word: String!, $idempotencyKey: String) { mineWord(userId: $userId, word: $word, idempotencyKey: $idempotencyKey) { success error } }",
+            json!({ "userId": user_id.to_string(), "word": word }),
+        ).await
+    }
+
+    pub async fn unmine(
+        &self,
+        user_id: UserId,
+        word: String,
+    ) -> Result<(), String> {
+        println!("Unmining:");
+        println!("{:?}", user_id);
+        println!("{:?}", word);
+
+        self.command(
+            "mutation($userId: String!, $word: String!, $idempotencyKey: String) { unmineWord(userId: $userId, word: $word, idempotencyKey: $idempotencyKey) { success error } }",
+            json!({ "userId": user_id.to_string(), "word": word }),
+        ).await
+    }
+
+    pub async fn mined_words(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<String>, String> {
+        let data = self.graphql(
+            "query($userId: String!) { profile(userId: $userId) { minedWords } }",
+            json!({ "userId": user_id.to_string() }),
+        ).await?;
+
+        let words = data["profile"]["minedWords"]
+            .as_array()
+            .ok_or("No profile")?
+            .iter()
+            .filter_map(|word| word.as_str().map(|word| word.to_string()))
+            .collect();
+        Ok(words)
     }
 
-    pub fn log_message(
+    /// Fetches everything shown on a profile card.
+    pub async fn profile(
+        &self,
+        user_id: UserId,
+    ) -> Result<Profile, String> {
+        let data = self.graphql(
+            "query($userId: String!) {
+                profile(userId: $userId) {
+                    userId displayName roles credit yuan created hsk hanziCount
+                    hskCoverage { hskLevel known total percent }
+                }
+            }",
+            json!({ "userId": user_id.to_string() }),
+        ).await?;
+
+        serde_json::from_value(data["profile"].clone()).map_err(|e| e.to_string())
+    }
+
+    /// The comrades with the most social credit, best first.
+    pub async fn leaderboard(
+        &self,
+        limit: usize,
+    ) -> Result<Vec<Ranking>, String> {
+        let data = self.graphql(
+            "query($limit: Int) { leaderboard(limit: $limit) { rank userId displayName credit hsk } }",
+            json!({ "limit": limit }),
+        ).await?;
+
+        serde_json::from_value(data["leaderboard"].clone()).map_err(|e| e.to_string())
+    }
+
+    /// Every change to the user's social credit, oldest first.
+    pub async fn credit_history(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<CreditPoint>, String> {
+        let data = self.graphql(
+            "query($userId: String!) { creditHistory(userId: $userId) { at credit change reason } }",
+            json!({ "userId": user_id.to_string() }),
+        ).await?;
+
+        serde_json::from_value(data["creditHistory"].clone()).map_err(|e| e.to_string())
+    }
+
+    /// Records the result of reviewing a card, updating its spaced repetition schedule.
+    pub async fn review_card(
+        &self,
+        user_id: UserId,
+        question: &Question,
+        grade: u8,
+    ) -> Result<(), String> {
+        println!("Reviewing:");
+        println!("{:?}", user_id);
+        println!("{:?}", question.question);
+        println!("{:?}", grade);
+
+        self.command(
+            "mutation($userId: String!, $question: String!, $validAnswers: [String!]!, $meaning: String!, $grade: Int!, $idempotencyKey: String) {
+                reviewCard(userId: $userId, question: $question, validAnswers: $validAnswers, meaning: $meaning, grade: $grade, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({
+                "userId": user_id.to_string(),
+                "question": question.question,
+                "validAnswers": question.valid_answers,
+                "meaning": question.meaning,
+                "grade": grade,
+            }),
+        ).await
+    }
+
+    /// Keeps every graded answer from a finished exam for analytics.
+    pub async fn record_exam(
+        &self,
+        user_id: UserId,
+        record: &ExamRecord,
+    ) -> Result<(), String> {
+        self.command(
+            "mutation($userId: String!, $record: ExamRecordInput!, $idempotencyKey: String) {
+                recordExam(userId: $userId, record: $record, idempotencyKey: $idempotencyKey) { success error }
+            }",
+            json!({ "userId": user_id.to_string(), "record": record }),
+        ).await
+    }
+
+    /// Fetches the cards for the user's next review session.
+    pub async fn review_deck(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<Question>, String> {
+        let data = self.graphql(
+            "query($userId: String!) { reviewDeck(userId: $userId) { question validAnswers meaning } }",
+            json!({ "userId": user_id.to_string() }),
+        ).await?;
+
+        let cards = data["reviewDeck"].as_array().ok_or("No review deck")?;
+        let deck = cards
+            .iter()
+            .map(|card| Question {
+                question: card["question"].as_str().unwrap_or_default().to_string(),
+                valid_answers: card["validAnswers"]
+                    .as_array()
+                    .map(|answers| answers.iter().filter_map(|a| a.as_str().map(|a| a.to_string())).collect())
+                    .unwrap_or_default(),
+                meaning: card["meaning"].as_str().unwrap_or_default().to_string(),
+                kind: QuestionType::HanziToPinyin,
+            })
+            .collect();
+        Ok(deck)
+    }
+
+    /// Fetches a deck which was created on the server. Built-in decks are loaded with
+    /// [crate::decks::load_exams] instead.
+    pub async fn deck(
+        &self,
+        name: &str,
+    ) -> Result<Option<Deck>, String> {
+        let data = self.graphql(
+            "query($name: String!) {
+                deck(name: $name) {
+                    name numQuestions maxWrong timelimit hskLevel modes promptStyle
+                    deck { question validAnswers meaning }
+                }
+            }",
+            json!({ "name": name }),
+        ).await?;
+
+        if data["deck"].is_null() {
+            return Ok(None);
+        }
+        serde_json::from_value(data["deck"].clone()).map(Some).map_err(|e| e.to_string())
+    }
+
+    /// Saves the state of a user's exam so it can be resumed if the bot restarts.
+    /// Each user has at most one checkpoint. Saving replaces the previous one.
+    pub async fn save_exam_checkpoint<T: Serialize>(
+        &self,
+        user_id: UserId,
+        checkpoint: &T,
+    ) -> Result<(), String> {
+        let json = serde_json::to_string(checkpoint).map_err(|e| e.to_string())?;
+        let mut conn = self.connection.lock().await;
+        conn.hset(EXAM_CHECKPOINTS, user_id.to_string(), json).await.map_err(|e| e.to_string())
+    }
+
+    pub async fn clear_exam_checkpoint(
+        &self,
+        user_id: UserId,
+    ) -> Result<(), String> {
+        let mut conn = self.connection.lock().await;
+        conn.hdel(EXAM_CHECKPOINTS, user_id.to_string()).await.map_err(|e| e.to_string())
+    }
+
+    /// Loads every saved exam checkpoint.
+    ///
+    /// Checkpoints which can no longer be read (for instance, after the format changed) are
+    /// discarded.
+    pub async fn exam_checkpoints<T: DeserializeOwned>(
+        &self,
+    ) -> Result<Vec<(UserId, T)>, String> {
+        let mut conn = self.connection.lock().await;
+        let saved: Vec<(String, String)> = conn.hgetall(EXAM_CHECKPOINTS).await.map_err(|e| e.to_string())?;
+
+        let mut checkpoints = Vec::new();
+        for (user_id, json) in saved.into_iter() {
+            let parsed = user_id.parse::<u64>().ok().zip(serde_json::from_str(&json).ok());
+            match parsed {
+                Some((user_id, checkpoint)) => checkpoints.push((UserId(user_id), checkpoint)),
+                None => {
+                    println!("Discarding unreadable exam checkpoint for {}", user_id);
+                    let () = conn.hdel(EXAM_CHECKPOINTS, &user_id).await.map_err(|e| e.to_string())?;
+                },
+            }
+        }
+        Ok(checkpoints)
+    }
+
+    /// Prevents the user from taking the same exam again for a while.
+    pub async fn start_exam_cooldown(
+        &self,
+        user_id: UserId,
+        exam_name: &str,
+        seconds: usize,
+    ) -> Result<(), String> {
+        let mut conn = self.connection.lock().await;
+        conn.set_ex(exam_cooldown_key(user_id, exam_name), 1, seconds).await.map_err(|e| e.to_string())
+    }
+
+    /// The number of seconds before the user may take the exam again, if they are cooling down.
+    pub async fn exam_cooldown(
+        &self,
+        user_id: UserId,
+        exam_name: &str,
+    ) -> Result<Option<u64>, String> {
+        let mut conn = self.connection.lock().await;
+        let ttl: i64 = conn.ttl(exam_cooldown_key(user_id, exam_name)).await.map_err(|e| e.to_string())?;
+        // TTL is negative when the key is missing or never expires.
+        Ok(if ttl > 0 { Some(ttl as u64) } else { None })
+    }
+
+    pub async fn log_message(
         &self,
         by_user_id: UserId,
+        channel_id: ChannelId,
+        timestamp: i64,
         message: String,
-    ) {
+    ) -> Result<(), String> {
+        let mut conn = self.connection.lock().await;
+        let id = Ulid::new();
+        let length = message.chars().count();
+        let cjk_count = crate::hanzi::count_hanzi(&message);
+        let hanzi = crate::hanzi::distinct_hanzi(&message).concat();
+        let () = conn.xadd("messages", "*", &[
+            ("id", id.to_string()),
+            ("user_id", by_user_id.to_string()),
+            ("channel_id", channel_id.to_string()),
+            ("timestamp", timestamp.to_string()),
+            ("length", length.to_string()),
+            ("cjk_count", cjk_count.to_string()),
+            ("hanzi", hanzi),
+        ]).await.map_err(|e| e.to_string())?;
+
         println!("Log message:");
         println!("{:?}", by_user_id);
-        println!("{:?}", message);
+        println!("{:?}", channel_id);
+        println!("{:?}", length);
+        Ok(())
     }
 }
@@ -1,9 +1,14 @@
+mod exam_driver;
+mod race_driver;
+mod emoji_driver;
+
 use dotenv;
 use std::env;
-//use bytes::Bytes;
 
 use chairmanmao::api;
 use chairmanmao::command_parser;
+use chairmanmao::dictionary;
+use chairmanmao::draw::{Direction, FontRegistry, OutputFormat, RenderOptions};
 
 use serde::{Serialize, Deserialize};
 
@@ -11,6 +16,7 @@ use serenity::{
     async_trait,
     model::channel::Message,
     model::channel::Reaction,
+    model::channel::ReactionType,
     model::gateway::Ready,
     model::id::*,
     prelude::*,
@@ -25,12 +31,134 @@ struct User {
 
 struct Handler;
 
+/// Reacting to a message with this emoji mines the words in it.
+const MINE_EMOJI: &str = "⛏️";
+
+/// True for [MINE_EMOJI], whether or not the client sent it with its variation selector.
+fn is_mine_emoji(emoji: &ReactionType) -> bool {
+    let without_selector = |text: &str| text.replace('\u{fe0f}', "");
+    match emoji {
+        ReactionType::Unicode(text) => without_selector(text) == without_selector(MINE_EMOJI),
+        _ => false,
+    }
+}
+
+/// Where `!draw` wraps long text, in pixels.
+const DRAW_WRAP: u32 = 1600;
+
+/// How many comrades `!leaderboard` shows.
+const LEADERBOARD_SIZE: usize = 10;
+
 async fn api_from_context(ctx: &Context) -> api::Api {
     let data = ctx.data.read().await;
     let api = data.get::<Api>().unwrap();
     api.clone()
 }
 
+async fn dictionary_from_context(ctx: &Context) -> std::sync::Arc<dictionary::Dictionary> {
+    let data = ctx.data.read().await;
+    let dictionary = data.get::<Dictionary>().unwrap();
+    dictionary.clone()
+}
+
+async fn fonts_from_context(ctx: &Context) -> std::sync::Arc<FontRegistry> {
+    let data = ctx.data.read().await;
+    let fonts = data.get::<Fonts>().unwrap();
+    fonts.clone()
+}
+
+/// Downloads a user's avatar, at the size a profile card needs. Returns `None` if it can't be had.
+async fn download_avatar(user: &serenity::model::user::User) -> Option<image::RgbaImage> {
+    // Discord serves avatars as WebP unless asked otherwise.
+    let url = match user.avatar.as_ref() {
+        Some(hash) => format!("https://cdn.discordapp.com/avatars/{}/{}.png?size=256", user.id, hash),
+        None => user.default_avatar_url(),
+    };
+    download_image(&url).await
+}
+
+/// Downloads a custom emoji, to draw over. Returns `None` if it can't be had.
+async fn download_emoji(emoji_id: EmojiId) -> Option<image::RgbaImage> {
+    download_image(&format!("https://cdn.discordapp.com/emojis/{}.png", emoji_id)).await
+}
+
+async fn download_image(url: &str) -> Option<image::RgbaImage> {
+    let response = reqwest::get(url).await.ok()?.error_for_status().ok()?;
+    let bytes = response.bytes().await.ok()?;
+    image::load_from_memory(&bytes).ok().map(|image| image.to_rgba8())
+}
+
+/// Finds the image an emoji is drawn over: a custom emoji, given by name or as `<:name:id>`, or
+/// failing that, an image attached to the message.
+async fn emoji_template(ctx: &Context, constants: &chairmanmao::discord::DiscordConstants, msg: &Message, template: Option<&str>) -> Result<Option<image::RgbaImage>, String> {
+    let template = match template {
+        Some(template) => template,
+        None => match msg.attachments.first() {
+            Some(attachment) => {
+                let bytes = attachment.download().await.map_err(|e| e.to_string())?;
+                let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+                return Ok(Some(image.to_rgba8()));
+            },
+            None => return Ok(None),
+        },
+    };
+
+    let emoji_id = match serenity::utils::parse_emoji(template) {
+        Some(emoji) => emoji.id,
+        None => {
+            let name = template.trim_matches(':');
+            let emojis = constants.guild_id.emojis(ctx).await.map_err(|e| e.to_string())?;
+            emojis.into_iter().find(|emoji| emoji.name == name).ok_or(format!("No such emoji: {}", template))?.id
+        },
+    };
+    match download_emoji(emoji_id).await {
+        Some(image) => Ok(Some(image)),
+        None => Err(format!("Could not download {}", template)),
+    }
+}
+
+/// Strips a leading `--flag` from a command's arguments, returning what follows it.
+fn strip_flag<'a>(args: &'a str, flag: &str) -> Option<&'a str> {
+    let rest = args.strip_prefix(flag)?;
+    if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+async fn active_exams_from_context(ctx: &Context) -> exam_driver::ActiveExams {
+    let data = ctx.data.read().await;
+    let active_exams = data.get::<ActiveExams>().unwrap();
+    active_exams.clone()
+}
+
+async fn active_races_from_context(ctx: &Context) -> race_driver::ActiveRaces {
+    let data = ctx.data.read().await;
+    let active_races = data.get::<ActiveRaces>().unwrap();
+    active_races.clone()
+}
+
+async fn pending_emojis_from_context(ctx: &Context) -> emoji_driver::PendingEmojis {
+    let data = ctx.data.read().await;
+    let pending_emojis = data.get::<PendingEmojis>().unwrap();
+    pending_emojis.clone()
+}
+
+/// Finds an exam by name, trying the built-in decks before the ones created on the server.
+async fn find_exam(api: &api::Api, exam_name: &str) -> Option<chairmanmao::exams::Exam> {
+    match chairmanmao::decks::load_exam(exam_name) {
+        Some(exam) => Some(exam),
+        None => match api.deck(exam_name).await {
+            Ok(deck) => deck.map(|deck| deck.to_exam()),
+            Err(e) => {
+                println!("Could not load deck {}: {}", exam_name, e);
+                None
+            },
+        },
+    }
+}
+
 async fn discord_constants_from_context(ctx: &Context) -> chairmanmao::discord::DiscordConstants {
     let data = ctx.data.read().await;
     let discord_constants = data.get::<DiscordConstants>().unwrap().as_ref().unwrap();
@@ -52,7 +180,10 @@ impl Handler {
                 parser.end()?;
                 let user = ctx.http.get_user(user_id.0).await.unwrap();
                 let username = format!("{}#{}", user.name, user.discriminator);
-                api.register(user_id, username).await;
+                if let Err(e) = api.register(user_id, username).await {
+                    msg.reply(&ctx, e).await.unwrap();
+                    return Some(());
+                }
                 constants.tiananmen_channel.say(&ctx, "Hey").await.unwrap();
             },
             "honor" => {
@@ -62,8 +193,10 @@ impl Handler {
                 let reason = parser.parse_rest();
                 parser.end()?;
 
-
-                api.honor(to_user_id, by_user_id, amount, reason).await;
+                if let Err(e) = api.honor(to_user_id, by_user_id, amount, reason).await {
+                    msg.reply(&ctx, e).await.unwrap();
+                    return Some(());
+                }
                 chairmanmao::messages::comrade_honored(&ctx, msg.channel_id, amount as u32).await.unwrap();
             },
             "dishonor" => {
@@ -72,34 +205,335 @@ impl Handler {
                 let amount = i32::try_from(parser.parse_integer()?).ok()?;
                 let reason = parser.parse_rest();
                 parser.end()?;
-                api.dishonor(to_user_id, by_user_id, amount, reason).await;
-                chairmanmao::messages::comrade_dishonored(&ctx, msg.channel_id, (-amount) as u32).await.unwrap();
+                if let Err(e) = api.dishonor(to_user_id, by_user_id, amount, reason).await {
+                    msg.reply(&ctx, e).await.unwrap();
+                    return Some(());
+                }
+                chairmanmao::messages::comrade_dishonored(&ctx, msg.channel_id, amount as u32).await.unwrap();
             },
             "jail" => {
                 let to_user_id = parser.parse_user_id()?;
                 let by_user_id = msg.author.id;
                 let reason = parser.parse_rest();
                 parser.end()?;
-                api.jail(to_user_id, by_user_id, reason).await;
+                if let Err(e) = api.jail(to_user_id, by_user_id, reason).await {
+                    msg.reply(&ctx, e).await.unwrap();
+                }
             },
             "unjail" => {
                 let to_user_id = parser.parse_user_id()?;
                 let by_user_id = msg.author.id;
                 parser.end()?;
-                api.unjail(to_user_id, by_user_id).await;
+                if let Err(e) = api.unjail(to_user_id, by_user_id).await {
+                    msg.reply(&ctx, e).await.unwrap();
+                }
             },
             "draw" => {
-                let text = parser.parse_rest();
+                let rest = parser.parse_rest();
                 parser.end()?;
-                chairmanmao::draw::draw(&text);
-                let filepath = std::path::Path::new("out.png");
-                msg.channel_id.send_message(&ctx, |m| {
-                    m.add_file(filepath)
-                }).await.unwrap();
-
+                let mut options = RenderOptions {
+                    wrap: Some(DRAW_WRAP),
+                    ..RenderOptions::default()
+                };
+
+                // The text may span several lines, so the flags are taken off the front by hand.
+                let mut text = rest.as_str();
+                let mut pinyin = false;
+                loop {
+                    if let Some(after) = strip_flag(text, "--vertical") {
+                        options.direction = Direction::Vertical;
+                        text = after;
+                    } else if let Some(after) = strip_flag(text, "--pinyin") {
+                        pinyin = true;
+                        text = after;
+                    } else if let Some(after) = strip_flag(text, "--font") {
+                        let font = after.split_whitespace().next().unwrap_or_default();
+                        options.font = font.to_string();
+                        text = after[font.len()..].trim_start();
+                    } else {
+                        break;
+                    }
+                }
+
+                let fonts = fonts_from_context(&ctx).await;
+                let drawn = if pinyin {
+                    // Pinyin can be given in brackets, as in 你好[ni3 hao3]. Otherwise it comes from the dictionary.
+                    let dictionary = dictionary_from_context(&ctx).await;
+                    fonts.draw_ruby(&chairmanmao::draw::ruby(text, &dictionary), &options)
+                } else {
+                    fonts.draw(text, &options)
+                };
+                match drawn {
+                    Ok(bytes) => {
+                        let filename = format!("draw.{}", options.format.extension());
+                        let sent = msg.channel_id.send_message(&ctx, |m| {
+                            m.add_file((bytes.as_slice(), filename.as_str()))
+                        }).await;
+                        if let Err(e) = sent {
+                            msg.reply(&ctx, format!("Could not send the drawing: {}", e)).await.unwrap();
+                        }
+                    },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "profile" => {
+                let _rest = parser.parse_rest();
+                parser.end()?;
+                let user = msg.mentions.first().unwrap_or(&msg.author);
+                let profile = match api.profile(user.id).await {
+                    Ok(profile) => profile,
+                    Err(e) => {
+                        msg.reply(&ctx, e).await.unwrap();
+                        return Some(());
+                    },
+                };
+
+                let fonts = fonts_from_context(&ctx).await;
+                let avatar = download_avatar(user).await;
+                let card = chairmanmao::profile::render_card(&fonts, &profile, avatar.as_ref())
+                    .and_then(|card| chairmanmao::draw::encode(&card, OutputFormat::Png));
+                match card {
+                    Ok(png) => {
+                        msg.channel_id.send_message(&ctx, |m| {
+                            m.add_file((png.as_slice(), "profile.png"))
+                        }).await.unwrap();
+                    },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "leaderboard" => {
+                parser.end()?;
+                let rankings = match api.leaderboard(LEADERBOARD_SIZE).await {
+                    Ok(rankings) => rankings,
+                    Err(e) => {
+                        msg.reply(&ctx, e).await.unwrap();
+                        return Some(());
+                    },
+                };
+
+                let fonts = fonts_from_context(&ctx).await;
+                let board = chairmanmao::charts::render_leaderboard(&fonts, &rankings)
+                    .and_then(|board| chairmanmao::draw::encode(&board, OutputFormat::Png));
+                match board {
+                    Ok(png) => {
+                        msg.channel_id.send_message(&ctx, |m| {
+                            m.add_file((png.as_slice(), "leaderboard.png"))
+                        }).await.unwrap();
+                    },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "history" => {
+                let _rest = parser.parse_rest();
+                parser.end()?;
+                let user = msg.mentions.first().unwrap_or(&msg.author);
+                let history = match api.credit_history(user.id).await {
+                    Ok(history) => history,
+                    Err(e) => {
+                        msg.reply(&ctx, e).await.unwrap();
+                        return Some(());
+                    },
+                };
+                if history.is_empty() {
+                    msg.reply(&ctx, format!("No credit history for {}", user.name)).await.unwrap();
+                    return Some(());
+                }
+
+                let fonts = fonts_from_context(&ctx).await;
+                let chart = chairmanmao::charts::render_credit_history(&fonts, &user.name, &history)
+                    .and_then(|chart| chairmanmao::draw::encode(&chart, OutputFormat::Png));
+                match chart {
+                    Ok(png) => {
+                        msg.channel_id.send_message(&ctx, |m| {
+                            m.add_file((png.as_slice(), "history.png"))
+                        }).await.unwrap();
+                    },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "emoji" => {
+                let rest = parser.parse_rest();
+                parser.end()?;
+                let mut options = RenderOptions::default();
+                let mut template = None;
+
+                let mut args = rest.as_str();
+                loop {
+                    if let Some(after) = strip_flag(args, "--font") {
+                        let font = after.split_whitespace().next().unwrap_or_default();
+                        options.font = font.to_string();
+                        args = after[font.len()..].trim_start();
+                    } else if let Some(after) = strip_flag(args, "--template") {
+                        let name = after.split_whitespace().next().unwrap_or_default();
+                        template = Some(name);
+                        args = after[name.len()..].trim_start();
+                    } else {
+                        break;
+                    }
+                }
+
+                let (name, text) = match args.split_once(char::is_whitespace) {
+                    Some((name, text)) => (name.trim_matches(':'), text.trim()),
+                    None => {
+                        msg.reply(&ctx, "Usage: `!emoji [--font <name>] [--template <emoji>] <name> <text>`").await.unwrap();
+                        return Some(());
+                    },
+                };
+                if let Err(e) = chairmanmao::emoji::validate_name(name) {
+                    msg.reply(&ctx, e).await.unwrap();
+                    return Some(());
+                }
+                if let Ok(emojis) = constants.guild_id.emojis(&ctx).await {
+                    if emojis.iter().any(|emoji| emoji.name == name) {
+                        msg.reply(&ctx, format!("There is already an emoji called :{}:", name)).await.unwrap();
+                        return Some(());
+                    }
+                }
+
+                let template = match emoji_template(&ctx, &constants, &msg, template).await {
+                    Ok(template) => template,
+                    Err(e) => {
+                        msg.reply(&ctx, e).await.unwrap();
+                        return Some(());
+                    },
+                };
+
+                let fonts = fonts_from_context(&ctx).await;
+                let emoji = chairmanmao::emoji::render_emoji(&fonts, text, &options, template.as_ref())
+                    .and_then(|emoji| chairmanmao::emoji::encode_emoji(&emoji));
+                match emoji {
+                    Ok(png) => {
+                        let pending_emojis = pending_emojis_from_context(&ctx).await;
+                        pending_emojis.propose(&ctx, msg.channel_id, msg.author.id, name.to_string(), png).await.unwrap();
+                    },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "fonts" => {
+                parser.end()?;
+                let fonts = fonts_from_context(&ctx).await;
+                let names: Vec<String> = fonts
+                    .names()
+                    .iter()
+                    .map(|name| if *name == chairmanmao::draw::DEFAULT_FONT { format!("{} (default)", name) } else { name.to_string() })
+                    .collect();
+                msg.reply(&ctx, format!("Fonts: {}\nUse one with `!draw --font <name> <text>`.", names.join(", "))).await.unwrap();
+            },
+            "mine" => {
+                let word = parser.parse_rest();
+                parser.end()?;
+                match api.mine(msg.author.id, word).await {
+                    Ok(()) => { msg.react(&ctx, '✅').await.unwrap(); },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "unmine" => {
+                let word = parser.parse_rest();
+                parser.end()?;
+                match api.unmine(msg.author.id, word).await {
+                    Ok(()) => { msg.react(&ctx, '✅').await.unwrap(); },
+                    Err(e) => { msg.reply(&ctx, e).await.unwrap(); },
+                }
+            },
+            "mined" => {
+                parser.end()?;
+                let dictionary = dictionary_from_context(&ctx).await;
+                let words = match api.mined_words(msg.author.id).await {
+                    Ok(words) => words,
+                    Err(e) => {
+                        msg.reply(&ctx, e).await.unwrap();
+                        return Some(());
+                    },
+                };
+                chairmanmao::messages::mined_words(&ctx, msg.channel_id, &words, &dictionary).await.unwrap();
+            },
+            "exam" | "practice" => {
+                let exam_name = parser.parse_rest();
+                parser.end()?;
+                match find_exam(&api, &exam_name).await {
+                    Some(exam) => {
+                        let active_exams = active_exams_from_context(&ctx).await;
+                        let kind = if command_name == "practice" {
+                            exam_driver::ExamKind::Practice
+                        } else {
+                            exam_driver::ExamKind::Exam
+                        };
+
+                        if kind == exam_driver::ExamKind::Exam {
+                            match api.exam_cooldown(msg.author.id, &exam.name).await {
+                                Ok(Some(seconds)) => {
+                                    let minutes = seconds.div_ceil(60);
+                                    let message = format!("You may retake {} in {} minutes. Try !practice in the meantime.", exam.name, minutes);
+                                    msg.reply(&ctx, message).await.unwrap();
+                                    return Some(());
+                                },
+                                Ok(None) => (),
+                                Err(e) => println!("Could not check exam cooldown: {}", e),
+                            }
+                        }
+
+                        if !active_exams.start(ctx.clone(), api, msg.author.id, msg.channel_id, exam, kind).await {
+                            msg.reply(&ctx, "You are already taking an exam.").await.unwrap();
+                        }
+                    },
+                    None => {
+                        msg.reply(&ctx, format!("No such exam: {}", exam_name)).await.unwrap();
+                    },
+                }
+            },
+            "race" => {
+                let rest = parser.parse_rest();
+                parser.end()?;
+                let (credit, exam_name) = match rest.strip_prefix("--credit ") {
+                    Some(exam_name) => (true, exam_name.trim().to_string()),
+                    None => (false, rest),
+                };
+
+                // Races for credit mint social credit, so only the Party may call them.
+                if credit {
+                    let is_party = msg.member.as_ref().map(|member| member.roles.contains(&constants.party_role.id)).unwrap_or(false);
+                    if !is_party {
+                        msg.reply(&ctx, "Only Party members may start a race for credit.").await.unwrap();
+                        return Some(());
+                    }
+                }
+
+                match find_exam(&api, &exam_name).await {
+                    Some(exam) => {
+                        let active_races = active_races_from_context(&ctx).await;
+                        if !active_races.start(ctx.clone(), api, msg.author.id, msg.channel_id, exam, credit).await {
+                            msg.reply(&ctx, "A race is already running in this channel.").await.unwrap();
+                        }
+                    },
+                    None => {
+                        msg.reply(&ctx, format!("No such exam: {}", exam_name)).await.unwrap();
+                    },
+                }
+            },
+            "review" => {
+                parser.end()?;
+                let deck = match api.review_deck(msg.author.id).await {
+                    Ok(deck) => deck,
+                    Err(e) => {
+                        msg.reply(&ctx, e).await.unwrap();
+                        return Some(());
+                    },
+                };
+
+                if deck.is_empty() {
+                    msg.reply(&ctx, "Nothing to review. Use `!mine <word>` to add words.").await.unwrap();
+                    return Some(());
+                }
+
+                let exam = chairmanmao::review::review_exam(deck);
+                let active_exams = active_exams_from_context(&ctx).await;
+                let kind = exam_driver::ExamKind::Review;
+                if !active_exams.start(ctx.clone(), api, msg.author.id, msg.channel_id, exam, kind).await {
+                    msg.reply(&ctx, "You are already taking an exam.").await.unwrap();
+                }
             },
             "ping" => {
-                let exam = chairmanmao::exams::load::load_exam("hsk1");
+                let exam = chairmanmao::decks::load_exam("hsk1")?;
                 chairmanmao::messages::exam_start(&ctx, msg.channel_id, &exam).await.unwrap();
             },
             _ => return None,
@@ -116,12 +550,40 @@ async fn reaction_users(ctx: Context, reaction: Reaction) -> Option<(UserId, Use
     return Some((to_user_id, by_user_id));
 }
 
+/// Mines every word in the reacted-to message on behalf of the user who reacted.
+async fn mine_reaction(ctx: Context, reaction: Reaction) -> Option<()> {
+    let api = api_from_context(&ctx).await;
+    let dictionary = dictionary_from_context(&ctx).await;
+    let user_id = reaction.user_id?;
+    let message = reaction.message(&ctx).await.ok()?;
+
+    for word in dictionary.segment(&message.content) {
+        // Words which have already been mined are rejected by the server. That's fine.
+        if let Err(e) = api.mine(user_id, word).await {
+            println!("{}", e);
+        }
+    }
+    Some(())
+}
+
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
         let api = api_from_context(&ctx).await;
         println!("{:?}", msg);
-        api.log_message(msg.author.id, msg.content.clone());
+        if let Err(e) = api.log_message(msg.author.id, msg.channel_id, msg.timestamp.timestamp_millis(), msg.content.clone()).await {
+            eprintln!("Could not log message {}: {}", msg.id, e);
+        }
+
+        let active_exams = active_exams_from_context(&ctx).await;
+        if active_exams.forward(&msg).await {
+            return;
+        }
+
+        let active_races = active_races_from_context(&ctx).await;
+        if active_races.forward(&msg).await {
+            return;
+        }
 
         if msg.content.starts_with("!") {
             self.run_command(ctx, msg).await;
@@ -130,22 +592,47 @@ impl EventHandler for Handler {
 
     async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
         let api = api_from_context(&ctx).await;
+        if is_mine_emoji(&reaction.emoji) {
+            mine_reaction(ctx, reaction).await;
+            return;
+        }
+
+        // Reactions on an emoji proposal are votes, not honor.
+        let constants = discord_constants_from_context(&ctx).await;
+        let pending_emojis = pending_emojis_from_context(&ctx).await;
+        if pending_emojis.review(&ctx, &constants, &reaction).await {
+            return;
+        }
+
         if let Some((to_user_id, by_user_id)) = reaction_users(ctx, reaction).await {
             if to_user_id != by_user_id {
                 let amount = 1;
                 let reason = "[REACTION]".to_owned();
-                api.honor(to_user_id, by_user_id, amount, reason).await;
+                if let Err(e) = api.honor(to_user_id, by_user_id, amount, reason).await {
+                    println!("{}", e);
+                }
             }
         }
     }
 
     async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
         let api = api_from_context(&ctx).await;
+        if is_mine_emoji(&reaction.emoji) {
+            return;
+        }
+
+        let pending_emojis = pending_emojis_from_context(&ctx).await;
+        if pending_emojis.is_pending(reaction.message_id).await {
+            return;
+        }
+
         if let Some((to_user_id, by_user_id)) = reaction_users(ctx, reaction).await {
             if to_user_id != by_user_id {
                 let amount = 1;
                 let reason = "[REACTION]".to_owned();
-                api.dishonor(to_user_id, by_user_id, amount, reason).await;
+                if let Err(e) = api.dishonor(to_user_id, by_user_id, amount, reason).await {
+                    println!("{}", e);
+                }
             }
         }
     }
@@ -164,6 +651,10 @@ impl EventHandler for Handler {
 
         let discord_constants = discord_constants_from_context(&ctx).await;
         discord_constants.tiananmen_channel.say(&ctx, format!("Online {}", discord_constants.mao_emoji)).await.unwrap();
+
+        let api = api_from_context(&ctx).await;
+        let active_exams = active_exams_from_context(&ctx).await;
+        active_exams.resume_checkpoints(ctx.clone(), api).await;
         //tokio::spawn(background_loop());
     }
 }
@@ -174,28 +665,35 @@ async fn background_loop() {
 }
 */
 
-/*
-async fn download_emoji(emoji_id: &str) {
-    let res = reqwest::get(format!("https://cdn.discordapp.com/emojis/{}.png", emoji_id))
-        .await
-        .unwrap();
 
-    let headers = res.headers().clone();
-    let data: Bytes = res.bytes().await.unwrap();
+struct Api;
+impl TypeMapKey for Api {
+    type Value = api::Api;
+}
 
-    println!("Headers {:?}", headers);
-    println!("Name {:?}", &data);
+struct Dictionary;
+impl TypeMapKey for Dictionary {
+    type Value = std::sync::Arc<dictionary::Dictionary>;
+}
 
-    let mut outfile = std::fs::File::create("out.png").unwrap();
-    let bytes: Vec<u8> = data.to_vec();
-    copy(&mut std::io::Cursor::new(bytes), &mut outfile).unwrap();
+struct Fonts;
+impl TypeMapKey for Fonts {
+    type Value = std::sync::Arc<FontRegistry>;
 }
-*/
 
+struct ActiveExams;
+impl TypeMapKey for ActiveExams {
+    type Value = exam_driver::ActiveExams;
+}
 
-struct Api;
-impl TypeMapKey for Api {
-    type Value = api::Api;
+struct ActiveRaces;
+impl TypeMapKey for ActiveRaces {
+    type Value = race_driver::ActiveRaces;
+}
+
+struct PendingEmojis;
+impl TypeMapKey for PendingEmojis {
+    type Value = emoji_driver::PendingEmojis;
 }
 
 struct DiscordConstants;
@@ -207,11 +705,12 @@ impl TypeMapKey for DiscordConstants {
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
-//    let emoji_id = "930693102266646598"; // eek
-//    download_emoji(emoji_id).await;
 
     let token = env::var("DISCORD_TOKEN").unwrap();
     let api = api::Api::new().await;
+    let exams = chairmanmao::decks::load_exams();
+    let dictionary = dictionary::Dictionary::from_exams(&exams);
+    let fonts = FontRegistry::load(chairmanmao::draw::FONT_DIR).unwrap();
 
     let mut client = Client::builder(&token)
         .event_handler(Handler)
@@ -221,6 +720,11 @@ async fn main() {
     {
         let mut data = client.data.write().await;
         data.insert::<Api>(api);
+        data.insert::<Dictionary>(std::sync::Arc::new(dictionary));
+        data.insert::<Fonts>(std::sync::Arc::new(fonts));
+        data.insert::<ActiveExams>(exam_driver::ActiveExams::default());
+        data.insert::<ActiveRaces>(race_driver::ActiveRaces::default());
+        data.insert::<PendingEmojis>(emoji_driver::PendingEmojis::default());
         data.insert::<DiscordConstants>(None);
     }
 
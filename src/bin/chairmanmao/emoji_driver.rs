@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+use chairmanmao::discord::DiscordConstants;
+
+/// A Party member reacts to a proposal with this to add the emoji to the server.
+pub const APPROVE_EMOJI: &str = "✅";
+
+/// A Party member reacts to a proposal with this to turn it down.
+pub const REJECT_EMOJI: &str = "❌";
+
+struct Proposal {
+    name: String,
+    png: Vec<u8>,
+    proposed_by: UserId,
+}
+
+/// Emoji waiting on the Party, keyed by the message which previews them.
+///
+/// Proposals only live in memory, so any still waiting when the bot restarts are forgotten.
+#[derive(Clone, Default)]
+pub struct PendingEmojis {
+    proposals: Arc<Mutex<HashMap<MessageId, Proposal>>>,
+}
+
+impl PendingEmojis {
+    /// Posts a preview of the emoji for the Party to approve or reject.
+    pub async fn propose(
+        &self,
+        ctx: &Context,
+        channel_id: ChannelId,
+        proposed_by: UserId,
+        name: String,
+        png: Vec<u8>,
+    ) -> serenity::Result<()> {
+        let filename = format!("{}.png", name);
+        let content = format!(
+            "<@{}> proposes :{}:. A Party member may react {} to add it, or {} to turn it down.",
+            proposed_by, name, APPROVE_EMOJI, REJECT_EMOJI,
+        );
+        let preview = channel_id.send_message(ctx, |m| {
+            m.content(content).add_file((png.as_slice(), filename.as_str()))
+        }).await?;
+
+        self.proposals.lock().await.insert(preview.id, Proposal {
+            name,
+            png,
+            proposed_by,
+        });
+
+        preview.react(ctx, ReactionType::Unicode(APPROVE_EMOJI.to_string())).await?;
+        preview.react(ctx, ReactionType::Unicode(REJECT_EMOJI.to_string())).await?;
+        Ok(())
+    }
+
+    /// True if the message is a proposal still waiting on the Party.
+    pub async fn is_pending(&self, message_id: MessageId) -> bool {
+        self.proposals.lock().await.contains_key(&message_id)
+    }
+
+    /// Acts on a Party member approving or rejecting a proposal. Reactions from anyone else are
+    /// ignored.
+    ///
+    /// Returns true if the reaction was on a proposal, whatever came of it.
+    pub async fn review(&self, ctx: &Context, constants: &DiscordConstants, reaction: &Reaction) -> bool {
+        if !self.is_pending(reaction.message_id).await {
+            return false;
+        }
+
+        let approved = reaction.emoji.unicode_eq(APPROVE_EMOJI);
+        let rejected = reaction.emoji.unicode_eq(REJECT_EMOJI);
+        let user_id = match reaction.user_id {
+            Some(user_id) if user_id != constants.bot_user_id && (approved || rejected) => user_id,
+            _ => return true,
+        };
+
+        let is_party = match constants.guild_id.member(ctx, user_id).await {
+            Ok(member) => member.roles.contains(&constants.party_role.id),
+            Err(_) => false,
+        };
+        if !is_party {
+            return true;
+        }
+
+        // Two Party members may react at once. Only the first gets the proposal.
+        let proposal = match self.proposals.lock().await.remove(&reaction.message_id) {
+            Some(proposal) => proposal,
+            None => return true,
+        };
+
+        let reply = if rejected {
+            format!("<@{}> The Party has turned down :{}:.", proposal.proposed_by, proposal.name)
+        } else {
+            let image = chairmanmao::emoji::data_uri(&proposal.png);
+            match constants.guild_id.create_emoji(&ctx.http, &proposal.name, &image).await {
+                Ok(emoji) => format!("<@{}> The Party has approved {}", proposal.proposed_by, emoji),
+                Err(e) => format!("Could not add :{}: {}", proposal.name, e),
+            }
+        };
+        reaction.channel_id.say(ctx, reply).await.unwrap();
+        true
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
+
+use chairmanmao::api::Api;
+use chairmanmao::clock::SystemClock;
+use chairmanmao::exams::{Answer, Exam, ExamOptions, Examiner, PromptStyle, TickResult};
+use chairmanmao::questions::QuestionType;
+use chairmanmao::stats::ExamRecord;
+
+/// How often the examiner is polled. Timing itself comes from the examiner's clock.
+const MILLIS_PER_TICK: u64 = 100;
+
+/// How long after finishing (or abandoning) an exam before the same user may take it again.
+pub const EXAM_COOLDOWN_SECS: usize = 60 * 60;
+
+/// Exams interrupted for longer than this are voided instead of resumed.
+const RESUME_WINDOW_MILLIS: u64 = 15 * 60 * 1000;
+
+/// What an exam is for. This decides what happens with the answers once it's over.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExamKind {
+    /// A regular exam, which counts towards pass rates and the cooldown.
+    /// Missed pronunciation questions are added to the examinee's review schedule.
+    Exam,
+    /// A practice run through a whole deck. It never affects HSK or credit.
+    /// Tones are graded leniently.
+    /// Missed pronunciation questions are added to the examinee's review schedule.
+    Practice,
+    /// A spaced repetition review, run under practice rules.
+    /// Every answer updates the examinee's review schedule.
+    Review,
+}
+
+impl ExamKind {
+    fn options(&self) -> ExamOptions {
+        let practice = *self != ExamKind::Exam;
+        ExamOptions {
+            practice,
+            tones_lenient: practice,
+            timelimit: None,
+        }
+    }
+}
+
+/// An exam in progress, saved after every question and answer.
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    channel_id: ChannelId,
+    kind: ExamKind,
+    saved_at: u64,
+    exam: SavedExam,
+    examiner: &'a Examiner,
+}
+
+/// A saved [CheckpointRef], loaded on startup.
+#[derive(Deserialize)]
+struct SavedCheckpoint {
+    channel_id: ChannelId,
+    kind: ExamKind,
+    saved_at: u64,
+    exam: SavedExam,
+    examiner: Examiner,
+}
+
+/// What a checkpoint keeps of an [Exam]. The questions being asked are already in the [Examiner],
+/// so the deck itself isn't saved.
+#[derive(Serialize, Deserialize)]
+struct SavedExam {
+    name: String,
+    prompt_style: PromptStyle,
+    hsk_level: usize,
+}
+
+impl SavedExam {
+    fn new(exam: &Exam) -> SavedExam {
+        SavedExam {
+            name: exam.name.clone(),
+            prompt_style: exam.prompt_style,
+            hsk_level: exam.hsk_level,
+        }
+    }
+
+    /// Puts the exam back together around the questions the examiner is asking.
+    fn restore(self, examiner: &Examiner) -> Exam {
+        Exam {
+            name: self.name,
+            cards: Vec::new(),
+            modes: Vec::new(),
+            deck: examiner.questions().to_vec(),
+            num_questions: examiner.num_questions(),
+            max_wrong: examiner.max_wrong(),
+            timelimit: examiner.timelimit() as usize,
+            hsk_level: self.hsk_level,
+            prompt_style: self.prompt_style,
+        }
+    }
+}
+
+/// An exam being run. New exams start out as one, and saved ones are restored to one.
+struct Checkpoint {
+    channel_id: ChannelId,
+    kind: ExamKind,
+    exam: Exam,
+    examiner: Examiner,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+struct Session {
+    channel_id: ChannelId,
+    answers: mpsc::UnboundedSender<Message>,
+}
+
+/// Takes a user's session out of [ActiveExams] when their exam ends, however it ends, so they can
+/// start another.
+struct SessionGuard {
+    sessions: Arc<Mutex<HashMap<UserId, Session>>>,
+    user_id: UserId,
+    answers: mpsc::UnboundedSender<Message>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let sessions = self.sessions.clone();
+        let user_id = self.user_id;
+        let answers = self.answers.clone();
+        // Locking the sessions may have to wait, and drop can't, so removal gets a task of its own.
+        tokio::spawn(async move {
+            let mut sessions = sessions.lock().await;
+            // Leave alone any exam the user started since.
+            if matches!(sessions.get(&user_id), Some(session) if session.answers.same_channel(&answers)) {
+                sessions.remove(&user_id);
+            }
+        });
+    }
+}
+
+/// The exams currently running in Discord. Each user may take one exam at a time.
+#[derive(Clone, Default)]
+pub struct ActiveExams {
+    sessions: Arc<Mutex<HashMap<UserId, Session>>>,
+}
+
+impl ActiveExams {
+    /// Passes the message along to the author's exam, if they are taking one in that channel.
+    ///
+    /// Returns true if the message was consumed as an answer.
+    pub async fn forward(&self, msg: &Message) -> bool {
+        let sessions = self.sessions.lock().await;
+        match sessions.get(&msg.author.id) {
+            Some(session) if session.channel_id == msg.channel_id => {
+                session.answers.send(msg.clone()).is_ok()
+            },
+            _ => false,
+        }
+    }
+
+    /// Starts administering `exam` to the user in the given channel.
+    ///
+    /// Returns false if the user is already taking an exam.
+    pub async fn start(
+        &self,
+        ctx: Context,
+        api: Api,
+        user_id: UserId,
+        channel_id: ChannelId,
+        exam: Exam,
+        kind: ExamKind,
+    ) -> bool {
+        let seed = rand::random::<u64>();
+        let examiner = Examiner::make(&exam, &kind.options(), Box::new(SystemClock::new()), seed);
+        let checkpoint = Checkpoint {
+            channel_id,
+            kind,
+            exam,
+            examiner,
+        };
+        self.spawn(ctx, api, user_id, checkpoint, false).await
+    }
+
+    /// Picks up the exams which were running when the bot last stopped.
+    ///
+    /// Exams which were interrupted for too long are voided. Voided exams still count towards the
+    /// cooldown, so restarting the bot can't be used to retake an exam.
+    pub async fn resume_checkpoints(&self, ctx: Context, api: Api) {
+        let checkpoints: Vec<(UserId, SavedCheckpoint)> = match api.exam_checkpoints().await {
+            Ok(checkpoints) => checkpoints,
+            Err(e) => {
+                println!("Could not load exam checkpoints: {}", e);
+                return;
+            },
+        };
+
+        for (user_id, saved) in checkpoints.into_iter() {
+            if now_millis().saturating_sub(saved.saved_at) > RESUME_WINDOW_MILLIS {
+                end_exam(&api, user_id, &saved.exam.name, saved.kind).await;
+                let message = format!(
+                    "<@{}>, your {} exam was interrupted for too long and has been voided.",
+                    user_id,
+                    saved.exam.name,
+                );
+                saved.channel_id.say(&ctx, message).await.unwrap();
+                continue;
+            }
+
+            let mut checkpoint = Checkpoint {
+                channel_id: saved.channel_id,
+                kind: saved.kind,
+                exam: saved.exam.restore(&saved.examiner),
+                examiner: saved.examiner,
+            };
+            checkpoint.examiner.resume(Box::new(SystemClock::new()));
+            self.spawn(ctx.clone(), api.clone(), user_id, checkpoint, true).await;
+        }
+    }
+
+    async fn spawn(&self, ctx: Context, api: Api, user_id: UserId, checkpoint: Checkpoint, resumed: bool) -> bool {
+        let mut sessions = self.sessions.lock().await;
+        if sessions.contains_key(&user_id) {
+            return false;
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let guard = SessionGuard {
+            sessions: self.sessions.clone(),
+            user_id,
+            answers: tx.clone(),
+        };
+        sessions.insert(user_id, Session {
+            channel_id: checkpoint.channel_id,
+            answers: tx,
+        });
+
+        tokio::spawn(async move {
+            // Dropped when the exam ends, even if a send in run_exam panics.
+            let _session = guard;
+            run_exam(&ctx, &api, user_id, checkpoint, resumed, rx).await;
+        });
+
+        true
+    }
+}
+
+async fn run_exam(
+    ctx: &Context,
+    api: &Api,
+    user_id: UserId,
+    checkpoint: Checkpoint,
+    resumed: bool,
+    mut answers: mpsc::UnboundedReceiver<Message>,
+) {
+    let Checkpoint { channel_id, kind, exam, mut examiner } = checkpoint;
+    let exam = &exam;
+    let options = kind.options();
+    let fonts = super::fonts_from_context(ctx).await;
+    let dictionary = super::dictionary_from_context(ctx).await;
+
+    if resumed {
+        let message = format!("<@{}>, resuming your {} exam.", user_id, exam.name);
+        channel_id.say(ctx, message).await.unwrap();
+        if let Some(question) = examiner.open_question() {
+            chairmanmao::messages::ask_question(ctx, channel_id, &fonts, question, exam.prompt_style).await.unwrap();
+        }
+    } else {
+        chairmanmao::messages::exam_start(ctx, channel_id, exam).await.unwrap();
+    }
+
+    let score = loop {
+        let mut changed = false;
+        match examiner.tick() {
+            TickResult::Nothing => (),
+            TickResult::Pause => (),
+            TickResult::NextQuestion(question) => {
+                changed = true;
+                chairmanmao::messages::ask_question(ctx, channel_id, &fonts, &question, exam.prompt_style).await.unwrap();
+            },
+            TickResult::Timeout => {
+                changed = true;
+                channel_id.say(ctx, "*Time's up!*").await.unwrap();
+            },
+            TickResult::Finished(score) => break score,
+        }
+
+        tokio::time::sleep(Duration::from_millis(MILLIS_PER_TICK)).await;
+
+        while let Ok(msg) = answers.try_recv() {
+            if msg.content.trim() == "!quit" {
+                changed = true;
+                examiner.give_up();
+            } else if let Some((question, answer)) = examiner.answer(msg.content.trim()) {
+                changed = true;
+                if answer.is_correct() {
+                    msg.react(ctx, '✅').await.unwrap();
+                } else {
+                    msg.react(ctx, if answer.is_wrong_tone() { '🟨' } else { '❌' }).await.unwrap();
+                    if options.practice {
+                        let reveal = format!("{} → {}", question.question, question.valid_answers.join(", "));
+                        chairmanmao::messages::reveal_answer(ctx, channel_id, &fonts, &dictionary, &question, reveal).await.unwrap();
+                    }
+                }
+            }
+        }
+
+        if changed {
+            save_checkpoint(api, user_id, channel_id, exam, &examiner, kind).await;
+        }
+    };
+
+    end_exam(api, user_id, &exam.name, kind).await;
+
+    // The results are saved before they're posted, so they aren't lost if posting fails.
+    let record = ExamRecord::new(exam, options.practice, &score);
+    if let Err(e) = api.record_exam(user_id, &record).await {
+        println!("Could not record exam: {}", e);
+    }
+
+    for (question, answer) in score.graded_questions.iter() {
+        let grade = match chairmanmao::review::grade(answer) {
+            Some(grade) => grade,
+            None => continue,
+        };
+
+        // Reviews only drill pronunciation.
+        let record = question.kind == QuestionType::HanziToPinyin && match kind {
+            ExamKind::Review => true,
+            ExamKind::Exam | ExamKind::Practice => !matches!(answer, Answer::Correct(_)),
+        };
+
+        if record {
+            if let Err(e) = api.review_card(user_id, question, grade).await {
+                println!("Could not record review: {}", e);
+            }
+        }
+    }
+
+    if let Err(e) = chairmanmao::messages::exam_results(ctx, channel_id, &score).await {
+        println!("Could not post exam results: {}", e);
+    }
+}
+
+async fn save_checkpoint(
+    api: &Api,
+    user_id: UserId,
+    channel_id: ChannelId,
+    exam: &Exam,
+    examiner: &Examiner,
+    kind: ExamKind,
+) {
+    let checkpoint = CheckpointRef {
+        channel_id,
+        kind,
+        saved_at: now_millis(),
+        exam: SavedExam::new(exam),
+        examiner,
+    };
+    if let Err(e) = api.save_exam_checkpoint(user_id, &checkpoint).await {
+        println!("Could not save exam checkpoint: {}", e);
+    }
+}
+
+/// Forgets the saved exam and, for real exams, starts the cooldown.
+async fn end_exam(api: &Api, user_id: UserId, exam_name: &str, kind: ExamKind) {
+    if let Err(e) = api.clear_exam_checkpoint(user_id).await {
+        println!("Could not clear exam checkpoint: {}", e);
+    }
+
+    if kind == ExamKind::Exam {
+        if let Err(e) = api.start_exam_cooldown(user_id, exam_name, EXAM_COOLDOWN_SECS).await {
+            println!("Could not start exam cooldown: {}", e);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+use tokio::sync::mpsc;
+
+use chairmanmao::api::{Api, SYSTEM_USER_ID};
+use chairmanmao::clock::SystemClock;
+use chairmanmao::exams::Exam;
+use chairmanmao::race::{Race, RaceAnswer, RaceTick};
+
+/// How often the race is polled. Timing itself comes from the race's clock.
+const MILLIS_PER_TICK: u64 = 100;
+
+/// Social credit for first, second and third place in a race run for credit.
+pub const RACE_CREDIT: [u32; 3] = [30, 20, 10];
+
+/// Races with fewer players than this never award credit.
+const MIN_PLAYERS_FOR_CREDIT: usize = 2;
+
+struct RaceSession {
+    answers: mpsc::UnboundedSender<Message>,
+}
+
+/// Takes a channel's session out of [ActiveRaces] when its race ends, however it ends, so answers
+/// stop going to it and another race can start.
+struct SessionGuard {
+    sessions: Arc<Mutex<HashMap<ChannelId, RaceSession>>>,
+    channel_id: ChannelId,
+    answers: mpsc::UnboundedSender<Message>,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let sessions = self.sessions.clone();
+        let channel_id = self.channel_id;
+        let answers = self.answers.clone();
+        // Locking the sessions may have to wait, and drop can't, so removal gets a task of its own.
+        tokio::spawn(async move {
+            let mut sessions = sessions.lock().await;
+            // Leave alone any race started in the channel since.
+            if matches!(sessions.get(&channel_id), Some(session) if session.answers.same_channel(&answers)) {
+                sessions.remove(&channel_id);
+            }
+        });
+    }
+}
+
+/// The races currently running in Discord. Each channel may hold one race at a time.
+#[derive(Clone, Default)]
+pub struct ActiveRaces {
+    sessions: Arc<Mutex<HashMap<ChannelId, RaceSession>>>,
+}
+
+impl ActiveRaces {
+    /// Passes the message along to the race in its channel, if there is one.
+    ///
+    /// Commands other than `!stop` are left alone, so the rest of the bot keeps working during a race.
+    /// Returns true if the message was consumed as an answer.
+    pub async fn forward(&self, msg: &Message) -> bool {
+        let content = msg.content.trim();
+        if content.starts_with('!') && content != "!stop" {
+            return false;
+        }
+
+        let sessions = self.sessions.lock().await;
+        match sessions.get(&msg.channel_id) {
+            Some(session) => session.answers.send(msg.clone()).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Starts a race through `exam` in the given channel. Only the user who started it can `!stop` it.
+    ///
+    /// When `credit` is set, the podium is awarded [RACE_CREDIT].
+    /// Returns false if a race is already running in the channel.
+    pub async fn start(
+        &self,
+        ctx: Context,
+        api: Api,
+        started_by: UserId,
+        channel_id: ChannelId,
+        exam: Exam,
+        credit: bool,
+    ) -> bool {
+        let mut sessions = self.sessions.lock().await;
+        if sessions.contains_key(&channel_id) {
+            return false;
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let guard = SessionGuard {
+            sessions: self.sessions.clone(),
+            channel_id,
+            answers: tx.clone(),
+        };
+        sessions.insert(channel_id, RaceSession {
+            answers: tx,
+        });
+
+        tokio::spawn(async move {
+            // Dropped when the race ends, even if a send in run_race panics.
+            let _session = guard;
+            run_race(&ctx, &api, started_by, channel_id, &exam, credit, rx).await;
+        });
+
+        true
+    }
+}
+
+async fn run_race(
+    ctx: &Context,
+    api: &Api,
+    started_by: UserId,
+    channel_id: ChannelId,
+    exam: &Exam,
+    credit: bool,
+    mut answers: mpsc::UnboundedReceiver<Message>,
+) {
+    let prizes: &[u32] = if credit { &RACE_CREDIT } else { &[] };
+    chairmanmao::messages::race_start(ctx, channel_id, exam, prizes).await.unwrap();
+
+    let fonts = super::fonts_from_context(ctx).await;
+    let dictionary = super::dictionary_from_context(ctx).await;
+    let seed = rand::random::<u64>();
+    let mut race = Race::make(exam, Box::new(SystemClock::new()), seed);
+
+    let result = loop {
+        match race.tick() {
+            RaceTick::Nothing => (),
+            RaceTick::Pause => (),
+            RaceTick::NextQuestion(question) => {
+                chairmanmao::messages::ask_question(ctx, channel_id, &fonts, &question, exam.prompt_style).await.unwrap();
+            },
+            RaceTick::Timeout(question) => {
+                let reveal = format!("*Time's up!* {} → {}", question.question, question.valid_answers.join(", "));
+                chairmanmao::messages::reveal_answer(ctx, channel_id, &fonts, &dictionary, &question, reveal).await.unwrap();
+            },
+            RaceTick::Finished(result) => break result,
+        }
+
+        tokio::time::sleep(Duration::from_millis(MILLIS_PER_TICK)).await;
+
+        while let Ok(msg) = answers.try_recv() {
+            let content = msg.content.trim();
+            if content == "!stop" {
+                // Anyone can type !stop, but only the user who started the race may end it.
+                if msg.author.id == started_by {
+                    race.stop();
+                }
+                continue;
+            }
+
+            match race.answer(msg.author.id, content) {
+                Some(RaceAnswer::Won { points, streak }) => {
+                    msg.react(ctx, '✅').await.unwrap();
+                    let mut announcement = format!("<@{}> +{}", msg.author.id, points);
+                    if streak > 1 {
+                        announcement.push_str(&format!("　🔥 {} in a row", streak));
+                    }
+                    channel_id.say(ctx, announcement).await.unwrap();
+                },
+                Some(RaceAnswer::WrongTone) => {
+                    msg.react(ctx, '🟨').await.unwrap();
+                },
+                Some(RaceAnswer::Incorrect) | None => (),
+            }
+        }
+    };
+
+    chairmanmao::messages::race_results(ctx, channel_id, &result).await.unwrap();
+
+    // Stopped races don't pay out, or a race could be ended as soon as someone took the lead.
+    if !credit || result.stopped || result.standings.len() < MIN_PLAYERS_FOR_CREDIT {
+        return;
+    }
+
+    for standing in result.podium() {
+        let amount = RACE_CREDIT[standing.place - 1];
+        let reason = format!("Placed {} in a {} race", standing.place, exam.name);
+        if let Err(e) = api.honor(standing.player, SYSTEM_USER_ID, amount as i32, reason).await {
+            println!("Could not award race credit: {}", e);
+        }
+    }
+}
@@ -1,5 +1,19 @@
+use chairmanmao::draw::{FontRegistry, RenderOptions, FONT_DIR};
+
 fn main() {
     let args = std::env::args().collect::<Vec<_>>();
     let text: &str = args.get(1).map(|s| s.as_str()).unwrap_or("你好");
-    chairmanmao::draw::draw(text);
+    let filename: &str = args.get(2).map(|s| s.as_str()).unwrap_or("out.png");
+
+    let drawn = FontRegistry::load(FONT_DIR).and_then(|fonts| fonts.draw(text, &RenderOptions::default()));
+    let bytes = match drawn {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    };
+
+    std::fs::write(filename, bytes).unwrap();
+    println!("Generated: {}", filename);
 }
@@ -1,149 +1,391 @@
-use std::collections::HashMap;
-use serde::{Deserialize};
-use serde_json;
-use chairmanmao::exams::{Exam, Examiner, TickResult, Question, Answer};
+//! Takes an exam in the terminal.
+//!
+//! The whole exam runs full screen, with a countdown for each question. A table of results is
+//! printed once it's over.
 
+use std::io::{self, Write};
+use std::time::Duration;
 
-#[derive(Deserialize, Debug)]
-struct JsonExam {
-    name: String,
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor, Stylize};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
 
-    #[serde(rename = "numQuestions")]
-    num_questions: usize,
+use chairmanmao::clock::SystemClock;
+use chairmanmao::exams::{Answer, Exam, ExamOptions, ExamScore, Examiner, Question, TickResult};
 
-    #[serde(rename = "maxWrong")]
-    max_wrong: usize,
+const USAGE: &str = "\
+Usage: exam [DECK] [OPTIONS]
 
-    timelimit: usize,
+Takes the exam in DECK (hsk1 by default).
 
-    #[serde(rename = "hskLevel")]
-    hsk_level: usize,
+Options:
+    --seed N              Shuffle the questions with the given seed. Random by default.
+    --practice            Run through the whole deck under practice rules.
+    --tones-lenient       Count answers with the wrong tones as half a mistake.
+    --timelimit SECONDS   Override the time limit for each question.
+    --list                List the available decks.
+    --help                Show this message.";
 
-    deck: Vec<JsonCard>,
-}
+/// How often the screen is redrawn while waiting for keys.
+const MILLIS_PER_FRAME: u64 = 50;
 
-#[derive(Deserialize, Debug)]
-struct JsonCard {
-    question: String,
+/// The width of the countdown bar, in columns.
+const BAR_WIDTH: usize = 30;
 
-    #[serde(rename = "validAnswers")]
-    valid_answers: Vec<String>,
-    meaning: String,
+#[derive(Debug)]
+struct Args {
+    deck: String,
+    seed: Option<u64>,
+    practice: bool,
+    tones_lenient: bool,
+    /// In seconds.
+    timelimit: Option<usize>,
+    list: bool,
+    help: bool,
 }
 
-fn convert_card(json_card: &JsonCard) -> Question {
-    let JsonCard {
-        question,
-        valid_answers,
-        meaning,
-    } = json_card;
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        deck: "hsk1".to_string(),
+        seed: None,
+        practice: false,
+        tones_lenient: false,
+        timelimit: None,
+        list: false,
+        help: false,
+    };
+    let mut deck = None;
 
-    Question {
-        question: question.clone(),
-        valid_answers: valid_answers.clone(),
-        meaning: meaning.clone(),
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let seed = args.next().ok_or("--seed needs a value")?;
+                parsed.seed = Some(seed.parse().map_err(|_| format!("Invalid seed: {}", seed))?);
+            },
+            "--timelimit" => {
+                let timelimit = args.next().ok_or("--timelimit needs a value")?;
+                let seconds: usize = timelimit.parse().map_err(|_| format!("Invalid time limit: {}", timelimit))?;
+                if seconds == 0 {
+                    return Err("The time limit must be at least one second".to_string());
+                }
+                parsed.timelimit = Some(seconds);
+            },
+            "--practice" => parsed.practice = true,
+            "--tones-lenient" => parsed.tones_lenient = true,
+            "--list" => parsed.list = true,
+            "--help" | "-h" => parsed.help = true,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ => {
+                if deck.is_some() {
+                    return Err(format!("Unexpected argument: {}", arg));
+                }
+                deck = Some(arg);
+            },
+        }
     }
+
+    if let Some(deck) = deck {
+        parsed.deck = deck;
+    }
+    Ok(parsed)
 }
 
-fn convert_exam(json_exam: &JsonExam) -> Exam {
-    let JsonExam {
-        name,
-        num_questions,
-        max_wrong,
-        timelimit,
-        hsk_level,
-        deck,
-    } = json_exam;
-
-    let deck = deck.iter().map(|json_card| convert_card(json_card)).collect::<Vec<_>>();
-    let max_wrong = Some(*max_wrong);
-    let timelimit = *timelimit * 1000; // convert from s to ms
-
-    Exam {
-        name: name.to_owned(),
-        deck,
-        num_questions: *num_questions,
-        max_wrong,
-        timelimit,
-        hsk_level: *hsk_level,
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    if args.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let exams = chairmanmao::decks::load_exams();
+    if args.list {
+        for exam in exams.iter() {
+            println!("{:10} {} questions, {} seconds each", exam.name, exam.num_questions, exam.timelimit / 1000);
+        }
+        return;
+    }
+
+    let exam = match exams.into_iter().find(|exam| exam.name == args.deck) {
+        Some(exam) => exam,
+        None => {
+            eprintln!("No such exam: {}. Use --list to see the available decks.", args.deck);
+            std::process::exit(1);
+        },
+    };
+
+    let options = ExamOptions {
+        practice: args.practice,
+        tones_lenient: args.tones_lenient,
+        timelimit: args.timelimit.map(|seconds| seconds * 1000),
+    };
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut examiner = Examiner::make(&exam, &options, Box::new(SystemClock::new()), seed);
+
+    let score = {
+        let mut screen = match Screen::enter() {
+            Ok(screen) => screen,
+            Err(e) => {
+                eprintln!("Could not start the terminal UI: {}", e);
+                std::process::exit(1);
+            },
+        };
+        run(&mut screen, &exam, &mut examiner)
+    };
+
+    match score {
+        Ok(score) => print_results(&exam, &score, seed),
+        Err(e) => {
+            eprintln!("Terminal error: {}", e);
+            std::process::exit(1);
+        },
     }
 }
 
+/// The terminal in full screen, raw mode. It's restored when dropped, even on a panic.
+struct Screen {
+    stdout: io::Stdout,
+}
 
-fn load_exam(exam_name: &str) -> Exam {
-    let mut exams: HashMap<String, Exam> = HashMap::new();
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide)?;
+        Ok(Screen { stdout })
+    }
+}
 
-    let exam_json = std::fs::read_to_string("data/exams.json").unwrap();
-    let exam_data: serde_json::Value = serde_json::from_str(&exam_json).unwrap();
-    for exam in exam_data["data"]["exams"].as_array().unwrap().iter() {
-        let json_exam: JsonExam = JsonExam::deserialize(exam).unwrap();
-        let exam_name = json_exam.name.clone();
-        println!("Exam found: {}", json_exam.name);
-        let exam = convert_exam(&json_exam);
-        exams.insert(exam_name, exam);
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
     }
+}
 
-    exams[exam_name].clone()
+/// The last answer, shown under the input until the next one.
+struct Feedback {
+    color: Color,
+    message: String,
 }
 
-fn main() {
-    let exam = load_exam("hsk1");
+fn run(screen: &mut Screen, exam: &Exam, examiner: &mut Examiner) -> io::Result<ExamScore> {
+    let mut input = String::new();
+    let mut question: Option<Question> = None;
+    let mut feedback: Option<Feedback> = None;
 
-    let (tx, rx) = std::sync::mpsc::channel::<String>();
+    loop {
+        match examiner.tick() {
+            TickResult::Nothing | TickResult::Pause => (),
+            TickResult::NextQuestion(next) => {
+                question = Some(next);
+                input.clear();
+            },
+            TickResult::Timeout => {
+                if let Some(question) = &question {
+                    feedback = Some(Feedback {
+                        color: Color::Red,
+                        message: format!("Time's up! {} → {}", question.question, question.valid_answers.join(", ")),
+                    });
+                }
+            },
+            TickResult::Finished(score) => return Ok(score),
+        }
 
-    let seed = 10;
-    let mut examiner = Examiner::make(&exam, 100, seed);
+        draw(screen, exam, examiner, question.as_ref(), &input, feedback.as_ref())?;
+
+        if !event::poll(Duration::from_millis(MILLIS_PER_FRAME))? {
+            continue;
+        }
 
-    let examiner_thread = std::thread::spawn(move || {
-        loop {
-            let tick_result = examiner.tick();
-            match tick_result {
-                TickResult::Nothing => (),
-                TickResult::NextQuestion(q) => {
-                    println!("Question: {}", q.question);
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Esc => examiner.give_up(),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => examiner.give_up(),
+                KeyCode::Enter => {
+                    if let Some((question, answer)) = examiner.answer(input.trim()) {
+                        feedback = Some(feedback_for(&question, &answer));
+                    }
+                    input.clear();
                 },
-                TickResult::Pause => (),
-                TickResult::Timeout => {
-                    println!("*timeout*");
-                    println!();
+                KeyCode::Backspace => {
+                    input.pop();
                 },
-                TickResult::Finished(score) => {
-                    return score;
-                }
-            };
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            if let Ok(answer) = rx.try_recv() {
-                if answer == "!quit" {
-                    examiner.give_up();
-                } else if let Some((question, answer)) = examiner.answer(&answer) {
-                    if let Answer::Correct(_) = answer {
-                        println!("CORRECT");
-                    } else {
-                        println!("INCORRECT: {}", &question.valid_answers[0]);
-                    }
-                    println!();
-                }
-                // } else { not ready to receive an answer }
+                KeyCode::Char(ch) => input.push(ch),
+                _ => (),
             }
         }
-    });
-
-    let _read_thread = std::thread::spawn(move || {
-        use std::io::prelude::*;
-        let stdin = std::io::stdin();
-        for line in stdin.lock().lines() {
-            let line = line.unwrap();
-            tx.send(line).unwrap();
+    }
+}
+
+fn feedback_for(question: &Question, answer: &Answer) -> Feedback {
+    let correct_answer = question.valid_answers.join(", ");
+    match answer {
+        Answer::Correct(_) => Feedback {
+            color: Color::Green,
+            message: "Correct!".to_string(),
+        },
+        Answer::WrongTone(_) => Feedback {
+            color: Color::Yellow,
+            message: format!("Wrong tone: {} → {}", question.question, correct_answer),
+        },
+        _ => Feedback {
+            color: Color::Red,
+            message: format!("Incorrect: {} → {}", question.question, correct_answer),
+        },
+    }
+}
+
+fn draw(
+    screen: &mut Screen,
+    exam: &Exam,
+    examiner: &Examiner,
+    question: Option<&Question>,
+    input: &str,
+    feedback: Option<&Feedback>,
+) -> io::Result<()> {
+    let stdout = &mut screen.stdout;
+    let mut row = 1;
+    queue!(stdout, Clear(ClearType::All))?;
+
+    let question_number = (examiner.num_answered() + 1).min(examiner.num_questions());
+    let mut status = format!("{}  ·  Question {}/{}", exam.name, question_number, examiner.num_questions());
+    if examiner.is_practice() {
+        status.push_str("  ·  Practice");
+    } else if let Some(max_wrong) = examiner.max_wrong() {
+        status.push_str(&format!("  ·  Mistakes {}/{}", examiner.mistakes(), max_wrong));
+    }
+    queue!(stdout, MoveTo(2, row), Print(status.dark_grey()))?;
+    row += 2;
+
+    if let Some(question) = question {
+        for line in question.question.lines() {
+            queue!(stdout, MoveTo(4, row), Print(line.bold()))?;
+            row += 1;
         }
-    });
+    }
+    row += 1;
+
+    if examiner.open_question().is_some() {
+        let time_left = examiner.time_left();
+        let fraction = time_left as f64 / examiner.timelimit().max(1) as f64;
+        let filled = (fraction * BAR_WIDTH as f64).ceil() as usize;
+        let color = if fraction > 0.5 {
+            Color::Green
+        } else if fraction > 0.2 {
+            Color::Yellow
+        } else {
+            Color::Red
+        };
+        let bar = format!("{}{}", "█".repeat(filled), "░".repeat(BAR_WIDTH - filled));
+        let seconds = format!(" {:.1}s", time_left as f64 / 1000.0);
+        queue!(
+            stdout,
+            MoveTo(2, row),
+            SetForegroundColor(color),
+            Print(bar),
+            Print(seconds),
+            ResetColor,
+        )?;
+    }
+    row += 2;
+
+    queue!(stdout, MoveTo(2, row), Print("> ".bold()), Print(input), Print("_".dark_grey()))?;
+    row += 2;
 
-    let score = examiner_thread.join().unwrap();
+    if let Some(feedback) = feedback {
+        queue!(stdout, MoveTo(2, row), SetForegroundColor(feedback.color), Print(&feedback.message), ResetColor)?;
+    }
+    row += 2;
+
+    queue!(stdout, MoveTo(2, row), Print("Enter to answer  ·  Esc to give up".dark_grey()))?;
+    stdout.flush()
+}
+
+fn print_results(exam: &Exam, score: &ExamScore, seed: u64) {
+    let result = &score.result;
+    let outcome = if result.passed {
+        "PASSED".green()
+    } else if result.gave_up {
+        "GAVE UP".yellow()
+    } else if result.timed_out {
+        "TIMED OUT".yellow()
+    } else if result.failed_too_many_wrong {
+        "TOO MANY WRONG".red()
+    } else {
+        "FINISHED".white()
+    };
+
+    println!();
+    println!("{}  {:.1}%  {}", exam.name.as_str().bold(), result.percent_correct, outcome.bold());
+    println!(
+        "{} of {} correct, {} mistakes, seed {}",
+        result.num_correct,
+        result.num_questions,
+        result.mistakes,
+        seed,
+    );
+    println!();
+
+    let headers = ["", "Question", "Your answer", "Correct answer", "Time", "Meaning"];
+    let mut rows: Vec<(Color, [String; 6])> = Vec::new();
+    for (idx, (question, answer)) in score.graded_questions.iter().enumerate() {
+        let (color, mark, given) = match answer {
+            Answer::Correct(given) => (Color::Green, "✓", given.clone()),
+            Answer::WrongTone(given) => (Color::Yellow, "~", given.clone()),
+            Answer::Incorrect(given) => (Color::Red, "✗", given.clone()),
+            Answer::Timeout => (Color::Red, "✗", "(timeout)".to_string()),
+            Answer::Quit => (Color::DarkGrey, "-", "(gave up)".to_string()),
+        };
+        let millis = result.question_millis.get(idx).copied().unwrap_or(0);
+        rows.push((color, [
+            mark.to_string(),
+            question.question.replace('\n', " "),
+            given,
+            question.valid_answers.join(", "),
+            format!("{:.1}s", millis as f64 / 1000.0),
+            question.meaning.clone(),
+        ]));
+    }
 
-    println!("Exam Complete");
-    println!("Score: {:.1}%  {}", score.score*100.0, if score.passed { "PASSED" } else { "FAILED" });
-    println!("Questions:");
-    for (question, answer) in &score.graded_questions {
-        println!("    {:10}    {:?}", question.question, answer);
+    let mut widths: Vec<usize> = headers.iter().map(|header| display_width(header)).collect();
+    for (_color, cells) in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(cells.iter()) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+
+    let header: Vec<String> = headers.iter().zip(widths.iter()).map(|(header, width)| pad(header, *width)).collect();
+    println!("{}", header.join("  ").bold());
+    for (color, cells) in rows.iter() {
+        let line: Vec<String> = cells.iter().zip(widths.iter()).map(|(cell, width)| pad(cell, *width)).collect();
+        println!("{}", line.join("  ").with(*color));
     }
+}
 
+/// The number of terminal columns the text takes up. Hanzi and other full width characters take two.
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| {
+            let wide = chairmanmao::hanzi::is_hanzi(ch)
+                || ('\u{3000}'..='\u{30FF}').contains(&ch)
+                || ('\u{FF00}'..='\u{FF60}').contains(&ch);
+            if wide { 2 } else { 1 }
+        })
+        .sum()
 }
+
+fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
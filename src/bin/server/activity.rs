@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use redis::AsyncCommands;
+use redis::streams::{StreamId, StreamReadOptions, StreamReadReply};
+use ulid::Ulid;
+
+use crate::schema::{self, Context};
+use crate::store::MessageLogged;
+use crate::events;
+
+const STREAM: &str = "messages";
+const GROUP: &str = "server";
+const CONSUMER: &str = "server";
+
+/// How long to wait before going back for entries which couldn't be applied.
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How many times an entry is tried before it is given up on, so one bad entry can't hold up the
+/// rest of the stream forever.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Tails the `messages` stream written by the bot and folds each entry into the [crate::store::Store].
+///
+/// The server reads through a consumer group, so entries are only acknowledged once they have been
+/// applied. On startup, any entries left pending by a previous run are replayed first. When an
+/// entry can't be applied, it and the entries after it stay pending and are retried after
+/// [RETRY_DELAY], up to [MAX_ATTEMPTS] times. Applying an entry again doesn't count it twice.
+pub async fn tail_messages(context: Arc<Context>) {
+    // Reads block for up to five seconds, so they get a connection of their own rather than hold up
+    // the shared one.
+    let host = std::env::var("REDIS_HOST").unwrap().to_string();
+    let client = redis::Client::open(host).unwrap();
+    let mut redis = client.get_async_connection().await.unwrap();
+
+    let created: redis::RedisResult<()> = redis.xgroup_create_mkstream(STREAM, GROUP, "0").await;
+    if let Err(e) = created {
+        // BUSYGROUP means the group already exists, which is what we want.
+        if e.code() != Some("BUSYGROUP") {
+            panic!("Could not create consumer group: {}", e);
+        }
+    }
+
+    let mut last_id = "0".to_string();
+    let mut attempts: HashMap<String, u32> = HashMap::new();
+
+    loop {
+        let options = StreamReadOptions::default()
+            .group(GROUP, CONSUMER)
+            .block(5000)
+            .count(100);
+
+        let reply: StreamReadReply = match redis.xread_options(&[STREAM], &[&last_id], &options).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                eprintln!("Error reading {}: {}", STREAM, e);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            },
+        };
+
+        let entries: Vec<StreamId> = reply.keys.into_iter().flat_map(|key| key.ids).collect();
+
+        // Once the pending entries are drained, switch over to new entries.
+        if entries.is_empty() && last_id != ">" {
+            last_id = ">".to_string();
+            continue;
+        }
+
+        let mut failed = false;
+        for entry in entries.iter() {
+            if let Err(e) = apply_entry(&context, entry).await {
+                let attempt = attempts.entry(entry.id.clone()).or_insert(0);
+                *attempt += 1;
+                if *attempt < MAX_ATTEMPTS {
+                    eprintln!("Error logging message {} (attempt {}): {}", &entry.id, attempt, e);
+                    // The entries after this one wait for it, so messages are logged in order.
+                    failed = true;
+                    break;
+                }
+                eprintln!("Giving up on message {} after {} attempts: {}", &entry.id, attempt, e);
+            }
+            attempts.remove(&entry.id);
+
+            let acked: redis::RedisResult<usize> = redis.xack(STREAM, GROUP, &[&entry.id]).await;
+            if let Err(e) = acked {
+                eprintln!("Error acknowledging {}: {}", &entry.id, e);
+            }
+        }
+
+        // Entries which failed are still pending, so go back to reading those.
+        if failed {
+            tokio::time::sleep(RETRY_DELAY).await;
+            last_id = "0".to_string();
+        }
+    }
+}
+
+async fn apply_entry(context: &Context, entry: &StreamId) -> Result<(), String> {
+    let message = match parse_message(entry) {
+        Some(message) => message,
+        None => {
+            eprintln!("Malformed entry in {}: {}", STREAM, &entry.id);
+            return Ok(());
+        },
+    };
+    let position = sortable_position(&entry.id).ok_or(format!("Malformed entry ID: {}", &entry.id))?;
+
+    context.store.log_message(&position, &message).await.map_err(|e| e.to_string())?;
+    record_hanzi(context, &message).await
+}
+
+/// A stream entry ID, padded so that comparing two as strings puts them in stream order.
+fn sortable_position(entry_id: &str) -> Option<String> {
+    let (millis, sequence) = entry_id.split_once('-')?;
+    Some(format!("{:020}-{:020}", millis.parse::<u64>().ok()?, sequence.parse::<u64>().ok()?))
+}
+
+/// Emits a [events::types::HanziUsed] event for any hanzi the author has never used before.
+async fn record_hanzi(context: &Context, message: &MessageLogged) -> Result<(), String> {
+    if message.hanzi.is_empty() {
+        return Ok(());
+    }
+
+    let new_hanzi: Vec<String> = match context.store.load_profile(message.user_id).await {
+        Some(profile) => message.hanzi
+            .iter()
+            .filter(|hanzi| !profile.hanzi.contains(hanzi))
+            .cloned()
+            .collect(),
+        None => return Ok(()),
+    };
+
+    if new_hanzi.is_empty() {
+        return Ok(());
+    }
+
+    let event = events::types::HanziUsed {
+        id: Ulid::new(),
+        user_id: message.user_id,
+        hanzi: new_hanzi,
+    };
+    let command = schema::process_event(context, event, None).await.map_err(|e| e.message().to_string())?;
+    match command.error() {
+        Some(e) => Err(format!("Could not record hanzi: {}", e)),
+        None => Ok(()),
+    }
+}
+
+fn parse_message(entry: &StreamId) -> Option<MessageLogged> {
+    let field = |name: &str| entry.get::<String>(name);
+
+    Some(MessageLogged {
+        user_id: field("user_id")?.parse().ok()?,
+        channel_id: field("channel_id")?.parse().ok()?,
+        timestamp: field("timestamp")?.parse().ok()?,
+        length: field("length")?.parse().ok()?,
+        cjk_count: field("cjk_count")?.parse().ok()?,
+        hanzi: field("hanzi").unwrap_or_default().chars().map(String::from).collect(),
+    })
+}
@@ -1,9 +1,16 @@
+use std::collections::HashMap;
 use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use redis::streams::{StreamId, StreamRangeReply};
 use ulid::Ulid;
+use chairmanmao::credit::StreamEntry;
+use chairmanmao::journal::{EventLog, LoggedEvent, Projection};
 
 use crate::store::Store;
 use async_trait::async_trait;
-use serde::{Serialize};
+
+/// The Redis stream events are logged to. The store's checkpoint goes by the same name.
+const EVENTS_STREAM: &str = "events";
 
 #[async_trait]
 pub trait Event {
@@ -13,13 +20,15 @@ pub trait Event {
 
     async fn validate(&self, store: &Store) -> Result<(), String>;
 
-    async fn exec(&self, store: &mut Store) -> Result<(), String>;
+    async fn exec(&self, store: &Store) -> Result<(), String>;
 
     fn to_map(&self) -> Vec<(String, String)>;
 }
 
+/// The `events` stream. Clones share one multiplexed connection, so they can be used at once.
+#[derive(Clone)]
 pub struct EventStream {
-    redis: redis::aio::Connection,
+    redis: ConnectionManager,
 }
 
 impl EventStream {
@@ -27,20 +36,112 @@ impl EventStream {
         let host = std::env::var("REDIS_HOST").unwrap().to_string();
 
         let client = redis::Client::open(host.clone()).unwrap();
-        let redis = client.get_async_connection().await.unwrap();
+        let redis = ConnectionManager::new(client).await.unwrap();
 
         EventStream {
             redis,
         }
     }
 
-    pub async fn append<E: Event + Serialize>(&mut self, event: &E) {
-        let map = vec![
-            ("id".to_string(), event.id().to_string()),
-            ("type".to_string(), event.type_name().to_string()),
-            ("event".to_string(), serde_json::to_string(event).unwrap()),
+    /// Every entry in the stream, oldest first, in the shape [chairmanmao::credit] reads.
+    pub async fn read_all(&self) -> redis::RedisResult<Vec<StreamEntry>> {
+        let reply: StreamRangeReply = self.redis.clone().xrange_all(EVENTS_STREAM).await?;
+        let mut entries = Vec::new();
+        for entry in reply.ids.into_iter() {
+            let mut fields = HashMap::new();
+            for (name, value) in entry.map.iter() {
+                fields.insert(name.clone(), redis::from_redis_value(value)?);
+            }
+            entries.push(StreamEntry {
+                id: entry.id,
+                fields,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+#[async_trait]
+impl EventLog for EventStream {
+    async fn append(&self, event: &LoggedEvent) -> Result<String, String> {
+        let mut map = vec![
+            ("id".to_string(), event.id.to_string()),
+            ("type".to_string(), event.event_type.clone()),
+            ("event".to_string(), event.event.clone()),
         ];
-        let () = self.redis.xadd("events", "*", &map).await.unwrap();
+        if let Some(key) = &event.idempotency_key {
+            map.push(("idempotency_key".to_string(), key.clone()));
+        }
+        self.redis.clone().xadd(EVENTS_STREAM, "*", &map).await.map_err(|e| e.to_string())
+    }
+
+    async fn read_after(&self, position: Option<&str>, count: usize) -> Result<Vec<(String, Option<LoggedEvent>)>, String> {
+        // XRANGE includes its start, so read one more and drop the entry at `position`.
+        let start = position.unwrap_or("-");
+        let reply: StreamRangeReply = self.redis
+            .clone()
+            .xrange_count(EVENTS_STREAM, start, "+", count + 1)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(reply.ids
+            .iter()
+            .filter(|entry| Some(entry.id.as_str()) != position)
+            .take(count)
+            .map(|entry| (entry.id.clone(), logged_event(entry)))
+            .collect())
+    }
+
+    async fn last_position(&self) -> Result<Option<String>, String> {
+        let reply: StreamRangeReply = self.redis
+            .clone()
+            .xrevrange_count(EVENTS_STREAM, "+", "-", 1)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(reply.ids.into_iter().next().map(|entry| entry.id))
+    }
+}
+
+/// Reads an event the server logged. Entries the bot wrote, with each field on its own, were never
+/// applied to the store, so these come back as `None`.
+fn logged_event(entry: &StreamId) -> Option<LoggedEvent> {
+    let id: String = entry.get("id")?;
+    Some(LoggedEvent {
+        id: Ulid::from_string(&id).ok()?,
+        event_type: entry.get("type")?,
+        event: entry.get("event")?,
+        idempotency_key: entry.get("idempotency_key"),
+    })
+}
+
+#[async_trait]
+impl Projection for Store {
+    async fn apply(&self, event: &LoggedEvent) -> Result<bool, String> {
+        if self.is_processed_event(event.id).await.map_err(|e| e.to_string())? {
+            return Ok(false);
+        }
+        if let Some(key) = &event.idempotency_key {
+            if self.load_processed_event(key).await.is_some() {
+                return Ok(false);
+            }
+        }
+
+        let parsed = types::parse(&event.event_type, &event.event)?;
+        parsed.exec(self).await?;
+
+        // If the server dies here, the event is applied again on restart. See [chairmanmao::journal].
+        // Credit changes are the exception, as [Store::add_credit] notes its event as it goes.
+        self.record_processed_event(event.id, &event.event_type, event.idempotency_key.as_deref())
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    async fn checkpoint(&self) -> Result<Option<String>, String> {
+        self.load_checkpoint(EVENTS_STREAM).await.map_err(|e| e.to_string())
+    }
+
+    async fn save_checkpoint(&self, position: &str) -> Result<(), String> {
+        self.store_checkpoint(EVENTS_STREAM, position).await.map_err(|e| e.to_string())
     }
 }
 
@@ -50,6 +151,41 @@ pub mod types {
     use crate::store::Store;
     use ulid::Ulid;
     use serde::{Serialize, Deserialize};
+    use chairmanmao::review::ReviewCard;
+    use chairmanmao::decks::Deck;
+    use chairmanmao::questions::Card;
+    use chairmanmao::api::SYSTEM_USER_ID;
+    use chairmanmao::stats::ExamRecord;
+
+    /// Reads an event back from the JSON it was logged as.
+    pub fn parse(event_type: &str, event: &str) -> Result<Box<dyn Event + Send + Sync>, String> {
+        fn boxed<'a, E: Event + Deserialize<'a> + Send + Sync + 'static>(event: &'a str) -> Result<Box<dyn Event + Send + Sync>, String> {
+            match serde_json::from_str::<E>(event) {
+                Ok(event) => Ok(Box::new(event)),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+
+        match event_type {
+            "ProfileRegistered" => boxed::<ProfileRegistered>(event),
+            "SetParty" => boxed::<SetParty>(event),
+            "ComradeHonored" => boxed::<ComradeHonored>(event),
+            "ComradeDishonored" => boxed::<ComradeDishonored>(event),
+            "ComradeJailed" => boxed::<ComradeJailed>(event),
+            "ComradeUnjailed" => boxed::<ComradeUnjailed>(event),
+            "SetHsk" => boxed::<SetHsk>(event),
+            "HanziUsed" => boxed::<HanziUsed>(event),
+            "WordMined" => boxed::<WordMined>(event),
+            "WordUnmined" => boxed::<WordUnmined>(event),
+            "CardReviewed" => boxed::<CardReviewed>(event),
+            "ExamRecorded" => boxed::<ExamRecorded>(event),
+            "DeckCreated" => boxed::<DeckCreated>(event),
+            "DeckEdited" => boxed::<DeckEdited>(event),
+            "CardsImported" => boxed::<CardsImported>(event),
+            "DeckDeleted" => boxed::<DeckDeleted>(event),
+            _ => Err(format!("Unknown event type: {}", event_type)),
+        }
+    }
 
     #[derive(Serialize, Deserialize)]
     pub struct ProfileRegistered {
@@ -76,8 +212,9 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            store.register(self.user_id, self.discord_username.clone()).await.unwrap();
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            // If a racing registration got in first, there's nothing left to do.
+            store.register(self.user_id, self.discord_username.clone()).await.map_err(|e| e.to_string())?;
             Ok(())
         }
 
@@ -116,15 +253,14 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            let mut profile = store.load_profile(self.user_id).await.unwrap();
-            if self.flag {
-                profile.add_role("Party");
-            } else {
-                profile.remove_role("Party");
-            }
-            store.store_profile(&profile).await;
-            Ok(())
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.user_id, |profile| {
+                if self.flag {
+                    profile.add_role("Party");
+                } else {
+                    profile.remove_role("Party");
+                }
+            }).await
         }
 
         fn to_map(&self) -> Vec<(String, String)> {
@@ -166,9 +302,12 @@ pub mod types {
                 return Err(format!("Not user exists with that toUserId: {}", &self.by_user_id));
             }
 
-            let by_profile = store.load_profile(self.by_user_id).await;
-            if by_profile.is_none() {
-                return Err(format!("Not user exists with that byUserId: {}", &self.by_user_id));
+            // Credit from the system (for instance, for winning a race) has no profile behind it.
+            if self.by_user_id != SYSTEM_USER_ID.0 {
+                let by_profile = store.load_profile(self.by_user_id).await;
+                if by_profile.is_none() {
+                    return Err(format!("Not user exists with that byUserId: {}", &self.by_user_id));
+                }
             }
 
             if self.to_user_id == self.by_user_id {
@@ -177,11 +316,8 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            let mut to_profile = store.load_profile(self.to_user_id).await.unwrap();
-            to_profile.credit += self.amount as usize;
-            store.store_profile(&to_profile).await;
-            Ok(())
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.add_credit(self.to_user_id, self.id, self.amount as i64).await.map_err(|e| e.to_string())
         }
 
         fn to_map(&self) -> Vec<(String, String)> {
@@ -220,10 +356,11 @@ pub mod types {
                 return Err("Amount must be positive".to_string());
             }
 
-            let to_profile = store.load_profile(self.to_user_id).await;
-            if to_profile.is_none() {
-                return Err(format!("Not user exists with that toUserId: {}", &self.by_user_id));
-            }
+            let to_profile = match store.load_profile(self.to_user_id).await {
+                Some(to_profile) => to_profile,
+                None => return Err(format!("Not user exists with that toUserId: {}", &self.by_user_id)),
+            };
+            chairmanmao::credit::check_dishonor(to_profile.credit, self.amount)?;
 
             let by_profile = store.load_profile(self.by_user_id).await;
             if by_profile.is_none() {
@@ -236,11 +373,8 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            let mut to_profile = store.load_profile(self.to_user_id).await.unwrap();
-            to_profile.credit -= self.amount as usize;
-            store.store_profile(&to_profile).await;
-            Ok(())
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.add_credit(self.to_user_id, self.id, -(self.amount as i64)).await.map_err(|e| e.to_string())
         }
 
         fn to_map(&self) -> Vec<(String, String)> {
@@ -300,12 +434,10 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            let mut to_profile = store.load_profile(self.to_user_id).await.unwrap();
-            to_profile.roles.push("Jailed".to_string());
-            to_profile.roles.sort();
-            store.store_profile(&to_profile).await;
-            Ok(())
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.to_user_id, |profile| {
+                profile.add_role("Jailed");
+            }).await
         }
 
         fn to_map(&self) -> Vec<(String, String)> {
@@ -363,12 +495,10 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            let mut to_profile = store.load_profile(self.to_user_id).await.unwrap();
-            let index = to_profile.roles.iter().position(|x| x == &"Jailed").unwrap();
-            to_profile.roles.remove(index);
-            store.store_profile(&to_profile).await;
-            Ok(())
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.to_user_id, |profile| {
+                profile.remove_role("Jailed");
+            }).await
         }
 
         fn to_map(&self) -> Vec<(String, String)> {
@@ -411,11 +541,10 @@ pub mod types {
             Ok(())
         }
 
-        async fn exec(&self, store: &mut Store) -> Result<(), String> {
-            let mut profile = store.load_profile(self.user_id).await.unwrap();
-            profile.hsk = self.hsk;
-            store.store_profile(&profile).await;
-            Ok(())
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.user_id, |profile| {
+                profile.hsk = self.hsk;
+            }).await
         }
 
         fn to_map(&self) -> Vec<(String, String)> {
@@ -427,4 +556,462 @@ pub mod types {
             ]
         }
     }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HanziUsed {
+         pub id: Ulid,
+         pub user_id: u64,
+         pub hanzi: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Event for HanziUsed {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "HanziUsed"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            let profile = store.load_profile(self.user_id).await;
+            if profile.is_none() {
+                return Err(format!("Not user exists with that user id: {}", &self.user_id));
+            }
+
+            if self.hanzi.is_empty() {
+                return Err("No hanzi given".to_string());
+            }
+
+            for hanzi in self.hanzi.iter() {
+                let mut chars = hanzi.chars();
+                let is_single_hanzi = match (chars.next(), chars.next()) {
+                    (Some(ch), None) => chairmanmao::hanzi::is_hanzi(ch),
+                    _ => false,
+                };
+                if !is_single_hanzi {
+                    return Err(format!("Not a hanzi: {}", hanzi));
+                }
+            }
+
+            let profile = profile.unwrap();
+            for hanzi in self.hanzi.iter() {
+                if profile.hanzi.contains(hanzi) {
+                    return Err(format!("Hanzi already used: {}", hanzi));
+                }
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.user_id, |profile| {
+                for hanzi in self.hanzi.iter() {
+                    if !profile.hanzi.contains(hanzi) {
+                        profile.hanzi.push(hanzi.clone());
+                    }
+                }
+            }).await
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("hanzi".to_string(), self.hanzi.concat()),
+            ]
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct WordMined {
+         pub id: Ulid,
+         pub user_id: u64,
+         pub word: String,
+    }
+
+    #[async_trait]
+    impl Event for WordMined {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "WordMined"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            let profile = store.load_profile(self.user_id).await;
+            if profile.is_none() {
+                return Err(format!("Not user exists with that user id: {}", &self.user_id));
+            }
+
+            if self.word.is_empty() || !self.word.chars().all(chairmanmao::hanzi::is_hanzi) {
+                return Err(format!("Not a Chinese word: {}", &self.word));
+            }
+
+            if profile.unwrap().mined_words.contains(&self.word) {
+                return Err(format!("Word already mined: {}", &self.word));
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.user_id, |profile| {
+                if !profile.mined_words.contains(&self.word) {
+                    profile.mined_words.push(self.word.clone());
+                }
+            }).await
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("word".to_string(), self.word.to_string()),
+            ]
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct WordUnmined {
+         pub id: Ulid,
+         pub user_id: u64,
+         pub word: String,
+    }
+
+    #[async_trait]
+    impl Event for WordUnmined {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "WordUnmined"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            let profile = store.load_profile(self.user_id).await;
+            if profile.is_none() {
+                return Err(format!("Not user exists with that user id: {}", &self.user_id));
+            }
+
+            if !profile.unwrap().mined_words.contains(&self.word) {
+                return Err(format!("Word not mined: {}", &self.word));
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.update_profile(self.user_id, |profile| {
+                profile.mined_words.retain(|word| word != &self.word);
+            }).await
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("word".to_string(), self.word.to_string()),
+            ]
+        }
+    }
+
+    /// A user answered a card during a review, or missed it during an exam.
+    /// The card's spaced repetition schedule is updated as of the time of the event.
+    #[derive(Serialize, Deserialize)]
+    pub struct CardReviewed {
+         pub id: Ulid,
+         pub user_id: u64,
+         pub question: String,
+         pub valid_answers: Vec<String>,
+         pub meaning: String,
+         pub grade: u8,
+    }
+
+    #[async_trait]
+    impl Event for CardReviewed {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "CardReviewed"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            let profile = store.load_profile(self.user_id).await;
+            if profile.is_none() {
+                return Err(format!("Not user exists with that user id: {}", &self.user_id));
+            }
+
+            if self.valid_answers.is_empty() {
+                return Err("Card has no valid answers".to_string());
+            }
+
+            if self.grade > 5 {
+                return Err(format!("Invalid grade: {}", self.grade));
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            let now = self.id.datetime().timestamp_millis();
+            let question = chairmanmao::exams::Question {
+                question: self.question.clone(),
+                valid_answers: self.valid_answers.clone(),
+                meaning: self.meaning.clone(),
+                kind: chairmanmao::questions::QuestionType::HanziToPinyin,
+            };
+
+            store.update_review_card(
+                self.user_id,
+                &self.question,
+                || ReviewCard::new(&question, now),
+                |card| card.review(self.grade, now),
+            ).await
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("question".to_string(), self.question.to_string()),
+                ("grade".to_string(), self.grade.to_string()),
+            ]
+        }
+    }
+
+    /// A user finished an exam, practice run or review. Every graded answer is kept for analytics.
+    #[derive(Serialize, Deserialize)]
+    pub struct ExamRecorded {
+         pub id: Ulid,
+         pub user_id: u64,
+         pub record: ExamRecord,
+    }
+
+    #[async_trait]
+    impl Event for ExamRecorded {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "ExamRecorded"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            let profile = store.load_profile(self.user_id).await;
+            if profile.is_none() {
+                return Err(format!("Not user exists with that user id: {}", &self.user_id));
+            }
+
+            if self.record.exam_name.is_empty() {
+                return Err("Exam has no name".to_string());
+            }
+
+            if !self.record.practice && self.record.answers.len() > self.record.num_questions {
+                return Err("More answers than questions".to_string());
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            let taken_at = bson::DateTime::from_millis(self.id.datetime().timestamp_millis());
+            store.store_exam_record(self.user_id, taken_at, &self.record).await;
+            Ok(())
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("user_id".to_string(), self.user_id.to_string()),
+                ("exam_name".to_string(), self.record.exam_name.to_string()),
+                ("passed".to_string(), self.record.passed.to_string()),
+            ]
+        }
+    }
+
+    /// A deck was created through the API. Built-in decks live in a file and never appear here.
+    #[derive(Serialize, Deserialize)]
+    pub struct DeckCreated {
+         pub id: Ulid,
+         pub deck: Deck,
+    }
+
+    #[async_trait]
+    impl Event for DeckCreated {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "DeckCreated"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            self.deck.validate().map_err(|e| e.to_string())?;
+
+            if store.load_deck(&self.deck.name).await.is_some() {
+                return Err(format!("Deck already exists: {}", &self.deck.name));
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            // If a racing creation got in first, this one lost, and there's nothing left to do.
+            store.create_deck(&self.deck).await.map_err(|e| e.to_string())?;
+            Ok(())
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("name".to_string(), self.deck.name.to_string()),
+            ]
+        }
+    }
+
+    /// A deck was replaced with a new version. The name stays the same.
+    #[derive(Serialize, Deserialize)]
+    pub struct DeckEdited {
+         pub id: Ulid,
+         pub deck: Deck,
+         /// The version of the deck the edit was made to, if the editor gave one. The edit is
+         /// turned away if the deck has changed since.
+         #[serde(default, skip_serializing_if = "Option::is_none")]
+         pub version: Option<u64>,
+    }
+
+    #[async_trait]
+    impl Event for DeckEdited {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "DeckEdited"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            self.deck.validate().map_err(|e| e.to_string())?;
+
+            let stored = match store.load_stored_deck(&self.deck.name).await {
+                Some(stored) => stored,
+                None => return Err(format!("No deck exists with that name: {}", &self.deck.name)),
+            };
+            chairmanmao::versioned::check_version("Deck", self.version, stored.version)?;
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            // If the deck was deleted in the meantime, there's nothing left to edit.
+            store.update_deck(&self.deck.name, |deck| *deck = self.deck.clone()).await?;
+            Ok(())
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("name".to_string(), self.deck.name.to_string()),
+            ]
+        }
+    }
+
+    /// Cards were imported into a deck. They are merged into the deck as it is when the event is
+    /// applied, so changes made to the deck in the meantime are kept.
+    #[derive(Serialize, Deserialize)]
+    pub struct CardsImported {
+         pub id: Ulid,
+         pub name: String,
+         pub cards: Vec<Card>,
+         /// Drop the deck's existing cards first.
+         pub replace: bool,
+    }
+
+    #[async_trait]
+    impl Event for CardsImported {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "CardsImported"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            let mut deck = match store.load_deck(&self.name).await {
+                Some(deck) => deck,
+                None => return Err(format!("No deck exists with that name: {}", &self.name)),
+            };
+            deck.merge_cards(&self.cards, self.replace);
+            deck.validate().map_err(|e| e.to_string())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            // If the deck was deleted in the meantime, there's nothing left to import into.
+            store.update_deck(&self.name, |deck| deck.merge_cards(&self.cards, self.replace)).await?;
+            Ok(())
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("name".to_string(), self.name.to_string()),
+            ]
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DeckDeleted {
+         pub id: Ulid,
+         pub name: String,
+    }
+
+    #[async_trait]
+    impl Event for DeckDeleted {
+        fn id(&self) -> Ulid {
+            self.id
+        }
+
+        fn type_name(&self) -> &'static str {
+            "DeckDeleted"
+        }
+
+        async fn validate(&self, store: &Store) -> Result<(), String> {
+            if store.load_deck(&self.name).await.is_none() {
+                return Err(format!("No deck exists with that name: {}", &self.name));
+            }
+
+            Ok(())
+        }
+
+        async fn exec(&self, store: &Store) -> Result<(), String> {
+            store.delete_deck(&self.name).await;
+            Ok(())
+        }
+
+        fn to_map(&self) -> Vec<(String, String)> {
+            vec![
+                ("id".to_string(), self.id().to_string()),
+                ("type".to_string(), self.type_name().to_string()),
+                ("name".to_string(), self.name.to_string()),
+            ]
+        }
+    }
 }
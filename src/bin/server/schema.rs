@@ -4,17 +4,36 @@ use juniper::{EmptySubscription, RootNode};
 use ulid::Ulid;
 use serde::{Serialize};
 
-use juniper::GraphQLObject;
+use juniper::{GraphQLObject, GraphQLInputObject};
 
-use tokio::sync::RwLock;
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::store::Store;
 use crate::events::{self, EventStream, Event};
+use chairmanmao::exams::{Exam, PromptStyle};
+use chairmanmao::dictionary::Dictionary;
+use chairmanmao::decks::{self, Format};
+use chairmanmao::questions::QuestionType;
+use chairmanmao::stats::{self, ExamRecord, GradedAnswer, Outcome};
+use chairmanmao::credit;
+use chairmanmao::journal::{EarlierRequest, Journal, LoggedEvent, Recorded, Reply};
 
 
+/// How often the store catches up on events which couldn't be applied when they were recorded.
+const CATCH_UP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shared by every request. Nothing here is locked, so requests run concurrently.
 pub struct Context {
-    store: Store,
-    event_stream: EventStream,
+    pub store: Store,
+    pub event_stream: EventStream,
+    pub journal: Arc<Journal<EventStream, Store>>,
+
+    /// The decks from [decks::DECKS_PATH]. These can't be changed through the API.
+    pub builtin_decks: Vec<decks::Deck>,
+    /// The HSK decks, used to measure hanzi coverage.
+    pub hsk_decks: Vec<Exam>,
+    pub dictionary: Dictionary,
 }
 
 impl juniper::Context for Context {}
@@ -23,23 +42,60 @@ impl Context {
     pub async fn new() -> Context {
         let store = Store::new().await;
         let event_stream = EventStream::new().await;
+        let journal = Arc::new(Journal::new(event_stream.clone(), store.clone()));
+        let applied = journal.catch_up().await.unwrap();
+        println!("Applied {} events from the log", applied);
+        let builtin_decks = decks::load_decks().unwrap();
+        let mut hsk_decks: Vec<Exam> = builtin_decks.iter().map(|deck| deck.to_exam()).collect();
+        hsk_decks.sort_by_key(|exam| exam.hsk_level);
+        let dictionary = Dictionary::from_exams(&hsk_decks);
         Context {
             store,
             event_stream,
+            journal,
+            builtin_decks,
+            hsk_decks,
+            dictionary,
+        }
+    }
+}
+
+/// Applies events which were logged but couldn't be applied at the time, and keeps the store's
+/// checkpoint moving.
+pub async fn catch_up_events(context: Arc<Context>) {
+    loop {
+        tokio::time::sleep(CATCH_UP_INTERVAL).await;
+        match context.journal.catch_up().await {
+            Ok(0) => (),
+            Ok(applied) => println!("Applied {} events from the log", applied),
+            Err(e) => eprintln!("Could not catch up on the event log: {}", e),
         }
     }
 }
 
 pub struct QueryRoot;
 
-#[juniper::graphql_object(context = RwLock<Context>)]
+#[juniper::graphql_object(context = Context)]
 impl QueryRoot {
     async fn profile(
         user_id: String,
-        context: &RwLock<Context>,
+        context: &Context,
     ) -> FieldResult<Profile> {
-        let context = context.read().await;
-        let profile = context.store.load_profile(user_id.parse().unwrap()).await.unwrap();
+        let profile = context.store.load_profile(user_id.parse()?).await.ok_or("No such profile")?;
+        let activity = context.store.load_activity(profile.user_id).await;
+
+        let hsk_coverage = context.hsk_decks
+            .iter()
+            .map(|exam| {
+                let (known, total) = chairmanmao::hanzi::coverage(&profile.hanzi, &exam.cards);
+                HskCoverage {
+                    hsk_level: exam.hsk_level as i32,
+                    known: known as i32,
+                    total: total as i32,
+                    percent: if total > 0 { 100.0 * known as f64 / total as f64 } else { 0.0 },
+                }
+            })
+            .collect();
 
         Ok(Profile {
             user_id: profile.user_id.to_string(),
@@ -49,7 +105,383 @@ impl QueryRoot {
             credit: profile.credit as i32,
             yuan: profile.yuan as i32,
             created: profile.created.to_rfc3339_string(),
+            last_seen: profile.last_seen.to_rfc3339_string(),
             hsk: profile.hsk.map(|h| h.try_into().unwrap()),
+            hanzi_count: profile.hanzi.len() as i32,
+            hsk_coverage,
+            mined_words: profile.mined_words,
+            activity: activity.map(Activity::from).unwrap_or_default(),
+        })
+    }
+
+    /// The comrades with the most social credit, best first. Comrades with the same credit share a
+    /// rank.
+    async fn leaderboard(
+        limit: Option<i32>,
+        context: &Context,
+    ) -> FieldResult<Vec<Ranking>> {
+        let limit = i64::from(limit.unwrap_or(10));
+        let profiles = context.store.top_profiles(limit).await;
+
+        let mut rankings: Vec<Ranking> = Vec::new();
+        for (index, profile) in profiles.into_iter().enumerate() {
+            let rank = match rankings.last() {
+                Some(previous) if previous.credit == profile.credit as i32 => previous.rank,
+                _ => index as i32 + 1,
+            };
+            rankings.push(Ranking {
+                rank,
+                user_id: profile.user_id.to_string(),
+                display_name: profile.display_name,
+                credit: profile.credit as i32,
+                hsk: profile.hsk.map(|h| h.try_into().unwrap()),
+            });
+        }
+        Ok(rankings)
+    }
+
+    /// Every change to a comrade's social credit, oldest first, reconstructed from the event stream.
+    async fn credit_history(
+        user_id: String,
+        context: &Context,
+    ) -> FieldResult<Vec<CreditPoint>> {
+        let user_id: u64 = user_id.parse()?;
+        let profile = context.store.load_profile(user_id).await.ok_or("No such profile")?;
+        let entries = context.event_stream.read_all().await?;
+        let history = credit::credit_history(&entries, user_id, profile.credit);
+
+        Ok(history.into_iter().map(|point| CreditPoint {
+            at: point.at,
+            credit: point.credit as i32,
+            change: point.change as i32,
+            reason: point.reason,
+        }).collect())
+    }
+
+    /// The cards for the user's next `!review` session.
+    async fn review_deck(
+        user_id: String,
+        limit: Option<i32>,
+        context: &Context,
+    ) -> FieldResult<Vec<Card>> {
+        let user_id: u64 = user_id.parse()?;
+        let profile = context.store.load_profile(user_id).await.ok_or("No such profile")?;
+        let cards = context.store.load_review_cards(user_id).await;
+        let now = bson::DateTime::now().timestamp_millis();
+        let limit = limit.map(|limit| limit as usize).unwrap_or(chairmanmao::review::REVIEW_SIZE);
+
+        let deck = chairmanmao::review::select_review_deck(
+            &cards,
+            &profile.mined_words,
+            &context.dictionary,
+            now,
+            limit,
+        );
+
+        Ok(deck.into_iter().map(|question| Card {
+            question: question.question,
+            valid_answers: question.valid_answers,
+            meaning: question.meaning,
+        }).collect())
+    }
+
+    /// Every deck, built-in decks first.
+    async fn decks(context: &Context) -> FieldResult<Vec<Deck>> {
+        let mut decks: Vec<Deck> = context.builtin_decks.iter().map(|deck| Deck::new(deck, true, 0)).collect();
+        for stored in context.store.load_decks().await.iter() {
+            decks.push(Deck::new(&stored.deck, false, stored.version));
+        }
+        Ok(decks)
+    }
+
+    async fn deck(
+        name: String,
+        context: &Context,
+    ) -> FieldResult<Option<Deck>> {
+        if let Some(deck) = context.builtin_decks.iter().find(|deck| deck.name == name) {
+            return Ok(Some(Deck::new(deck, true, 0)));
+        }
+        Ok(context.store.load_stored_deck(&name).await.map(|stored| Deck::new(&stored.deck, false, stored.version)))
+    }
+
+    /// The cards of a deck as CSV, TSV or Anki-style text.
+    async fn export_deck(
+        name: String,
+        format: String,
+        context: &Context,
+    ) -> FieldResult<String> {
+        let format: Format = format.parse()?;
+        let deck = match context.builtin_decks.iter().find(|deck| deck.name == name) {
+            Some(deck) => deck.clone(),
+            None => context.store.load_deck(&name).await.ok_or("No such deck")?,
+        };
+        Ok(decks::export_cards(&deck.cards, format))
+    }
+
+    /// How often each card is missed across every user, hardest first.
+    /// Cards answered fewer than `min_attempts` times (default 5) are left out.
+    async fn card_stats(
+        deck: Option<String>,
+        min_attempts: Option<i32>,
+        limit: Option<i32>,
+        context: &Context,
+    ) -> FieldResult<Vec<CardStats>> {
+        let records = context.store.load_exam_records(None, deck.as_deref()).await;
+        let min_attempts = usize::try_from(min_attempts.unwrap_or(5))?;
+        let stats = stats::card_stats(&records, min_attempts);
+        Ok(take(stats, limit)?.iter().map(CardStats::from).collect())
+    }
+
+    /// The cards a user misses most often.
+    async fn most_missed(
+        user_id: String,
+        limit: Option<i32>,
+        context: &Context,
+    ) -> FieldResult<Vec<CardStats>> {
+        let records = context.store.load_exam_records(Some(user_id.parse()?), None).await;
+        let stats = stats::most_missed(&records);
+        Ok(take(stats, limit)?.iter().map(CardStats::from).collect())
+    }
+
+    /// How often each deck is passed, for each combination of `numQuestions`, `maxWrong` and
+    /// `timelimit` it has been taken with. Practice runs aren't counted.
+    async fn pass_rates(
+        deck: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Vec<PassRate>> {
+        let records = context.store.load_exam_records(None, deck.as_deref()).await;
+        Ok(stats::pass_rates(&records).iter().map(PassRate::from).collect())
+    }
+}
+
+fn take<T>(mut items: Vec<T>, limit: Option<i32>) -> FieldResult<Vec<T>> {
+    if let Some(limit) = limit {
+        items.truncate(usize::try_from(limit)?);
+    }
+    Ok(items)
+}
+
+#[derive(GraphQLObject)]
+pub struct Ranking {
+    pub rank: i32,
+    pub user_id: String,
+    pub display_name: String,
+    pub credit: i32,
+    pub hsk: Option<i32>,
+}
+
+/// A comrade's social credit just after it changed.
+#[derive(GraphQLObject)]
+pub struct CreditPoint {
+    pub at: String,
+    pub credit: i32,
+    /// Zero for registration.
+    pub change: i32,
+    pub reason: String,
+}
+
+#[derive(GraphQLObject)]
+pub struct CardStats {
+    pub question: String,
+    pub kind: String,
+    pub attempts: i32,
+    pub correct: i32,
+    pub wrong_tone: i32,
+    pub incorrect: i32,
+    pub timeouts: i32,
+    /// Mistakes per attempt, from 0 to 1. A wrong tone is half a mistake.
+    pub error_rate: f64,
+    pub average_millis: i32,
+}
+
+impl From<&stats::CardStats> for CardStats {
+    fn from(stats: &stats::CardStats) -> CardStats {
+        CardStats {
+            question: stats.question.clone(),
+            kind: stats.kind.name().to_string(),
+            attempts: stats.attempts as i32,
+            correct: stats.correct as i32,
+            wrong_tone: stats.wrong_tone as i32,
+            incorrect: stats.incorrect as i32,
+            timeouts: stats.timeouts as i32,
+            error_rate: stats.error_rate,
+            average_millis: stats.average_millis as i32,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+pub struct PassRate {
+    pub exam_name: String,
+    pub num_questions: i32,
+    pub max_wrong: Option<i32>,
+    /// In milliseconds.
+    pub timelimit: i32,
+    pub attempts: i32,
+    pub passes: i32,
+    pub abandoned: i32,
+    /// From 0 to 1.
+    pub pass_rate: f64,
+}
+
+impl From<&stats::PassRate> for PassRate {
+    fn from(rate: &stats::PassRate) -> PassRate {
+        PassRate {
+            exam_name: rate.exam_name.clone(),
+            num_questions: rate.num_questions as i32,
+            max_wrong: rate.max_wrong.map(|max_wrong| max_wrong as i32),
+            timelimit: rate.timelimit as i32,
+            attempts: rate.attempts as i32,
+            passes: rate.passes as i32,
+            abandoned: rate.abandoned as i32,
+            pass_rate: rate.pass_rate,
+        }
+    }
+}
+
+/// An [ExamRecord], as sent by the bot when an exam ends.
+#[derive(GraphQLInputObject)]
+pub struct ExamRecordInput {
+    pub exam_name: String,
+    pub practice: bool,
+    pub num_questions: i32,
+    pub max_wrong: Option<i32>,
+    /// In milliseconds.
+    pub timelimit: i32,
+    pub passed: bool,
+    pub completed: bool,
+    pub answers: Vec<GradedAnswerInput>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct GradedAnswerInput {
+    pub question: String,
+    /// A question type, like `hanziToPinyin`.
+    pub kind: String,
+    /// One of `correct`, `wrongTone`, `incorrect`, `timeout` or `quit`.
+    pub outcome: String,
+    pub millis: i32,
+}
+
+impl ExamRecordInput {
+    fn into_record(self) -> FieldResult<ExamRecord> {
+        let answers = self.answers
+            .into_iter()
+            .map(|answer| -> FieldResult<GradedAnswer> {
+                Ok(GradedAnswer {
+                    question: answer.question,
+                    kind: answer.kind.parse::<QuestionType>()?,
+                    outcome: answer.outcome.parse::<Outcome>()?,
+                    millis: u64::try_from(answer.millis)?,
+                })
+            })
+            .collect::<FieldResult<Vec<GradedAnswer>>>()?;
+
+        Ok(ExamRecord {
+            exam_name: self.exam_name,
+            practice: self.practice,
+            num_questions: usize::try_from(self.num_questions)?,
+            max_wrong: self.max_wrong.map(usize::try_from).transpose()?,
+            timelimit: usize::try_from(self.timelimit)?,
+            passed: self.passed,
+            completed: self.completed,
+            answers,
+        })
+    }
+}
+
+#[derive(GraphQLObject)]
+pub struct Card {
+    pub question: String,
+    pub valid_answers: Vec<String>,
+    pub meaning: String,
+}
+
+#[derive(GraphQLObject)]
+pub struct Deck {
+    pub name: String,
+    pub num_questions: i32,
+    pub max_wrong: i32,
+    /// In seconds.
+    pub timelimit: i32,
+    pub hsk_level: i32,
+    pub modes: Vec<String>,
+    pub prompt_style: String,
+    pub deck: Vec<Card>,
+    pub builtin: bool,
+    /// Bumped each time the deck is changed. Pass it to `editDeck` to make sure nobody else has
+    /// changed the deck since. Always 0 for built-in decks.
+    pub version: i32,
+}
+
+impl Deck {
+    fn new(deck: &decks::Deck, builtin: bool, version: u64) -> Deck {
+        Deck {
+            name: deck.name.clone(),
+            num_questions: deck.num_questions as i32,
+            max_wrong: deck.max_wrong as i32,
+            timelimit: deck.timelimit as i32,
+            hsk_level: deck.hsk_level as i32,
+            modes: deck.modes.iter().map(|mode| mode.name().to_string()).collect(),
+            prompt_style: deck.prompt_style.name().to_string(),
+            deck: deck.cards.iter().map(|card| Card {
+                question: card.hanzi.clone(),
+                valid_answers: card.pinyin.clone(),
+                meaning: card.meaning.clone(),
+            }).collect(),
+            builtin,
+            version: version as i32,
+        }
+    }
+}
+
+/// A deck, in the same shape as the deck file format. See [decks].
+#[derive(GraphQLInputObject)]
+pub struct DeckInput {
+    pub name: String,
+    pub num_questions: i32,
+    pub max_wrong: i32,
+    /// In seconds.
+    pub timelimit: i32,
+    pub hsk_level: i32,
+    /// Defaults to `["hanziToPinyin"]`.
+    pub modes: Option<Vec<String>>,
+    /// Defaults to `text`.
+    pub prompt_style: Option<String>,
+    pub deck: Vec<CardInput>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct CardInput {
+    pub question: String,
+    pub valid_answers: Vec<String>,
+    pub meaning: String,
+}
+
+impl DeckInput {
+    fn into_deck(self) -> FieldResult<decks::Deck> {
+        let modes = match self.modes {
+            Some(modes) => modes.iter().map(|mode| mode.parse()).collect::<Result<Vec<QuestionType>, String>>()?,
+            None => vec![QuestionType::HanziToPinyin],
+        };
+        let prompt_style = match self.prompt_style {
+            Some(style) => style.parse::<PromptStyle>()?,
+            None => PromptStyle::Text,
+        };
+
+        Ok(decks::Deck {
+            name: self.name,
+            num_questions: usize::try_from(self.num_questions)?,
+            max_wrong: usize::try_from(self.max_wrong)?,
+            timelimit: usize::try_from(self.timelimit)?,
+            hsk_level: usize::try_from(self.hsk_level)?,
+            modes,
+            prompt_style,
+            cards: self.deck.into_iter().map(|card| chairmanmao::questions::Card {
+                hanzi: card.question,
+                pinyin: card.valid_answers,
+                meaning: card.meaning,
+            }).collect(),
         })
     }
 }
@@ -63,17 +495,73 @@ pub struct Profile {
     pub credit: i32,
     pub yuan: i32,
     pub created: String,
+    pub last_seen: String,
     pub hsk: Option<i32>,
+    pub hanzi_count: i32,
+    pub hsk_coverage: Vec<HskCoverage>,
+    pub mined_words: Vec<String>,
+    pub activity: Activity,
+}
+
+/// How many of the distinct hanzi in an HSK deck a comrade has used.
+#[derive(GraphQLObject)]
+pub struct HskCoverage {
+    pub hsk_level: i32,
+    pub known: i32,
+    pub total: i32,
+    pub percent: f64,
+}
+
+#[derive(GraphQLObject, Default)]
+pub struct Activity {
+    pub message_count: i32,
+    pub character_count: i32,
+    pub cjk_count: i32,
+    pub first_message: Option<String>,
+    pub last_message: Option<String>,
+    pub channels: Vec<ChannelActivity>,
+}
+
+#[derive(GraphQLObject)]
+pub struct ChannelActivity {
+    pub channel_id: String,
+    pub message_count: i32,
+}
+
+impl From<crate::store::Activity> for Activity {
+    fn from(activity: crate::store::Activity) -> Activity {
+        let mut channels: Vec<ChannelActivity> = activity.channels
+            .into_iter()
+            .map(|(channel_id, message_count)| ChannelActivity {
+                channel_id,
+                message_count: message_count as i32,
+            })
+            .collect();
+        channels.sort_by_key(|channel| -channel.message_count);
+
+        Activity {
+            message_count: activity.message_count as i32,
+            character_count: activity.character_count as i32,
+            cjk_count: activity.cjk_count as i32,
+            first_message: Some(activity.first_message.to_rfc3339_string()),
+            last_message: Some(activity.last_message.to_rfc3339_string()),
+            channels,
+        }
+    }
 }
 
 pub struct MutationRoot;
 
-#[juniper::graphql_object(context = RwLock<Context>)]
+/// Every mutation takes an optional `idempotencyKey`. A mutation repeated with a key which has
+/// already been used is not applied again. It reports the event from the first time instead, so a
+/// client may safely retry when it can't tell whether a request went through.
+#[juniper::graphql_object(context = Context)]
 impl MutationRoot {
     async fn register(
         user_id: String,
         discord_username: String,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let id = Ulid::new();
 
@@ -83,7 +571,7 @@ impl MutationRoot {
             discord_username: discord_username.clone(),
         };
 
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
     }
 
     async fn honor(
@@ -91,17 +579,18 @@ impl MutationRoot {
         by_user_id: String,
         amount: i32,
         reason: String,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let id = Ulid::new();
         let event = events::types::ComradeHonored {
             id,
             to_user_id: to_user_id.parse::<u64>().unwrap(),
             by_user_id: by_user_id.parse::<u64>().unwrap(),
-            amount: amount as u64,
+            amount: u64::try_from(amount)?,
             reason: reason.clone(),
         };
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
     }
 
     async fn dishonor(
@@ -109,7 +598,8 @@ impl MutationRoot {
         by_user_id: String,
         amount: i32,
         reason: String,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let id = Ulid::new();
 
@@ -117,17 +607,18 @@ impl MutationRoot {
             id,
             to_user_id: to_user_id.parse::<u64>().unwrap(),
             by_user_id: by_user_id.parse::<u64>().unwrap(),
-            amount: amount as u64,
+            amount: u64::try_from(amount)?,
             reason: reason.clone(),
         };
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
     }
 
     async fn jail(
         to_user_id: String,
         by_user_id: String,
         reason: String,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let id = Ulid::new();
 
@@ -137,13 +628,14 @@ impl MutationRoot {
             by_user_id: by_user_id.parse::<u64>().unwrap(),
             reason: reason.clone(),
         };
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
     }
 
     async fn unjail(
         to_user_id: String,
         by_user_id: String,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let event = events::types::ComradeUnjailed {
             id: Ulid::new(),
@@ -151,13 +643,14 @@ impl MutationRoot {
             by_user_id: by_user_id.parse::<u64>().unwrap(),
         };
 
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
     }
 
     async fn set_party(
         user_id: String,
         flag: bool,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let event = events::types::SetParty {
             id: Ulid::new(),
@@ -165,13 +658,14 @@ impl MutationRoot {
             flag,
         };
 
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
     }
 
     async fn set_hsk(
         user_id: String,
         hsk: Option<i32>,
-        context: &RwLock<Context>,
+        idempotency_key: Option<String>,
+        context: &Context,
     ) -> FieldResult<Command> {
         let event = events::types::SetHsk {
             id: Ulid::new(),
@@ -179,21 +673,209 @@ impl MutationRoot {
             hsk: hsk.map(|h| u64::try_from(h).unwrap()),
         };
 
-        process_event(context, event).await
+        process_event(context, event, idempotency_key).await
+    }
+
+    async fn review_card(
+        user_id: String,
+        question: String,
+        valid_answers: Vec<String>,
+        meaning: String,
+        grade: i32,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let event = events::types::CardReviewed {
+            id: Ulid::new(),
+            user_id: user_id.parse::<u64>()?,
+            question,
+            valid_answers,
+            meaning,
+            grade: u8::try_from(grade)?,
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+
+    async fn record_exam(
+        user_id: String,
+        record: ExamRecordInput,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let event = events::types::ExamRecorded {
+            id: Ulid::new(),
+            user_id: user_id.parse::<u64>()?,
+            record: record.into_record()?,
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+
+    async fn mine_word(
+        user_id: String,
+        word: String,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let event = events::types::WordMined {
+            id: Ulid::new(),
+            user_id: user_id.parse::<u64>()?,
+            word,
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+
+    async fn unmine_word(
+        user_id: String,
+        word: String,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let event = events::types::WordUnmined {
+            id: Ulid::new(),
+            user_id: user_id.parse::<u64>()?,
+            word,
+        };
+
+        process_event(context, event, idempotency_key).await
     }
+
+    async fn create_deck(
+        deck: DeckInput,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let deck = deck.into_deck()?;
+        if is_builtin_deck(context, &deck.name).await {
+            return Command::failed(format!("A built-in deck already has that name: {}", &deck.name));
+        }
+
+        let event = events::types::DeckCreated {
+            id: Ulid::new(),
+            deck,
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+
+    /// Replaces a deck. If `version` is given, the edit is turned away unless the deck is still at
+    /// that version.
+    async fn edit_deck(
+        deck: DeckInput,
+        version: Option<i32>,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let deck = deck.into_deck()?;
+        if is_builtin_deck(context, &deck.name).await {
+            return Command::failed(format!("Built-in decks can't be edited: {}", &deck.name));
+        }
+
+        let event = events::types::DeckEdited {
+            id: Ulid::new(),
+            deck,
+            version: version.map(u64::try_from).transpose()?,
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+
+    async fn delete_deck(
+        name: String,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        if is_builtin_deck(context, &name).await {
+            return Command::failed(format!("Built-in decks can't be deleted: {}", &name));
+        }
+
+        let event = events::types::DeckDeleted {
+            id: Ulid::new(),
+            name,
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+
+    /// Adds cards to a deck from CSV, TSV or Anki-style text.
+    /// Cards whose question is already in the deck are replaced.
+    /// When `replace` is set, the deck's existing cards are dropped first.
+    async fn import_cards(
+        name: String,
+        format: String,
+        text: String,
+        replace: Option<bool>,
+        idempotency_key: Option<String>,
+        context: &Context,
+    ) -> FieldResult<Command> {
+        let format: Format = format.parse()?;
+        let imported = match decks::import_cards(&text, format) {
+            Ok(cards) => cards,
+            Err(e) => return Command::failed(e.to_string()),
+        };
+
+        if is_builtin_deck(context, &name).await {
+            return Command::failed(format!("Built-in decks can't be edited: {}", &name));
+        }
+
+        let event = events::types::CardsImported {
+            id: Ulid::new(),
+            name,
+            cards: imported,
+            replace: replace.unwrap_or(false),
+        };
+
+        process_event(context, event, idempotency_key).await
+    }
+}
+
+async fn is_builtin_deck(context: &Context, name: &str) -> bool {
+    context.builtin_decks.iter().any(|deck| deck.name == name)
 }
 
-async fn process_event<E: Event + Serialize>(context: &RwLock<Context>, event: E) -> FieldResult<Command> {
-    let mut context = context.write().await;
+pub async fn process_event<E: Event + Serialize>(
+    context: &Context,
+    event: E,
+    idempotency_key: Option<String>,
+) -> FieldResult<Command> {
+    if let Some(key) = idempotency_key.as_deref() {
+        if let Some(earlier) = earlier_request(context, key).await {
+            return Command::replied(Reply::replay(&earlier, event.type_name()));
+        }
+    }
 
-    match  event.validate(&context.store).await {
-        Err(msg) => Command::failed(msg),
-        Ok(()) => {
-            context.event_stream.append(&event).await;
-            event.exec(&mut context.store).await.unwrap();
-            Command::succeeded(&event)
-        },
+    if let Err(msg) = event.validate(&context.store).await {
+        return Command::failed(msg);
     }
+
+    let logged = LoggedEvent {
+        id: event.id(),
+        event_type: event.type_name().to_string(),
+        event: serde_json::to_string(&event)?,
+        idempotency_key,
+    };
+    let recorded = context.journal.record(&logged).await;
+    if let Ok(Recorded::Pending(e)) = &recorded {
+        eprintln!("Could not apply event {}: {}", event.id(), e);
+    }
+
+    // A request with the same key may have got in between the check above and recording.
+    let earlier = match (&recorded, logged.idempotency_key.as_deref()) {
+        (Ok(Recorded::Duplicate), Some(key)) => earlier_request(context, key).await,
+        _ => None,
+    };
+    Command::replied(Reply::recorded(&logged, &recorded, earlier.as_ref()))
+}
+
+/// The event applied for an earlier request with the same idempotency key, if there was one.
+async fn earlier_request(context: &Context, key: &str) -> Option<EarlierRequest> {
+    let processed = context.store.load_processed_event(key).await?;
+    Some(EarlierRequest {
+        event_id: processed.event_id,
+        event_type: processed.event_type,
+    })
 }
 
 #[derive(GraphQLObject)]
@@ -204,6 +886,11 @@ pub struct Command {
 }
 
 impl Command {
+    /// Why the command failed, if it did.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
     fn failed<S: AsRef<str>>(error_message: S) -> FieldResult<Command> {
         return Ok(Command {
             success: false,
@@ -212,16 +899,19 @@ impl Command {
         })
     }
 
-    fn succeeded(event: &dyn Event) -> FieldResult<Command> {
-        return Ok(Command {
-            success: true,
-            error: None,
-            event_id: Some(event.id().to_string()),
-        })
+    fn replied(reply: Reply) -> FieldResult<Command> {
+        match reply {
+            Reply::Succeeded(event_id) => Ok(Command {
+                success: true,
+                error: None,
+                event_id: Some(event_id),
+            }),
+            Reply::Failed(error_message) => Command::failed(error_message),
+        }
     }
 }
 
-pub type Schema = RootNode<'static, QueryRoot, MutationRoot, EmptySubscription<RwLock<Context>>>;
+pub type Schema = RootNode<'static, QueryRoot, MutationRoot, EmptySubscription<Context>>;
 
 pub fn create_schema() -> Schema {
     Schema::new(
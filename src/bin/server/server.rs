@@ -1,6 +1,7 @@
 mod store;
 mod schema;
 mod events;
+mod activity;
 
 use std::{convert::Infallible};
 
@@ -19,9 +20,12 @@ async fn main() {
     pretty_env_logger::init();
     let addr = ([0, 0, 0, 0], 8000).into();
 
-    let context = std::sync::Arc::new(tokio::sync::RwLock::new(schema::Context::new().await));
+    let context = std::sync::Arc::new(schema::Context::new().await);
     let schema = std::sync::Arc::new(create_schema());
 
+    tokio::spawn(activity::tail_messages(context.clone()));
+    tokio::spawn(schema::catch_up_events(context.clone()));
+
     let new_service = make_service_fn(move |_| {
         let context = context.clone();
         let schema = schema.clone();
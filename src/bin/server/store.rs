@@ -1,5 +1,15 @@
 // This trait is required to use `try_next()` on the cursor
+use std::collections::HashMap;
+use futures::stream::TryStreamExt;
 use mongodb::{bson::doc, Database};
+use mongodb::options::{FindOptions, IndexOptions, ReplaceOptions, UpdateOptions};
+use mongodb::error::{ErrorKind, WriteError, WriteFailure};
+use mongodb::IndexModel;
+use ulid::Ulid;
+use chairmanmao::review::ReviewCard;
+use chairmanmao::decks::Deck;
+use chairmanmao::stats::ExamRecord;
+use chairmanmao::versioned::{self, Attempt};
 use serde::{Serialize, Deserialize};
 
 async fn connect_to_mongo() -> Database {
@@ -13,29 +23,170 @@ async fn connect_to_mongo() -> Database {
     db
 }
 
+#[derive(Clone)]
 pub struct Store {
     profiles_collection: mongodb::Collection<Profile>,
+    activity_collection: mongodb::Collection<Activity>,
+    review_cards_collection: mongodb::Collection<StoredReviewCard>,
+    decks_collection: mongodb::Collection<StoredDeck>,
+    exam_records_collection: mongodb::Collection<StoredExamRecord>,
+    processed_events_collection: mongodb::Collection<ProcessedEvent>,
+    checkpoints_collection: mongodb::Collection<Checkpoint>,
+}
+
+/// The fields of a [Profile] which are only changed in place, by atomic updates. Saving a profile
+/// leaves them alone, so it can't undo a change made since the profile was loaded.
+const IN_PLACE_PROFILE_FIELDS: [&str; 2] = ["credit", "last_seen"];
+
+/// A conditional save which matched nothing lost to someone else's save.
+fn saved(matched: bool) -> Attempt {
+    if matched {
+        Attempt::Saved
+    } else {
+        Attempt::Conflict
+    }
+}
+
+/// The code Mongo gives a write which would break a unique index.
+const DUPLICATE_KEY: i32 = 11000;
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(&*e.kind, ErrorKind::Write(WriteFailure::WriteError(WriteError { code: DUPLICATE_KEY, .. })))
 }
 
 impl Store {
     pub async fn new() -> Store {
         let db: Database = connect_to_mongo().await;
         let profiles_collection = db.collection::<Profile>("Profiles");
+        let activity_collection = db.collection::<Activity>("Activity");
+        let review_cards_collection = db.collection::<StoredReviewCard>("ReviewCards");
+        let decks_collection = db.collection::<StoredDeck>("Decks");
+        let exam_records_collection = db.collection::<StoredExamRecord>("ExamRecords");
+        let processed_events_collection = db.collection::<ProcessedEvent>("ProcessedEvents");
+        let checkpoints_collection = db.collection::<Checkpoint>("Checkpoints");
+
+        // Event IDs are kept unique by `_id`. Idempotency keys are optional, so only the events
+        // which have one are indexed.
+        let index = IndexModel::builder()
+            .keys(doc! { "idempotency_key": 1 })
+            .options(IndexOptions::builder()
+                .unique(true)
+                .partial_filter_expression(doc! { "idempotency_key": { "$exists": true } })
+                .build())
+            .build();
+        processed_events_collection.create_index(index, None).await.unwrap();
+
+        let index = IndexModel::builder()
+            .keys(doc! { "user_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        profiles_collection.create_index(index, None).await.unwrap();
+
+        let index = IndexModel::builder()
+            .keys(doc! { "name": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        decks_collection.create_index(index, None).await.unwrap();
+
+        let index = IndexModel::builder()
+            .keys(doc! { "user_id": 1, "question": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        review_cards_collection.create_index(index, None).await.unwrap();
+
+        let index = IndexModel::builder()
+            .keys(doc! { "user_id": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        activity_collection.create_index(index, None).await.unwrap();
+
+        // Documents saved before they were versioned start at version 0.
+        let filter = doc! { "version": { "$exists": false } };
+        let update = doc! { "$set": { "version": 0_i64 } };
+        profiles_collection.update_many(filter.clone(), update.clone(), None).await.unwrap();
+        decks_collection.update_many(filter.clone(), update.clone(), None).await.unwrap();
+        review_cards_collection.update_many(filter, update, None).await.unwrap();
 
         Store {
             profiles_collection,
+            activity_collection,
+            review_cards_collection,
+            decks_collection,
+            exam_records_collection,
+            processed_events_collection,
+            checkpoints_collection,
+        }
+    }
+
+    /// Marks an event as processed. Returns false if an event with the same ID or idempotency key
+    /// was already processed, in which case this one must not be.
+    pub async fn record_processed_event(
+        &self,
+        event_id: Ulid,
+        event_type: &str,
+        idempotency_key: Option<&str>,
+    ) -> mongodb::error::Result<bool> {
+        let processed = ProcessedEvent {
+            event_id: event_id.to_string(),
+            event_type: event_type.to_string(),
+            idempotency_key: idempotency_key.map(|key| key.to_string()),
+            processed_at: bson::DateTime::now(),
+        };
+        match self.processed_events_collection.insert_one(processed, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e),
         }
     }
 
-    pub async fn profile_count(&mut self) -> mongodb::error::Result<u64> {
+    /// Finds the event which was processed under an idempotency key, if any.
+    pub async fn load_processed_event(&self, idempotency_key: &str) -> Option<ProcessedEvent> {
+        let filter = doc! {
+            "idempotency_key": idempotency_key,
+        };
+        self.processed_events_collection.find_one(filter, None).await.unwrap()
+    }
+
+    pub async fn is_processed_event(&self, event_id: Ulid) -> mongodb::error::Result<bool> {
+        let filter = doc! {
+            "_id": event_id.to_string(),
+        };
+        Ok(self.processed_events_collection.find_one(filter, None).await?.is_some())
+    }
+
+    /// How far through a stream the store has got, if it has ever saved a checkpoint for it.
+    pub async fn load_checkpoint(&self, name: &str) -> mongodb::error::Result<Option<String>> {
+        let filter = doc! {
+            "_id": name,
+        };
+        let checkpoint = self.checkpoints_collection.find_one(filter, None).await?;
+        Ok(checkpoint.map(|checkpoint| checkpoint.position))
+    }
+
+    pub async fn store_checkpoint(&self, name: &str, position: &str) -> mongodb::error::Result<()> {
+        let filter = doc! {
+            "_id": name,
+        };
+        let checkpoint = Checkpoint {
+            name: name.to_string(),
+            position: position.to_string(),
+        };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.checkpoints_collection.replace_one(filter, checkpoint, options).await?;
+        Ok(())
+    }
+
+    pub async fn profile_count(&self) -> mongodb::error::Result<u64> {
         self.profiles_collection.count_documents(None, None).await
     }
 
+    /// Creates a profile. Returns `None` if the user already has one, as they can if two requests
+    /// to register them race.
     pub async fn register(
-        &mut self,
+        &self,
         user_id: u64,
         discord_username: String,
-    ) -> mongodb::error::Result<Profile> {
+    ) -> mongodb::error::Result<Option<Profile>> {
         let count = self.profile_count().await?;
         let yuan = if count == 0 {
             10000
@@ -60,10 +211,14 @@ impl Store {
             defected: false,
 
             hsk: None,
+            version: 0,
         };
 
-        self.profiles_collection.insert_one(profile.clone(), None).await?;
-        Ok(profile)
+        match self.profiles_collection.insert_one(profile.clone(), None).await {
+            Ok(_) => Ok(Some(profile)),
+            Err(e) if is_duplicate_key(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     pub async fn load_profile(&self, user_id: u64) -> Option<Profile> {
@@ -73,14 +228,334 @@ impl Store {
         self.profiles_collection.find_one(filter, None).await.unwrap()
     }
 
-    pub async fn store_profile(&mut self, profile: &Profile) {
+    /// The comrades with the most credit, best first. Comrades who have defected are left out.
+    pub async fn top_profiles(&self, limit: i64) -> Vec<Profile> {
+        let filter = doc! {
+            "defected": false,
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "credit": -1, "user_id": 1 })
+            .limit(limit)
+            .build();
+        let cursor = self.profiles_collection.find(filter, options).await.unwrap();
+        cursor.try_collect().await.unwrap()
+    }
+
+    /// Saves a profile, unless someone else has saved it since it was loaded. Returns false, saving
+    /// nothing, if they have.
+    pub async fn store_profile(&self, profile: &Profile) -> mongodb::error::Result<bool> {
+        let mut fields = bson::to_document(profile)?;
+        fields.remove("version");
+        for field in IN_PLACE_PROFILE_FIELDS.iter() {
+            fields.remove(field);
+        }
+
         let filter = doc! {
             "user_id": profile.user_id as i64,
+            "version": profile.version as i64,
+        };
+        let update = doc! {
+            "$set": fields,
+            "$inc": { "version": 1_i64 },
+        };
+        let result = self.profiles_collection.update_one(filter, update, None).await?;
+        Ok(result.matched_count == 1)
+    }
+
+    /// Loads a profile, changes it, and saves it. If someone else saves the profile in the
+    /// meantime, it starts over with their version.
+    pub async fn update_profile<F>(&self, user_id: u64, change: F) -> Result<(), String>
+    where
+        F: Fn(&mut Profile) + Send + Sync,
+    {
+        let change = &change;
+        let found = versioned::update(&format!("Profile {}", user_id), || async move {
+            let mut profile = match self.load_profile(user_id).await {
+                Some(profile) => profile,
+                None => return Ok(Attempt::Missing),
+            };
+            change(&mut profile);
+            Ok(saved(self.store_profile(&profile).await.map_err(|e| e.to_string())?))
+        }).await?;
+
+        if !found {
+            return Err(format!("No such profile: {}", user_id));
+        }
+        Ok(())
+    }
+
+    /// Changes a comrade's credit in place, for the event `event_id`.
+    ///
+    /// The event is noted on the profile, under `credit_events`, in the same write. So if the event
+    /// is applied again, as it is if the server dies before marking it processed, nothing changes.
+    pub async fn add_credit(&self, user_id: u64, event_id: Ulid, change: i64) -> mongodb::error::Result<()> {
+        let filter = doc! {
+            "user_id": user_id as i64,
+            "credit_events": { "$ne": event_id.to_string() },
         };
-        self.profiles_collection.replace_one(filter, profile, None).await.unwrap();
+        let update = doc! {
+            "$inc": {
+                "credit": change,
+            },
+            "$push": {
+                "credit_events": event_id.to_string(),
+            },
+        };
+        self.profiles_collection.update_one(filter, update, None).await?;
+        Ok(())
+    }
+
+    pub async fn load_activity(&self, user_id: u64) -> Option<Activity> {
+        let filter = doc! {
+            "user_id": user_id as i64,
+        };
+        self.activity_collection.find_one(filter, None).await.unwrap()
+    }
+
+    /// Records a message in the author's [Activity] and bumps their [Profile::last_seen].
+    ///
+    /// Messages may be logged by users who have not registered yet.
+    /// Their activity is still counted, but there is no profile to update.
+    ///
+    /// Messages must be logged in the order they were sent. `position` is where the message is in
+    /// the `messages` stream, in a form which sorts in the same order, and a message at or before
+    /// the author's [Activity::last_entry] has already been counted, so it is skipped. That way a
+    /// message can be logged again if something fails part way.
+    pub async fn log_message(&self, position: &str, message: &MessageLogged) -> mongodb::error::Result<()> {
+        let filter = doc! {
+            "user_id": message.user_id as i64,
+        };
+        let timestamp = bson::DateTime::from_millis(message.timestamp);
+
+        let mut not_counted = filter.clone();
+        not_counted.insert("$or", vec![
+            doc! { "last_entry": { "$exists": false } },
+            doc! { "last_entry": { "$lt": position } },
+        ]);
+        let update = doc! {
+            "$inc": {
+                "message_count": 1_i64,
+                "character_count": message.length as i64,
+                "cjk_count": message.cjk_count as i64,
+                format!("channels.{}", message.channel_id): 1_i64,
+            },
+            "$min": {
+                "first_message": timestamp,
+            },
+            "$max": {
+                "last_message": timestamp,
+            },
+            "$set": {
+                "last_entry": position,
+            },
+        };
+        let options = UpdateOptions::builder().upsert(true).build();
+        match self.activity_collection.update_one(not_counted, update, options).await {
+            // The author's activity already counts this message, so there was nothing to match.
+            Err(e) if is_duplicate_key(&e) => (),
+            result => {
+                result?;
+            },
+        }
+
+        let update = doc! {
+            "$max": {
+                "last_seen": timestamp,
+            },
+        };
+        self.profiles_collection.update_one(filter, update, None).await?;
+        Ok(())
+    }
+
+    pub async fn load_review_cards(&self, user_id: u64) -> Vec<ReviewCard> {
+        let filter = doc! {
+            "user_id": user_id as i64,
+        };
+        let cursor = self.review_cards_collection.find(filter, None).await.unwrap();
+        let cards: Vec<StoredReviewCard> = cursor.try_collect().await.unwrap();
+        cards.into_iter().map(|stored| stored.card).collect()
+    }
+
+    /// Loads a user's card for a question, or starts a new one, changes it, and saves it. If someone
+    /// else saves the card in the meantime, it starts over with their version.
+    pub async fn update_review_card<N, F>(&self, user_id: u64, question: &str, new: N, change: F) -> Result<(), String>
+    where
+        N: Fn() -> ReviewCard + Send + Sync,
+        F: Fn(&mut ReviewCard) + Send + Sync,
+    {
+        let filter = &doc! {
+            "user_id": user_id as i64,
+            "question": question,
+        };
+        let (new, change) = (&new, &change);
+
+        versioned::update(&format!("Review card {} for {}", question, user_id), || async move {
+            let stored = self.review_cards_collection.find_one(filter.clone(), None).await.map_err(|e| e.to_string())?;
+            match stored {
+                Some(mut stored) => {
+                    let mut filter = filter.clone();
+                    filter.insert("version", stored.version as i64);
+                    change(&mut stored.card);
+                    stored.version += 1;
+                    let result = self.review_cards_collection.replace_one(filter, stored, None).await.map_err(|e| e.to_string())?;
+                    Ok(saved(result.matched_count == 1))
+                },
+                None => {
+                    let mut card = new();
+                    change(&mut card);
+                    let stored = StoredReviewCard {
+                        user_id,
+                        card,
+                        version: 0,
+                    };
+                    match self.review_cards_collection.insert_one(stored, None).await {
+                        Ok(_) => Ok(Attempt::Saved),
+                        // Someone else made the card first.
+                        Err(e) if is_duplicate_key(&e) => Ok(Attempt::Conflict),
+                        Err(e) => Err(e.to_string()),
+                    }
+                },
+            }
+        }).await?;
+        Ok(())
+    }
+
+    pub async fn load_decks(&self) -> Vec<StoredDeck> {
+        let cursor = self.decks_collection.find(None, None).await.unwrap();
+        cursor.try_collect().await.unwrap()
+    }
+
+    pub async fn load_deck(&self, name: &str) -> Option<Deck> {
+        self.load_stored_deck(name).await.map(|stored| stored.deck)
+    }
+
+    pub async fn load_stored_deck(&self, name: &str) -> Option<StoredDeck> {
+        let filter = doc! {
+            "name": name,
+        };
+        self.decks_collection.find_one(filter, None).await.unwrap()
+    }
+
+    /// Creates a deck. Returns false, changing nothing, if there is already a deck with its name.
+    pub async fn create_deck(&self, deck: &Deck) -> mongodb::error::Result<bool> {
+        let stored = StoredDeck {
+            deck: deck.clone(),
+            version: 0,
+        };
+        match self.decks_collection.insert_one(stored, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Loads a deck, changes it, and saves it. If someone else saves the deck in the meantime, it
+    /// starts over with their version. Returns false if there is no such deck.
+    pub async fn update_deck<F>(&self, name: &str, change: F) -> Result<bool, String>
+    where
+        F: Fn(&mut Deck) + Send + Sync,
+    {
+        let change = &change;
+        versioned::update(&format!("Deck {}", name), || async move {
+            let mut stored = match self.load_stored_deck(name).await {
+                Some(stored) => stored,
+                None => return Ok(Attempt::Missing),
+            };
+            let filter = doc! {
+                "name": name,
+                "version": stored.version as i64,
+            };
+            change(&mut stored.deck);
+            stored.version += 1;
+            let result = self.decks_collection.replace_one(filter, stored, None).await.map_err(|e| e.to_string())?;
+            Ok(saved(result.matched_count == 1))
+        }).await
+    }
+
+    pub async fn delete_deck(&self, name: &str) {
+        let filter = doc! {
+            "name": name,
+        };
+        self.decks_collection.delete_one(filter, None).await.unwrap();
+    }
+
+    pub async fn store_exam_record(&self, user_id: u64, taken_at: bson::DateTime, record: &ExamRecord) {
+        let stored = StoredExamRecord {
+            user_id,
+            taken_at,
+            record: record.clone(),
+        };
+        self.exam_records_collection.insert_one(stored, None).await.unwrap();
+    }
+
+    /// Loads the recorded exams, optionally only those of one user or one deck.
+    pub async fn load_exam_records(&self, user_id: Option<u64>, exam_name: Option<&str>) -> Vec<ExamRecord> {
+        let mut filter = doc! {};
+        if let Some(user_id) = user_id {
+            filter.insert("user_id", user_id as i64);
+        }
+        if let Some(exam_name) = exam_name {
+            filter.insert("examName", exam_name);
+        }
+        let cursor = self.exam_records_collection.find(filter, None).await.unwrap();
+        let records: Vec<StoredExamRecord> = cursor.try_collect().await.unwrap();
+        records.into_iter().map(|stored| stored.record).collect()
     }
 }
 
+/// An event which has been applied. Each event ID, and each idempotency key, appears only once.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessedEvent {
+    #[serde(rename = "_id")]
+    pub event_id: String,
+    pub event_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    pub processed_at: bson::DateTime,
+}
+
+/// The position of the last entry applied from a stream.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    #[serde(rename = "_id")]
+    pub name: String,
+    pub position: String,
+}
+
+/// A [ReviewCard] belonging to a particular user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredReviewCard {
+    pub user_id: u64,
+
+    #[serde(flatten)]
+    pub card: ReviewCard,
+
+    /// Bumped each time the card is saved. See [Store::update_review_card].
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// A [Deck] created through the API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredDeck {
+    #[serde(flatten)]
+    pub deck: Deck,
+
+    /// Bumped each time the deck is saved. See [Store::update_deck].
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// An [ExamRecord] belonging to a particular user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredExamRecord {
+    pub user_id: u64,
+    pub taken_at: bson::DateTime,
+
+    #[serde(flatten)]
+    pub record: ExamRecord,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Profile {
     pub user_id: u64,
@@ -91,13 +566,18 @@ pub struct Profile {
 
     pub roles: Vec<String>,
     pub display_name: String,
-    pub credit: usize,
+    /// Dishonors are checked against this, but two at once can still take it below zero.
+    pub credit: i64,
     pub yuan: usize,
     pub hanzi: Vec<String>,
     pub mined_words: Vec<String>,
     pub defected: bool,
 
     pub hsk: Option<u64>,
+
+    /// Bumped each time the profile is saved. See [Store::store_profile].
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Profile {
@@ -125,3 +605,36 @@ impl Profile {
         self.roles.contains(&role.to_string())
     }
 }
+
+/// The metadata for a single chat message, as logged by the bot to the `messages` stream.
+///
+/// The content of the message itself is never stored, only which hanzi it used.
+#[derive(Debug, Clone)]
+pub struct MessageLogged {
+    pub user_id: u64,
+    pub channel_id: u64,
+    pub timestamp: i64,
+    pub length: u64,
+    pub cjk_count: u64,
+
+    /// The distinct hanzi used in the message.
+    pub hanzi: Vec<String>,
+}
+
+/// Running per-user message statistics.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Activity {
+    pub user_id: u64,
+    pub message_count: u64,
+    pub character_count: u64,
+    pub cjk_count: u64,
+    pub first_message: bson::DateTime,
+    pub last_message: bson::DateTime,
+
+    /// Message counts, keyed by channel id.
+    pub channels: HashMap<String, u64>,
+
+    /// The position of the last message counted. See [Store::log_message].
+    #[serde(default)]
+    pub last_entry: Option<String>,
+}
@@ -0,0 +1,246 @@
+//! The leaderboard and credit history charts, drawn as images for `!leaderboard` and `!history`.
+
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+use crate::credit::CreditPoint;
+use crate::draw::{fill_circle, fill_rect, overlay, DrawError, FontRegistry};
+use crate::profile::{text, BACKGROUND, GOLD, MUTED, PANEL, RED, WHITE};
+
+/// A comrade's place on the leaderboard. Comrades with the same credit share a rank.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Ranking {
+    pub rank: u32,
+    pub user_id: String,
+    pub display_name: String,
+    pub credit: i64,
+    pub hsk: Option<u8>,
+}
+
+pub const CHART_WIDTH: u32 = 800;
+pub const CHART_HEIGHT: u32 = 400;
+
+const HEADER_HEIGHT: u32 = 80;
+const ROW_HEIGHT: u32 = 56;
+const MARGIN: i64 = 40;
+
+const SILVER: Rgba<u8> = Rgba([192, 192, 200, 255]);
+const BRONZE: Rgba<u8> = Rgba([205, 127, 50, 255]);
+
+/// Where the plot sits inside the credit history chart.
+const PLOT_LEFT: i64 = 90;
+const PLOT_RIGHT: i64 = CHART_WIDTH as i64 - 30;
+const PLOT_TOP: i64 = 70;
+const PLOT_BOTTOM: i64 = CHART_HEIGHT as i64 - 50;
+
+/// Draws the leaderboard as a table, one row per comrade, with the podium picked out in gold,
+/// silver and bronze.
+pub fn render_leaderboard(fonts: &FontRegistry, rankings: &[Ranking]) -> Result<RgbaImage, DrawError> {
+    let height = HEADER_HEIGHT + ROW_HEIGHT * rankings.len().max(1) as u32 + MARGIN as u32 / 2;
+    let mut board = RgbaImage::from_pixel(CHART_WIDTH, height, BACKGROUND);
+    fill_rect(&mut board, 0, 0, CHART_WIDTH, 8, RED);
+
+    overlay(&mut board, &text(fonts, "Social credit leaderboard", 40.0, WHITE)?, MARGIN, 20);
+
+    if rankings.is_empty() {
+        let empty = text(fonts, "No comrades yet", 28.0, MUTED)?;
+        overlay(&mut board, &empty, MARGIN, HEADER_HEIGHT as i64 + 12);
+        return Ok(board);
+    }
+
+    for (row, ranking) in rankings.iter().enumerate() {
+        let y = HEADER_HEIGHT as i64 + (row as u32 * ROW_HEIGHT) as i64;
+        if row % 2 == 0 {
+            fill_rect(&mut board, MARGIN / 2, y, CHART_WIDTH - MARGIN as u32, ROW_HEIGHT, PANEL);
+        }
+
+        let rank_colour = match ranking.rank {
+            1 => GOLD,
+            2 => SILVER,
+            3 => BRONZE,
+            _ => MUTED,
+        };
+        let label_y = y + 10;
+        overlay(&mut board, &text(fonts, &format!("#{}", ranking.rank), 30.0, rank_colour)?, MARGIN, label_y);
+
+        let name = match ranking.display_name.trim() {
+            "" => "Comrade",
+            name => name,
+        };
+        overlay(&mut board, &text(fonts, name, 30.0, WHITE)?, MARGIN + 90, label_y);
+
+        let credit = text(fonts, &ranking.credit.to_string(), 30.0, WHITE)?;
+        let credit_x = CHART_WIDTH as i64 - MARGIN - credit.width() as i64;
+        overlay(&mut board, &credit, credit_x, label_y);
+
+        if let Some(level) = ranking.hsk {
+            let hsk = text(fonts, &format!("HSK {}", level), 22.0, MUTED)?;
+            overlay(&mut board, &hsk, credit_x - 40 - hsk.width() as i64, label_y + 6);
+        }
+    }
+
+    Ok(board)
+}
+
+/// Draws a comrade's credit over time as a step chart. The last step runs to the right edge,
+/// since their credit has stood there ever since.
+pub fn render_credit_history(fonts: &FontRegistry, name: &str, history: &[CreditPoint]) -> Result<RgbaImage, DrawError> {
+    let mut chart = RgbaImage::from_pixel(CHART_WIDTH, CHART_HEIGHT, BACKGROUND);
+    fill_rect(&mut chart, 0, 0, CHART_WIDTH, 8, RED);
+    overlay(&mut chart, &text(fonts, &format!("{}'s social credit", name), 36.0, WHITE)?, MARGIN, 18);
+
+    let points: Vec<(i64, i64)> = history
+        .iter()
+        .filter_map(|point| Some((point.millis()?, point.credit)))
+        .collect();
+    if points.is_empty() {
+        let empty = text(fonts, "No credit history", 28.0, MUTED)?;
+        overlay(&mut chart, &empty, PLOT_LEFT, PLOT_TOP + 20);
+        return Ok(chart);
+    }
+
+    let (low, high, step) = credit_range(&points);
+    let y_of = |credit: i64| {
+        let fraction = (credit - low) as f32 / (high - low) as f32;
+        PLOT_BOTTOM - (fraction * (PLOT_BOTTOM - PLOT_TOP) as f32).round() as i64
+    };
+
+    let start = points[0].0;
+    let span = (points[points.len() - 1].0 - start).max(1);
+    let x_of = |millis: i64| {
+        let fraction = (millis - start) as f32 / span as f32;
+        // Leave room after the last change, so it doesn't sit against the edge.
+        PLOT_LEFT + (fraction * (PLOT_RIGHT - PLOT_LEFT - 40) as f32).round() as i64
+    };
+
+    // Gridlines, labelled with their credit
+    let mut credit = low;
+    while credit <= high {
+        let y = y_of(credit);
+        fill_rect(&mut chart, PLOT_LEFT, y, (PLOT_RIGHT - PLOT_LEFT) as u32, 1, PANEL);
+        let label = text(fonts, &credit.to_string(), 18.0, MUTED)?;
+        overlay(&mut chart, &label, PLOT_LEFT - 10 - label.width() as i64, y - label.height() as i64 / 2);
+        credit += step;
+    }
+
+    // The steps themselves
+    for (index, &(millis, credit)) in points.iter().enumerate() {
+        let x = x_of(millis);
+        let y = y_of(credit);
+        let next_x = match points.get(index + 1) {
+            Some(&(next_millis, _)) => x_of(next_millis),
+            None => PLOT_RIGHT,
+        };
+        fill_rect(&mut chart, x, y - 1, (next_x - x).max(0) as u32 + 1, 3, GOLD);
+
+        if let Some(&(_, previous)) = index.checked_sub(1).and_then(|previous| points.get(previous)) {
+            let (top, bottom) = (y_of(previous).min(y), y_of(previous).max(y));
+            fill_rect(&mut chart, x - 1, top, 3, (bottom - top) as u32 + 1, GOLD);
+        }
+        fill_circle(&mut chart, x as f32, y as f32, 5.0, RED);
+    }
+
+    // When it starts and ends
+    let dates = [&history[0].at, &history[history.len() - 1].at];
+    if let Some(first) = dates[0].get(..10) {
+        overlay(&mut chart, &text(fonts, first, 18.0, MUTED)?, PLOT_LEFT, PLOT_BOTTOM + 16);
+    }
+    if let Some(last) = dates[1].get(..10).filter(|last| Some(*last) != dates[0].get(..10)) {
+        let label = text(fonts, last, 18.0, MUTED)?;
+        overlay(&mut chart, &label, PLOT_RIGHT - label.width() as i64, PLOT_BOTTOM + 16);
+    }
+
+    Ok(chart)
+}
+
+/// The credit at the bottom and top of the chart, and the step between gridlines. The lowest and
+/// highest credit are widened out to whole gridlines.
+fn credit_range(points: &[(i64, i64)]) -> (i64, i64, i64) {
+    let lowest = points.iter().map(|(_millis, credit)| *credit).min().unwrap_or(0);
+    let highest = points.iter().map(|(_millis, credit)| *credit).max().unwrap_or(0);
+    let step = grid_step((highest - lowest).max(1));
+    let low = lowest.div_euclid(step) * step;
+    let high = (highest.div_euclid(step) + 1) * step;
+    (low, high, step)
+}
+
+/// A round step between gridlines (1, 2 or 5 times a power of ten) giving about four of them.
+fn grid_step(range: i64) -> i64 {
+    let rough = (range.max(1) as f64 / 4.0).max(1.0);
+    let magnitude = 10f64.powi(rough.log10().floor() as i32);
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|multiple| multiple * magnitude)
+        .find(|step| *step >= rough)
+        .unwrap_or(10.0 * magnitude);
+    step as i64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::draw::FONT_DIR;
+
+    fn point(at: &str, credit: i64) -> CreditPoint {
+        CreditPoint {
+            at: at.to_string(),
+            credit,
+            change: 0,
+            reason: String::new(),
+        }
+    }
+
+    #[test]
+    fn leaderboard() {
+        let fonts = FontRegistry::load(FONT_DIR).unwrap();
+        let rankings: Vec<Ranking> = (1..=5)
+            .map(|rank| Ranking {
+                rank,
+                user_id: rank.to_string(),
+                display_name: format!("同志{}", rank),
+                credit: 2000 - rank as i64 * 100,
+                hsk: if rank % 2 == 0 { Some(rank as u8) } else { None },
+            })
+            .collect();
+
+        let board = render_leaderboard(&fonts, &rankings).unwrap();
+        assert_eq!(board.width(), CHART_WIDTH);
+        assert_eq!(board.height(), HEADER_HEIGHT + 5 * ROW_HEIGHT + MARGIN as u32 / 2);
+        assert_eq!(*board.get_pixel(MARGIN as u32 / 2 + 1, HEADER_HEIGHT + 1), PANEL);
+        assert_eq!(*board.get_pixel(MARGIN as u32 / 2 + 1, HEADER_HEIGHT + ROW_HEIGHT + 1), BACKGROUND);
+
+        assert!(render_leaderboard(&fonts, &[]).is_ok());
+    }
+
+    #[test]
+    fn credit_history() {
+        let fonts = FontRegistry::load(FONT_DIR).unwrap();
+        let history = vec![
+            point("2022-01-01T00:00:00Z", 1000),
+            point("2022-01-05T00:00:00Z", 950),
+            point("2022-02-01T00:00:00Z", 1230),
+        ];
+
+        let chart = render_credit_history(&fonts, "Mao", &history).unwrap();
+        assert_eq!(chart.dimensions(), (CHART_WIDTH, CHART_HEIGHT));
+        // The last step runs out to the right edge.
+        let (low, high, _step) = credit_range(&[(0, 950), (0, 1230)]);
+        let y = PLOT_BOTTOM - ((1230 - low) as f32 / (high - low) as f32 * (PLOT_BOTTOM - PLOT_TOP) as f32).round() as i64;
+        assert_eq!(*chart.get_pixel(PLOT_RIGHT as u32 - 1, y as u32), GOLD);
+
+        assert!(render_credit_history(&fonts, "Mao", &history[..1]).is_ok());
+        assert!(render_credit_history(&fonts, "Mao", &[]).is_ok());
+    }
+
+    #[test]
+    fn gridlines() {
+        assert_eq!(grid_step(0), 1);
+        assert_eq!(grid_step(40), 10);
+        assert_eq!(grid_step(280), 100);
+        assert_eq!(grid_step(3000), 1000);
+        assert_eq!(credit_range(&[(0, 950), (0, 1230)]), (900, 1300, 100));
+        assert_eq!(credit_range(&[(0, 1000)]), (1000, 1001, 1));
+        assert_eq!(credit_range(&[(0, -30), (0, 10)]), (-30, 20, 10));
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// A source of time, in milliseconds.
+///
+/// Only differences between readings are meaningful. The starting point is up to the clock.
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> u64;
+}
+
+/// Real time, counted from when the clock was created.
+#[derive(Debug, Clone)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// A clock which only moves when it is told to. It starts at 0.
+///
+/// Clones share the same time, so a test can keep one while handing another to an
+/// [crate::exams::Examiner].
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedClock {
+    now: Arc<AtomicU64>,
+}
+
+impl SimulatedClock {
+    pub fn new() -> SimulatedClock {
+        SimulatedClock::default()
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn simulated_clones_share_time() {
+        let clock = SimulatedClock::new();
+        let shared = clock.clone();
+        clock.advance(250);
+        assert_eq!(shared.now(), 250);
+    }
+}
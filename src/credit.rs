@@ -0,0 +1,244 @@
+//! Social credit history, reconstructed from the `events` stream.
+//!
+//! Events reach the stream in two shapes. The bot writes each field of an event as its own stream
+//! field, while the server writes the whole event as JSON under `event`. Both are read here.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Serialize, Deserialize};
+use ulid::Ulid;
+
+/// A comrade's credit just after a change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CreditPoint {
+    /// When the change happened, in RFC 3339.
+    pub at: String,
+    pub credit: i64,
+    /// How much the credit changed by. Zero for registration.
+    pub change: i64,
+    pub reason: String,
+}
+
+impl CreditPoint {
+    /// [CreditPoint::at] in milliseconds since the epoch, or `None` if it can't be read.
+    pub fn millis(&self) -> Option<i64> {
+        bson::DateTime::parse_rfc3339_str(&self.at).ok().map(|at| at.timestamp_millis())
+    }
+}
+
+/// An entry from the `events` stream: its stream ID and its fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamEntry {
+    pub id: String,
+    pub fields: HashMap<String, String>,
+}
+
+impl StreamEntry {
+    /// Reads a field, whether it was written on its own or inside the JSON `event`.
+    fn field(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.fields.get(name) {
+            return Some(value.clone());
+        }
+
+        let event: serde_json::Value = serde_json::from_str(self.fields.get("event")?).ok()?;
+        match &event[name] {
+            serde_json::Value::String(value) => Some(value.clone()),
+            serde_json::Value::Number(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// When the event happened, from its ULID, or failing that, from its stream ID.
+    fn millis(&self) -> Option<i64> {
+        if let Some(id) = self.field("id").and_then(|id| Ulid::from_string(&id).ok()) {
+            return Some(id.timestamp_ms() as i64);
+        }
+        self.id.split('-').next()?.parse().ok()
+    }
+}
+
+/// Checks that a comrade with `credit` can be dishonored by `amount`. Credit can't go below zero.
+pub fn check_dishonor(credit: i64, amount: u64) -> Result<(), String> {
+    if amount > credit.max(0) as u64 {
+        return Err(format!("Comrade only has {} credit to lose", credit));
+    }
+    Ok(())
+}
+
+/// Every change to a comrade's credit, oldest first, along with their registration if the stream
+/// still has it.
+///
+/// The history is worked out backwards from `current_credit`, so it comes out right even if the
+/// stream has been trimmed, or the starting credit has changed since. An entry which repeats an
+/// earlier one field for field is skipped. Entries which only share an event ID are different
+/// events, and both are counted. An entry with the same idempotency key as an earlier one is a
+/// retried request, which the server logged but never applied, so it is skipped.
+pub fn credit_history(entries: &[StreamEntry], user_id: u64, current_credit: i64) -> Vec<CreditPoint> {
+    let user_id = user_id.to_string();
+    let mut changes: Vec<(i64, i64, String)> = Vec::new();
+    let mut seen: HashSet<Vec<(&String, &String)>> = HashSet::new();
+    let mut seen_keys: HashSet<&String> = HashSet::new();
+
+    for entry in entries.iter() {
+        let mut fields: Vec<(&String, &String)> = entry.fields.iter().collect();
+        fields.sort();
+        if !seen.insert(fields) {
+            continue;
+        }
+        if let Some(key) = entry.fields.get("idempotency_key") {
+            if !seen_keys.insert(key) {
+                continue;
+            }
+        }
+        let event_type = match entry.field("type") {
+            Some(event_type) => event_type,
+            None => continue,
+        };
+        let millis = match entry.millis() {
+            Some(millis) => millis,
+            None => continue,
+        };
+
+        match event_type.as_str() {
+            "ProfileRegistered" if entry.field("user_id").as_ref() == Some(&user_id) => {
+                changes.push((millis, 0, "Registered".to_string()));
+            },
+            "ComradeHonored" | "ComradeDishonored" if entry.field("to_user_id").as_ref() == Some(&user_id) => {
+                let amount: i64 = match entry.field("amount").and_then(|amount| amount.parse().ok()) {
+                    Some(amount) => amount,
+                    None => continue,
+                };
+                let change = if event_type == "ComradeHonored" { amount } else { -amount };
+                changes.push((millis, change, entry.field("reason").unwrap_or_default()));
+            },
+            _ => (),
+        }
+    }
+
+    changes.sort_by_key(|(millis, _change, _reason)| *millis);
+
+    let mut credit = current_credit - changes.iter().map(|(_millis, change, _reason)| change).sum::<i64>();
+    changes
+        .into_iter()
+        .map(|(millis, change, reason)| {
+            credit += change;
+            CreditPoint {
+                at: bson::DateTime::from_millis(millis).to_rfc3339_string(),
+                credit,
+                change,
+                reason,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(id: &str, fields: &[(&str, &str)]) -> StreamEntry {
+        StreamEntry {
+            id: id.to_string(),
+            fields: fields.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        }
+    }
+
+    fn ulid(millis: u64) -> String {
+        Ulid::from_parts(millis, 0).to_string()
+    }
+
+    #[test]
+    fn history() {
+        let server_event = serde_json::json!({
+            "id": ulid(3000),
+            "to_user_id": 1,
+            "by_user_id": 0,
+            "amount": 30,
+            "reason": "Won a race",
+        });
+        let entries = vec![
+            entry("1000-0", &[("id", &ulid(1000)), ("type", "ProfileRegistered"), ("user_id", "1")]),
+            entry("2000-0", &[("id", &ulid(2000)), ("type", "ComradeDishonored"), ("to_user_id", "1"), ("amount", "50"), ("reason", "Spam")]),
+            entry("2500-0", &[("id", &ulid(2500)), ("type", "ComradeHonored"), ("to_user_id", "2"), ("amount", "10")]),
+            entry("2600-0", &[("id", &ulid(2600)), ("type", "ComradeJailed"), ("to_user_id", "1")]),
+            entry("3000-0", &[("id", &ulid(3000)), ("type", "ComradeHonored"), ("event", &server_event.to_string())]),
+        ];
+
+        let history = credit_history(&entries, 1, 980);
+        let summary: Vec<(i64, i64, &str)> = history
+            .iter()
+            .map(|point| (point.credit, point.change, point.reason.as_str()))
+            .collect();
+        assert_eq!(summary, vec![(1000, 0, "Registered"), (950, -50, "Spam"), (980, 30, "Won a race")]);
+        assert_eq!(history[1].millis(), Some(2000));
+    }
+
+    #[test]
+    fn trimmed_stream() {
+        // Without the registration, the history still ends at the current credit.
+        let entries = vec![
+            entry("5000-0", &[("type", "ComradeHonored"), ("to_user_id", "1"), ("amount", "20")]),
+            entry("4000-0", &[("type", "ComradeHonored"), ("to_user_id", "1"), ("amount", "oops")]),
+        ];
+        let history = credit_history(&entries, 1, 1200);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].credit, 1200);
+        assert_eq!(history[0].millis(), Some(5000));
+
+        assert!(credit_history(&entries, 2, 1000).is_empty());
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        // As in data/db.stream, where one ID was used by two honors with different amounts.
+        let id = ulid(1000);
+        let entries = vec![
+            entry("1000-0", &[("id", &id), ("type", "ComradeHonored"), ("to_user_id", "1"), ("amount", "1")]),
+            entry("1000-1", &[("id", &id), ("type", "ComradeHonored"), ("to_user_id", "1"), ("amount", "2")]),
+        ];
+        let history = credit_history(&entries, 1, 1003);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.iter().map(|point| point.change).sum::<i64>(), 3);
+        assert_eq!(history[1].credit, 1003);
+    }
+
+    #[test]
+    fn retried_requests() {
+        // A retry is logged with a fresh ID, but the projection skips it, as its key was applied.
+        let event = |id: &str| serde_json::json!({
+            "id": id,
+            "to_user_id": 1,
+            "by_user_id": 0,
+            "amount": 5,
+            "reason": "Helpful",
+        }).to_string();
+        let first = event(&ulid(1000));
+        let retry = event(&ulid(2000));
+        let entries = vec![
+            entry("1000-0", &[("id", &ulid(1000)), ("type", "ComradeHonored"), ("event", &first), ("idempotency_key", "key")]),
+            entry("2000-0", &[("id", &ulid(2000)), ("type", "ComradeHonored"), ("event", &retry), ("idempotency_key", "key")]),
+        ];
+        let history = credit_history(&entries, 1, 1005);
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].credit, history[0].change), (1005, 5));
+    }
+
+    #[test]
+    fn dishonor_limit() {
+        assert_eq!(check_dishonor(100, 100), Ok(()));
+        assert_eq!(check_dishonor(100, 101), Err("Comrade only has 100 credit to lose".to_string()));
+        // Credit below zero, from two dishonors at once, can't lose any more.
+        assert!(check_dishonor(-5, 1).is_err());
+    }
+
+    #[test]
+    fn repeated_entries() {
+        let id = ulid(1000);
+        let fields = [("id", id.as_str()), ("type", "ComradeHonored"), ("to_user_id", "1"), ("amount", "1")];
+        let entries = vec![entry("1000-0", &fields), entry("1000-1", &fields)];
+        let history = credit_history(&entries, 1, 1001);
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].credit, history[0].change), (1001, 1));
+    }
+}
@@ -0,0 +1,480 @@
+//! Decks are the word lists which exams are made from.
+//!
+//! A deck file is JSON with a list of decks under `exams`:
+//!
+//! ```json
+//! {
+//!     "exams": [
+//!         {
+//!             "name": "hsk1",
+//!             "numQuestions": 10,
+//!             "maxWrong": 2,
+//!             "timelimit": 10,
+//!             "hskLevel": 1,
+//!             "modes": ["hanziToPinyin"],
+//!             "deck": [
+//!                 { "question": "我", "validAnswers": ["wo3"], "meaning": "I" }
+//!             ]
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! * `numQuestions` is how many cards are asked in a (non-practice) exam.
+//! * `maxWrong` is how many mistakes are allowed before failing.
+//! * `timelimit` is in seconds per question.
+//! * `hskLevel` is the level awarded for passing. Use 0 for decks which don't award one.
+//! * `modes` is optional and defaults to `["hanziToPinyin"]`. See [QuestionType] for the others.
+//! * `promptStyle` is optional and defaults to `"text"`. See [PromptStyle] for the others.
+//! * `deck` lists the cards. `validAnswers` holds the accepted pinyin.
+//!
+//! The `{"data": {"exams": [...]}}` wrapper from the original HSK exam export is also accepted.
+//! Every deck is checked with [Deck::validate] when it's loaded.
+//!
+//! Cards can also be imported and exported as CSV, TSV and Anki-style text. See [Format].
+
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+use crate::exams::{Exam, PromptStyle};
+use crate::questions::{derive_deck, Card, QuestionType};
+
+/// Where the built-in decks live.
+pub const DECKS_PATH: &str = "data/exams.json";
+
+/// A deck of cards, along with the rules for examining it. See the [module docs](self) for the format.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Deck {
+    pub name: String,
+    pub num_questions: usize,
+    pub max_wrong: usize,
+    /// The time limit for each question, in seconds.
+    pub timelimit: usize,
+    pub hsk_level: usize,
+    #[serde(default = "default_modes")]
+    pub modes: Vec<QuestionType>,
+    #[serde(default, skip_serializing_if = "PromptStyle::is_text")]
+    pub prompt_style: PromptStyle,
+    #[serde(rename = "deck")]
+    pub cards: Vec<Card>,
+}
+
+fn default_modes() -> Vec<QuestionType> {
+    vec![QuestionType::HanziToPinyin]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeckError {
+    EmptyName,
+    NoCards,
+    NoModes,
+    NoTimelimit,
+    EmptyQuestion,
+    NoValidAnswers(String),
+    DuplicateQuestion(String),
+    TooManyQuestions { num_questions: usize, num_cards: usize },
+    /// A problem with a specific deck, when loading several at once.
+    InDeck(String, Box<DeckError>),
+    /// A line in an imported file couldn't be read. Lines are numbered from 1.
+    Parse { line: usize, message: String },
+    Json(String),
+    Io(String),
+}
+
+impl fmt::Display for DeckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeckError::EmptyName => write!(f, "Deck has no name"),
+            DeckError::NoCards => write!(f, "Deck has no cards"),
+            DeckError::NoModes => write!(f, "Deck has no question modes"),
+            DeckError::NoTimelimit => write!(f, "Deck has no time limit"),
+            DeckError::EmptyQuestion => write!(f, "Card has an empty question"),
+            DeckError::NoValidAnswers(question) => write!(f, "Card has no valid answers: {}", question),
+            DeckError::DuplicateQuestion(question) => write!(f, "Card appears more than once: {}", question),
+            DeckError::TooManyQuestions { num_questions, num_cards } => {
+                write!(f, "Deck asks {} questions but only has {} cards", num_questions, num_cards)
+            },
+            DeckError::InDeck(name, error) => write!(f, "{}: {}", name, error),
+            DeckError::Parse { line, message } => write!(f, "Line {}: {}", line, message),
+            DeckError::Json(message) => write!(f, "Invalid deck JSON: {}", message),
+            DeckError::Io(message) => write!(f, "Could not read decks: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for DeckError {}
+
+impl Deck {
+    /// Checks the deck is usable:
+    ///
+    /// * It has a name, at least one card, at least one mode and a time limit.
+    /// * Every card has a question and at least one valid answer.
+    /// * No question appears twice.
+    /// * `numQuestions` is no more than the number of cards.
+    pub fn validate(&self) -> Result<(), DeckError> {
+        if self.name.trim().is_empty() {
+            return Err(DeckError::EmptyName);
+        }
+        if self.cards.is_empty() {
+            return Err(DeckError::NoCards);
+        }
+        if self.modes.is_empty() {
+            return Err(DeckError::NoModes);
+        }
+        if self.timelimit == 0 {
+            return Err(DeckError::NoTimelimit);
+        }
+
+        let mut seen: Vec<&str> = Vec::new();
+        for card in self.cards.iter() {
+            if card.hanzi.trim().is_empty() {
+                return Err(DeckError::EmptyQuestion);
+            }
+            if card.pinyin.iter().all(|answer| answer.trim().is_empty()) {
+                return Err(DeckError::NoValidAnswers(card.hanzi.clone()));
+            }
+            if seen.contains(&card.hanzi.as_str()) {
+                return Err(DeckError::DuplicateQuestion(card.hanzi.clone()));
+            }
+            seen.push(&card.hanzi);
+        }
+
+        if self.num_questions > self.cards.len() {
+            return Err(DeckError::TooManyQuestions {
+                num_questions: self.num_questions,
+                num_cards: self.cards.len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Adds imported cards. A card whose question is already in the deck replaces the one there.
+    /// When `replace` is set, the deck's existing cards are dropped first.
+    pub fn merge_cards(&mut self, cards: &[Card], replace: bool) {
+        if replace {
+            self.cards.clear();
+        }
+        for card in cards.iter() {
+            self.cards.retain(|existing| existing.hanzi != card.hanzi);
+            self.cards.push(card.clone());
+        }
+    }
+
+    /// Builds the [Exam] for this deck, deriving a question for each card in each mode.
+    pub fn to_exam(&self) -> Exam {
+        Exam {
+            name: self.name.clone(),
+            cards: self.cards.clone(),
+            modes: self.modes.clone(),
+            deck: derive_deck(&self.cards, &self.modes, 0),
+            num_questions: self.num_questions,
+            max_wrong: Some(self.max_wrong),
+            timelimit: self.timelimit * 1000, // convert from s to ms
+            hsk_level: self.hsk_level,
+            prompt_style: self.prompt_style,
+        }
+    }
+}
+
+/// Parses and validates the decks in a deck file.
+pub fn parse_decks(json: &str) -> Result<Vec<Deck>, DeckError> {
+    let file: serde_json::Value = serde_json::from_str(json).map_err(|e| DeckError::Json(e.to_string()))?;
+    let exams = match file.get("data") {
+        Some(data) => &data["exams"],
+        None => &file["exams"],
+    };
+    let decks = Vec::<Deck>::deserialize(exams).map_err(|e| DeckError::Json(e.to_string()))?;
+
+    for deck in decks.iter() {
+        deck.validate().map_err(|e| DeckError::InDeck(deck.name.clone(), Box::new(e)))?;
+    }
+
+    Ok(decks)
+}
+
+/// Writes decks out in the deck file format.
+pub fn format_decks(decks: &[Deck]) -> String {
+    serde_json::to_string_pretty(&serde_json::json!({ "exams": decks })).unwrap()
+}
+
+/// Loads the built-in decks from [DECKS_PATH].
+pub fn load_decks() -> Result<Vec<Deck>, DeckError> {
+    let json = std::fs::read_to_string(DECKS_PATH).map_err(|e| DeckError::Io(e.to_string()))?;
+    parse_decks(&json)
+}
+
+/// Loads the exams for every built-in deck.
+///
+/// Panics if the decks can't be loaded, since nothing works without them.
+pub fn load_exams() -> Vec<Exam> {
+    let decks = load_decks().unwrap_or_else(|e| panic!("Could not load {}: {}", DECKS_PATH, e));
+    decks.iter().map(|deck| deck.to_exam()).collect()
+}
+
+/// Loads the exam for the built-in deck with the given name, if there is one.
+pub fn load_exam(exam_name: &str) -> Option<Exam> {
+    load_exams().into_iter().find(|exam| exam.name == exam_name)
+}
+
+/// A plain text format for importing and exporting cards.
+///
+/// * [Format::Csv] and [Format::Tsv] have a `question`, `validAnswers`, `meaning` header row.
+///   Multiple valid answers are separated by `|`.
+/// * [Format::Anki] follows Anki's "Notes in Plain Text" export: one note per line with tab
+///   separated fields (hanzi, pinyin, meaning), and `#` lines for settings.
+///   Any fields past the third are ignored, HTML is stripped, and multiple pinyin readings may be
+///   separated by `|` or `,`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Tsv,
+    Anki,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Format, String> {
+        match name.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "anki" | "txt" => Ok(Format::Anki),
+            _ => Err(format!("Unknown format: {}. Use csv, tsv or anki.", name)),
+        }
+    }
+}
+
+const HEADER: [&str; 3] = ["question", "validAnswers", "meaning"];
+
+/// Reads cards from text in the given format. The cards are not validated.
+pub fn import_cards(text: &str, format: Format) -> Result<Vec<Card>, DeckError> {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.has_headers(false).flexible(true);
+    match format {
+        Format::Csv => (),
+        Format::Tsv => {
+            builder.delimiter(b'\t');
+        },
+        Format::Anki => {
+            builder.delimiter(b'\t').comment(Some(b'#'));
+        },
+    }
+
+    let mut cards = Vec::new();
+    for record in builder.from_reader(text.as_bytes()).records() {
+        let record = record.map_err(|e| DeckError::Parse {
+            line: e.position().map(|position| position.line() as usize).unwrap_or(0),
+            message: e.to_string(),
+        })?;
+        let line = record.position().map(|position| position.line() as usize).unwrap_or(0);
+
+        let fields: Vec<String> = match format {
+            Format::Csv | Format::Tsv => record.iter().map(|field| field.trim().to_string()).collect(),
+            Format::Anki => record.iter().map(strip_html).collect(),
+        };
+
+        if fields.iter().all(|field| field.is_empty()) {
+            continue;
+        }
+        if format != Format::Anki && fields.iter().map(String::as_str).eq(HEADER.iter().copied()) {
+            continue;
+        }
+        if fields.len() < 2 {
+            return Err(DeckError::Parse {
+                line,
+                message: "Expected a question and its valid answers".to_string(),
+            });
+        }
+
+        let separators: &[char] = match format {
+            Format::Csv | Format::Tsv => &['|'],
+            Format::Anki => &['|', ','],
+        };
+        let valid_answers = fields[1]
+            .split(separators)
+            .map(|answer| answer.trim().to_string())
+            .filter(|answer| !answer.is_empty())
+            .collect();
+
+        cards.push(Card {
+            hanzi: fields[0].clone(),
+            pinyin: valid_answers,
+            meaning: fields.get(2).cloned().unwrap_or_default(),
+        });
+    }
+
+    Ok(cards)
+}
+
+/// Writes cards out as text in the given format.
+pub fn export_cards(cards: &[Card], format: Format) -> String {
+    let mut builder = csv::WriterBuilder::new();
+    builder.flexible(true);
+    if format != Format::Csv {
+        builder.delimiter(b'\t');
+    }
+
+    let mut writer = builder.from_writer(vec![]);
+    if format == Format::Anki {
+        writer.write_record(["#separator:tab"]).unwrap();
+        writer.write_record(["#html:false"]).unwrap();
+    } else {
+        writer.write_record(HEADER).unwrap();
+    }
+
+    for card in cards.iter() {
+        let valid_answers = match format {
+            Format::Csv | Format::Tsv => card.pinyin.join("|"),
+            Format::Anki => card.pinyin.join(", "),
+        };
+        writer.write_record([&card.hanzi, &valid_answers, &card.meaning]).unwrap();
+    }
+
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+fn strip_html(field: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for ch in field.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(ch),
+            _ => (),
+        }
+    }
+
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card(hanzi: &str, pinyin: &[&str], meaning: &str) -> Card {
+        Card {
+            hanzi: hanzi.to_string(),
+            pinyin: pinyin.iter().map(|answer| answer.to_string()).collect(),
+            meaning: meaning.to_string(),
+        }
+    }
+
+    fn deck() -> Deck {
+        Deck {
+            name: "test".to_string(),
+            num_questions: 2,
+            max_wrong: 0,
+            timelimit: 10,
+            hsk_level: 0,
+            modes: default_modes(),
+            prompt_style: PromptStyle::Text,
+            cards: vec![
+                card("我", &["wo3"], "I"),
+                card("好", &["hao3", "hao4"], "good, well"),
+            ],
+        }
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(deck().validate(), Ok(()));
+
+        let mut empty_answers = deck();
+        empty_answers.cards[1].pinyin.clear();
+        assert_eq!(empty_answers.validate(), Err(DeckError::NoValidAnswers("好".to_string())));
+
+        let mut too_many = deck();
+        too_many.num_questions = 3;
+        assert_eq!(too_many.validate(), Err(DeckError::TooManyQuestions { num_questions: 3, num_cards: 2 }));
+
+        let mut duplicate = deck();
+        duplicate.cards.push(card("我", &["wo3"], "me"));
+        assert_eq!(duplicate.validate(), Err(DeckError::DuplicateQuestion("我".to_string())));
+
+        let mut unnamed = deck();
+        unnamed.name = " ".to_string();
+        assert_eq!(unnamed.validate(), Err(DeckError::EmptyName));
+    }
+
+    #[test]
+    fn parse_both_wrappers() {
+        let json = format_decks(&[deck()]);
+        assert_eq!(parse_decks(&json), Ok(vec![deck()]));
+
+        let wrapped = format!(r#"{{"data": {}}}"#, json);
+        assert_eq!(parse_decks(&wrapped), Ok(vec![deck()]));
+
+        let mut invalid = deck();
+        invalid.num_questions = 5;
+        let error = parse_decks(&format_decks(&[invalid])).unwrap_err();
+        assert!(matches!(error, DeckError::InDeck(name, _) if name == "test"));
+    }
+
+    #[test]
+    fn modes_default_to_pinyin() {
+        let json = r#"{"exams": [{"name": "x", "numQuestions": 1, "maxWrong": 0, "timelimit": 5,
+            "hskLevel": 0, "deck": [{"question": "我", "validAnswers": ["wo3"], "meaning": "I"}]}]}"#;
+        let decks = parse_decks(json).unwrap();
+        assert_eq!(decks[0].modes, vec![QuestionType::HanziToPinyin]);
+        assert_eq!(decks[0].to_exam().timelimit, 5000);
+    }
+
+    #[test]
+    fn csv_and_tsv_round_trip() {
+        let cards = deck().cards;
+        for format in [Format::Csv, Format::Tsv] {
+            let text = export_cards(&cards, format);
+            assert_eq!(import_cards(&text, format), Ok(cards.clone()));
+        }
+
+        let text = export_cards(&cards, Format::Csv);
+        assert!(text.starts_with("question,validAnswers,meaning\n"));
+        assert!(text.contains("好,hao3|hao4,\"good, well\"\n"));
+    }
+
+    #[test]
+    fn anki_import() {
+        let text = "#separator:tab\n#html:true\n我\t<b>wǒ</b>\tI\textra\n\n好\thǎo, hào\tgood&nbsp;\n";
+        let cards = import_cards(text, Format::Anki).unwrap();
+        assert_eq!(cards, vec![card("我", &["wǒ"], "I"), card("好", &["hǎo", "hào"], "good")]);
+
+        let exported = export_cards(&cards, Format::Anki);
+        assert_eq!(import_cards(&exported, Format::Anki), Ok(cards));
+    }
+
+    #[test]
+    fn import_errors() {
+        let error = import_cards("question,validAnswers,meaning\n我\n", Format::Csv).unwrap_err();
+        assert!(matches!(error, DeckError::Parse { line: 2, .. }));
+    }
+
+    #[test]
+    fn builtin_decks_are_valid() {
+        let decks = load_decks().unwrap();
+        assert!(decks.iter().any(|deck| deck.name == "hsk1"));
+    }
+
+    #[test]
+    fn merge_cards() {
+        let imported = vec![card("好", &["hao3"], "good"), card("你", &["ni3"], "you")];
+
+        // An imported card replaces the one with the same question, and new ones go on the end.
+        let mut merged = deck();
+        merged.merge_cards(&imported, false);
+        assert_eq!(merged.cards, vec![card("我", &["wo3"], "I"), imported[0].clone(), imported[1].clone()]);
+        assert_eq!(merged.validate(), Ok(()));
+
+        let mut replaced = deck();
+        replaced.merge_cards(&imported, true);
+        assert_eq!(replaced.cards, imported);
+    }
+}
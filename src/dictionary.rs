@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use crate::exams::Exam;
+use crate::hanzi::is_hanzi;
+
+/// A single word in the [Dictionary].
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub word: String,
+    pub pinyin: Vec<String>,
+    pub meaning: String,
+}
+
+/// A small Chinese-English dictionary, built from the words in the exam decks.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    entries: HashMap<String, Entry>,
+    longest_word: usize,
+}
+
+impl Dictionary {
+    /// Builds a dictionary from every card in `exams`.
+    ///
+    /// When a word appears in more than one deck, the first one wins.
+    pub fn from_exams(exams: &[Exam]) -> Dictionary {
+        let mut dictionary = Dictionary::default();
+
+        for exam in exams.iter() {
+            for card in exam.cards.iter() {
+                if dictionary.entries.contains_key(&card.hanzi) {
+                    continue;
+                }
+
+                let entry = Entry {
+                    word: card.hanzi.clone(),
+                    pinyin: card.pinyin.clone(),
+                    meaning: card.meaning.clone(),
+                };
+                dictionary.longest_word = dictionary.longest_word.max(entry.word.chars().count());
+                dictionary.entries.insert(entry.word.clone(), entry);
+            }
+        }
+
+        dictionary
+    }
+
+    pub fn lookup(&self, word: &str) -> Option<&Entry> {
+        self.entries.get(word)
+    }
+
+    /// Splits the hanzi in `text` into words.
+    ///
+    /// This uses greedy longest-match against the dictionary.
+    /// Hanzi which don't start any known word are returned as single-character words.
+    /// Anything which isn't a hanzi is skipped.
+    pub fn segment(&self, text: &str) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut words = Vec::new();
+        let mut idx = 0;
+
+        while idx < chars.len() {
+            if !is_hanzi(chars[idx]) {
+                idx += 1;
+                continue;
+            }
+
+            let mut len = self.longest_word.min(chars.len() - idx);
+            while len > 1 {
+                let candidate: String = chars[idx..idx + len].iter().collect();
+                if self.entries.contains_key(&candidate) {
+                    break;
+                }
+                len -= 1;
+            }
+
+            words.push(chars[idx..idx + len.max(1)].iter().collect());
+            idx += len.max(1);
+        }
+
+        words
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::exams::PromptStyle;
+    use crate::questions::{derive_deck, Card, QuestionType};
+
+    fn dictionary() -> Dictionary {
+        let card = |hanzi: &str, pinyin: &str| Card {
+            hanzi: hanzi.to_string(),
+            pinyin: vec![pinyin.to_string()],
+            meaning: String::new(),
+        };
+
+        let cards = vec![card("中国", "zhong1guo2"), card("中国人", "zhong1guo2ren2"), card("好", "hao3")];
+        let modes = vec![QuestionType::HanziToPinyin];
+        let exam = Exam {
+            name: "test".to_string(),
+            deck: derive_deck(&cards, &modes, 0),
+            cards,
+            modes,
+            num_questions: 1,
+            max_wrong: None,
+            timelimit: 1000,
+            hsk_level: 1,
+            prompt_style: PromptStyle::Text,
+        };
+        Dictionary::from_exams(&[exam])
+    }
+
+    #[test]
+    fn segment_longest_match() {
+        let dictionary = dictionary();
+        assert_eq!(dictionary.segment("我是中国人。"), vec!["我", "是", "中国人"]);
+        assert_eq!(dictionary.segment("中国很好!"), vec!["中国", "很", "好"]);
+        assert_eq!(dictionary.segment("hello"), Vec::<String>::new());
+    }
+}
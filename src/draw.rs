@@ -1,63 +1,1115 @@
-use image::{DynamicImage, Rgba};
-use rusttype::{point, Font, Scale};
-
-pub fn load_font(font_name: &str) -> Font {
-    let path = format!("data/{}", font_name);
-    let font_data = std::fs::read(path).unwrap();
-    let font = Font::try_from_vec(font_data).unwrap();
-    font
-}
-
-pub fn draw(text: &str) {
-    let font = load_font("ZCOOL_KuaiLe.ttf");
-
-    // The font size to use
-    let scale = Scale::uniform(128.0);
-
-    // Use a dark red colour
-    let colour = (255, 0, 0);
-
-    let v_metrics = font.v_metrics(scale);
-
-    // layout the glyphs in a line with 20 pixels padding
-    let glyphs: Vec<_> = font
-        .layout(&text, scale, point(0.0, v_metrics.ascent))
-        .collect();
-
-    // work out the layout size
-    let glyphs_height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
-    let glyphs_width = {
-        let min_x = glyphs
-            .first()
-            .map(|g| g.pixel_bounding_box().unwrap().min.x)
-            .unwrap();
-        let max_x = glyphs
-            .last()
-            .map(|g| g.pixel_bounding_box().unwrap().max.x)
-            .unwrap();
-        (max_x - min_x) as u32
+use std::fmt;
+use std::path::Path;
+
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rusttype::{point, Font, Glyph, GlyphId, Point, PositionedGlyph, Scale};
+
+use crate::dictionary::Dictionary;
+use crate::hanzi::is_hanzi;
+use crate::pinyin;
+
+/// How to render a piece of text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// The name of a font in the [FontRegistry]. Characters it lacks are drawn in the other fonts.
+    pub font: String,
+    /// The font size, in pixels.
+    pub size: f32,
+    pub foreground: Rgba<u8>,
+    pub background: Rgba<u8>,
+    /// Space around the text on every side, in pixels.
+    pub padding: u32,
+    pub direction: Direction,
+    pub align: Align,
+    /// The longest a line may be before it wraps, in pixels: its width in horizontal text,
+    /// or its height in vertical text.
+    pub wrap: Option<u32>,
+    /// The distance between lines, or between columns in vertical text, as a multiple of the font's line height.
+    pub line_spacing: f32,
+    pub format: OutputFormat,
+}
+
+impl Default for RenderOptions {
+    /// Red text at 128px on a transparent background, in a single left-aligned line, as a PNG.
+    fn default() -> RenderOptions {
+        RenderOptions {
+            font: DEFAULT_FONT.to_string(),
+            size: 128.0,
+            foreground: Rgba([255, 0, 0, 255]),
+            background: Rgba([0, 0, 0, 0]),
+            padding: 5,
+            direction: Direction::Horizontal,
+            align: Align::Start,
+            wrap: None,
+            line_spacing: 1.0,
+            format: OutputFormat::Png,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Lines run left to right, from the top down.
+    Horizontal,
+    /// Columns run top to bottom, from the right, as in traditional Chinese.
+    Vertical,
+}
+
+/// How lines shorter than the longest are placed.
+/// In vertical text, [Align::Start] is the top and [Align::End] is the bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+impl Align {
+    /// How far to move a line which is `slack` pixels shorter than the longest.
+    fn offset(&self, slack: f32) -> f32 {
+        match self {
+            Align::Start => 0.0,
+            Align::Center => slack / 2.0,
+            Align::End => slack,
+        }
+    }
+}
+
+/// Where fonts are loaded from.
+pub const FONT_DIR: &str = "data";
+
+pub const DEFAULT_FONT: &str = "zcool_kuaile";
+
+/// The most pixels an image may have. Anything bigger is too big to upload to Discord.
+pub const MAX_PIXELS: u64 = 8_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    /// With a quality from 1 to 100. JPEGs have no transparency, so transparent pixels are drawn over white.
+    Jpeg(u8),
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg(_) => "jpg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawError {
+    /// There was nothing visible to draw.
+    EmptyText,
+    FontNotFound(String),
+    InvalidFont(String),
+    NoFonts(String),
+    Io(String),
+    InvalidSize(f32),
+    InvalidLineSpacing(f32),
+    /// The image would be more than [MAX_PIXELS], at this width and height.
+    TooLarge(u32, u32),
+    Encode(String),
+}
+
+impl fmt::Display for DrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DrawError::EmptyText => write!(f, "Nothing to draw"),
+            DrawError::FontNotFound(name) => write!(f, "Font not found: {}", name),
+            DrawError::InvalidFont(name) => write!(f, "Could not load font: {}", name),
+            DrawError::NoFonts(dir) => write!(f, "No fonts in {}", dir),
+            DrawError::Io(message) => write!(f, "Could not read fonts: {}", message),
+            DrawError::InvalidSize(size) => write!(f, "Invalid font size: {}", size),
+            DrawError::InvalidLineSpacing(spacing) => write!(f, "Invalid line spacing: {}", spacing),
+            DrawError::TooLarge(width, height) => write!(f, "Too much to draw: the image would be {}×{}", width, height),
+            DrawError::Encode(message) => write!(f, "Could not encode image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for DrawError {}
+
+/// Every font in a directory, loaded once and kept in memory.
+///
+/// Fonts are named after their file, in lowercase and without the extension, so
+/// `ZCOOL_KuaiLe.ttf` is `zcool_kuaile`. When a font lacks a character, the others are tried in
+/// turn: [DEFAULT_FONT] first, then the rest by name.
+pub struct FontRegistry {
+    fonts: Vec<(String, Font<'static>)>,
+}
+
+impl FontRegistry {
+    /// Loads every `.ttf` and `.otf` file in `dir`.
+    pub fn load(dir: impl AsRef<Path>) -> Result<FontRegistry, DrawError> {
+        let dir = dir.as_ref();
+        let mut fonts = Vec::new();
+
+        for entry in std::fs::read_dir(dir).map_err(|e| DrawError::Io(e.to_string()))? {
+            let path = entry.map_err(|e| DrawError::Io(e.to_string()))?.path();
+            let extension = path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase());
+            if !matches!(extension.as_deref(), Some("ttf") | Some("otf")) {
+                continue;
+            }
+
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) => stem.to_lowercase(),
+                None => continue,
+            };
+            let font_data = std::fs::read(&path).map_err(|e| DrawError::Io(e.to_string()))?;
+            let font = Font::try_from_vec(font_data).ok_or_else(|| DrawError::InvalidFont(name.clone()))?;
+            fonts.push((name, font));
+        }
+
+        if fonts.is_empty() {
+            return Err(DrawError::NoFonts(dir.display().to_string()));
+        }
+        fonts.sort_by(|(a, _), (b, _)| (a != DEFAULT_FONT, a).cmp(&(b != DEFAULT_FONT, b)));
+        Ok(FontRegistry { fonts })
+    }
+
+    /// The names of the fonts, in the order they're tried.
+    pub fn names(&self) -> Vec<&str> {
+        self.fonts.iter().map(|(name, _font)| name.as_str()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Font<'static>> {
+        let name = name.to_lowercase();
+        self.fonts.iter().find(|(font_name, _font)| *font_name == name).map(|(_name, font)| font)
+    }
+
+    /// The named font, followed by every other font to fall back on.
+    fn chain(&self, name: &str) -> Result<FontChain<'_>, DrawError> {
+        let primary = self.get(name).ok_or_else(|| DrawError::FontNotFound(name.to_string()))?;
+        let mut fonts = vec![primary];
+        for (_name, font) in self.fonts.iter() {
+            if !std::ptr::eq(font, primary) {
+                fonts.push(font);
+            }
+        }
+        Ok(FontChain { fonts })
+    }
+
+    /// Draws a piece of text and encodes it in the format given by the options.
+    pub fn draw(&self, text: &str, options: &RenderOptions) -> Result<Vec<u8>, DrawError> {
+        let image = self.render(text, options)?;
+        encode(&image, options.format)
+    }
+
+    /// Draws a piece of text.
+    ///
+    /// Each `\n` starts a new line, and lines longer than [RenderOptions::wrap] are wrapped.
+    /// Leading and trailing spaces take up room like any other character.
+    pub fn render(&self, text: &str, options: &RenderOptions) -> Result<RgbaImage, DrawError> {
+        validate(text, options)?;
+        let fonts = self.chain(&options.font)?;
+        let layout = match options.direction {
+            Direction::Horizontal => layout_horizontal(&fonts, text, options),
+            Direction::Vertical => layout_vertical(&fonts, text, options),
+        };
+        rasterize(layout, options)
+    }
+
+    /// Draws text with each [Ruby] annotation centered above its base, like pinyin over hanzi.
+    ///
+    /// Lines break and wrap as in [FontRegistry::render], except that an annotated run is never split.
+    /// Ruby text is always laid out horizontally.
+    pub fn render_ruby(&self, ruby: &[Ruby], options: &RenderOptions) -> Result<RgbaImage, DrawError> {
+        validate(ruby.iter().map(|ruby| ruby.base.as_str()).collect::<String>().as_str(), options)?;
+        let fonts = self.chain(&options.font)?;
+        rasterize(layout_ruby(&fonts, ruby, options), options)
+    }
+
+    /// Draws text with ruby annotations and encodes it in the format given by the options.
+    pub fn draw_ruby(&self, ruby: &[Ruby], options: &RenderOptions) -> Result<Vec<u8>, DrawError> {
+        let image = self.render_ruby(ruby, options)?;
+        encode(&image, options.format)
+    }
+}
+
+fn validate(text: &str, options: &RenderOptions) -> Result<(), DrawError> {
+    if text.trim().is_empty() {
+        return Err(DrawError::EmptyText);
+    }
+    if !(options.size.is_finite() && options.size > 0.0) {
+        return Err(DrawError::InvalidSize(options.size));
+    }
+    if !(options.line_spacing.is_finite() && options.line_spacing > 0.0) {
+        return Err(DrawError::InvalidLineSpacing(options.line_spacing));
+    }
+    Ok(())
+}
+
+/// Fonts in order of preference. Each character is drawn in the first font which has it.
+struct FontChain<'a> {
+    fonts: Vec<&'a Font<'static>>,
+}
+
+impl FontChain<'_> {
+    /// The metrics of the first font decide the line height and baseline for all of them.
+    fn primary(&self) -> &Font<'static> {
+        self.fonts[0]
+    }
+
+    /// The glyph for `ch`, along with the place in the chain of the font it came from.
+    /// If no font has the character, the first font's placeholder glyph is used.
+    fn glyph(&self, ch: char) -> (usize, Glyph<'static>) {
+        for (idx, font) in self.fonts.iter().enumerate() {
+            let glyph = font.glyph(ch);
+            if glyph.id().0 != 0 {
+                return (idx, glyph);
+            }
+        }
+        (0, self.primary().glyph(ch))
+    }
+
+    /// Whether any of the fonts can draw `ch`.
+    fn has(&self, ch: char) -> bool {
+        self.fonts.iter().any(|font| font.glyph(ch).id().0 != 0)
+    }
+
+    /// Lays out a single line starting at `origin`, and returns its glyphs and its width.
+    fn layout_line(&self, line: &str, scale: Scale, origin: Point<f32>) -> (Vec<PositionedGlyph<'static>>, f32) {
+        let mut glyphs = Vec::new();
+        let mut x = origin.x;
+        let mut previous: Option<(usize, GlyphId)> = None;
+
+        for ch in line.chars().filter(|ch| !ch.is_control()) {
+            let (idx, glyph) = self.glyph(ch);
+            let id = glyph.id();
+            // Kerning only makes sense between glyphs of the same font.
+            if let Some((previous_idx, previous_id)) = previous {
+                if previous_idx == idx {
+                    x += self.fonts[idx].pair_kerning(scale, previous_id, id);
+                }
+            }
+
+            let glyph = glyph.scaled(scale);
+            let advance = glyph.h_metrics().advance_width;
+            glyphs.push(glyph.positioned(point(x, origin.y)));
+            x += advance;
+            previous = Some((idx, id));
+        }
+
+        (glyphs, x - origin.x)
+    }
+}
+
+/// Glyphs in their places, along with the size of the block of text they make up.
+struct Layout {
+    glyphs: Vec<PositionedGlyph<'static>>,
+    width: f32,
+    height: f32,
+}
+
+fn layout_horizontal(fonts: &FontChain, text: &str, options: &RenderOptions) -> Layout {
+    let scale = Scale::uniform(options.size);
+    let v_metrics = fonts.primary().v_metrics(scale);
+    let glyphs_height = v_metrics.ascent - v_metrics.descent;
+    let line_height = (glyphs_height + v_metrics.line_gap) * options.line_spacing;
+
+    let line_width = |line: &str| fonts.layout_line(line, scale, point(0.0, 0.0)).1;
+
+    let lines = break_lines(text, options.wrap, line_width);
+    let widths: Vec<f32> = lines.iter().map(|line| line_width(line)).collect();
+    let width = widths.iter().copied().fold(0.0, f32::max);
+    let height = line_height * (lines.len() - 1) as f32 + glyphs_height;
+
+    let mut glyphs = Vec::new();
+    for (idx, line) in lines.iter().enumerate() {
+        let x = options.align.offset(width - widths[idx]);
+        let y = line_height * idx as f32 + v_metrics.ascent;
+        glyphs.extend(fonts.layout_line(line, scale, point(x, y)).0);
+    }
+
+    Layout { glyphs, width, height }
+}
+
+/// Lays the text out in columns, top to bottom, starting from the right.
+///
+/// Every character is set upright in a square cell, the way Chinese is written vertically.
+/// Latin text is set upright too, one letter per cell, rather than turned on its side.
+fn layout_vertical(fonts: &FontChain, text: &str, options: &RenderOptions) -> Layout {
+    let scale = Scale::uniform(options.size);
+    let v_metrics = fonts.primary().v_metrics(scale);
+    let cell = options.size;
+    let column_width = (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap) * options.line_spacing;
+    // Centers the font's ascent and descent within the cell.
+    let baseline = v_metrics.ascent - (v_metrics.ascent - v_metrics.descent - cell) / 2.0;
+
+    let columns = break_lines(text, options.wrap, |column| column.chars().count() as f32 * cell);
+    let heights: Vec<f32> = columns.iter().map(|column| column.chars().count() as f32 * cell).collect();
+    let width = column_width * (columns.len() - 1) as f32 + cell;
+    let height = heights.iter().copied().fold(0.0, f32::max);
+
+    let mut glyphs = Vec::new();
+    for (idx, column) in columns.iter().enumerate() {
+        let x = column_width * (columns.len() - 1 - idx) as f32;
+        let top = options.align.offset(height - heights[idx]);
+        for (row, ch) in column.chars().enumerate() {
+            let glyph = fonts.glyph(ch).1.scaled(scale);
+            let advance = glyph.h_metrics().advance_width;
+            let position = point(x + (cell - advance) / 2.0, top + cell * row as f32 + baseline);
+            glyphs.push(glyph.positioned(position));
+        }
+    }
+
+    Layout { glyphs, width, height }
+}
+
+/// A run of text with an annotation to draw above it. Text without an annotation has an empty one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ruby {
+    pub base: String,
+    pub annotation: String,
+}
+
+impl Ruby {
+    fn new(base: &str, annotation: &str) -> Ruby {
+        Ruby {
+            base: base.to_string(),
+            annotation: annotation.to_string(),
+        }
+    }
+}
+
+/// The size of ruby annotations, relative to the text they annotate.
+const RUBY_SCALE: f32 = 0.4;
+
+/// Pairs the hanzi in `text` with their pinyin, in tone marks.
+///
+/// Pinyin may be given in brackets straight after the hanzi it belongs to, as in `你好[ni3 hao3]`.
+/// Other hanzi are looked up in the dictionary, and left bare if it doesn't have them.
+pub fn ruby(text: &str, dictionary: &Dictionary) -> Vec<Ruby> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result: Vec<Ruby> = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let start = idx;
+        if !is_hanzi(chars[idx]) {
+            while idx < chars.len() && !is_hanzi(chars[idx]) {
+                idx += 1;
+            }
+            let text: String = chars[start..idx].iter().collect();
+            result.push(Ruby::new(&text, ""));
+            continue;
+        }
+
+        while idx < chars.len() && is_hanzi(chars[idx]) {
+            idx += 1;
+        }
+        let hanzi: String = chars[start..idx].iter().collect();
+
+        let explicit = match chars.get(idx) {
+            Some('[') => chars[idx..].iter().position(|ch| *ch == ']').map(|len| idx + len),
+            _ => None,
+        };
+        if let Some(end) = explicit {
+            let pinyin: String = chars[idx + 1..end].iter().collect();
+            result.extend(annotate(&hanzi, &pinyin));
+            idx = end + 1;
+            continue;
+        }
+
+        for word in dictionary.segment(&hanzi) {
+            match dictionary.lookup(&word).and_then(|entry| entry.pinyin.first()) {
+                Some(pinyin) => result.extend(annotate(&word, pinyin)),
+                None => result.push(Ruby::new(&word, "")),
+            }
+        }
+    }
+
+    result
+}
+
+/// Pairs each hanzi in a word with its syllable of the pinyin.
+///
+/// When the syllables don't line up with the hanzi, as with erhua, the whole word shares the pinyin.
+pub fn annotate(hanzi: &str, pinyin: &str) -> Vec<Ruby> {
+    let syllables = pinyin::to_syllables(pinyin).unwrap_or_default();
+    if syllables.len() != hanzi.chars().count() {
+        return vec![Ruby::new(hanzi, &pinyin::to_tone_marks(pinyin))];
+    }
+
+    hanzi
+        .chars()
+        .zip(syllables.iter())
+        .map(|(ch, syllable)| {
+            let erhua = if syllable.erhua { " r5" } else { "" };
+            let marks = pinyin::to_tone_marks(&format!("{}{}{}", syllable.letters, syllable.tone, erhua));
+            Ruby::new(&ch.to_string(), &marks)
+        })
+        .collect()
+}
+
+fn layout_ruby(fonts: &FontChain, ruby: &[Ruby], options: &RenderOptions) -> Layout {
+    let scale = Scale::uniform(options.size);
+    let ruby_scale = Scale::uniform(options.size * RUBY_SCALE);
+    let v_metrics = fonts.primary().v_metrics(scale);
+    let ruby_metrics = fonts.primary().v_metrics(ruby_scale);
+    let ruby_height = ruby_metrics.ascent - ruby_metrics.descent;
+    let glyphs_height = ruby_height + v_metrics.ascent - v_metrics.descent;
+    let line_height = (glyphs_height + v_metrics.line_gap) * options.line_spacing;
+    // Keeps neighbouring annotations from running together.
+    let gap = options.size * 0.1;
+
+    // Unannotated text is split wherever it may wrap, and at newlines.
+    let mut lines: Vec<Vec<Ruby>> = vec![vec![]];
+    for ruby in ruby.iter() {
+        if !ruby.annotation.is_empty() {
+            // Many fonts for hanzi have no tone marks. Tone numbers are better than blanks.
+            let annotation = if ruby.annotation.chars().all(|ch| fonts.has(ch)) {
+                ruby.annotation.clone()
+            } else {
+                pinyin::to_tone_numbers(&ruby.annotation)
+            };
+            lines.last_mut().unwrap().push(Ruby::new(&ruby.base, &annotation));
+            continue;
+        }
+        for (idx, line) in ruby.base.split('\n').enumerate() {
+            if idx > 0 {
+                lines.push(vec![]);
+            }
+            let line = line.trim_end_matches('\r');
+            lines.last_mut().unwrap().extend(segments(line).iter().map(|segment| Ruby::new(segment, "")));
+        }
+    }
+
+    let cell_width = |ruby: &Ruby| {
+        let base = fonts.layout_line(&ruby.base, scale, point(0.0, 0.0)).1;
+        if ruby.annotation.is_empty() {
+            return base;
+        }
+        let annotation = fonts.layout_line(&ruby.annotation, ruby_scale, point(0.0, 0.0)).1;
+        base.max(annotation + gap)
     };
 
-    // Create a new rgba image with some padding
-    let mut image = DynamicImage::new_rgba8(glyphs_width + 10, glyphs_height + 10).to_rgba8();
+    let mut wrapped: Vec<Vec<(Ruby, f32)>> = Vec::new();
+    for line in lines {
+        let mut current: Vec<(Ruby, f32)> = Vec::new();
+        let mut current_width = 0.0;
+        for ruby in line {
+            let width = cell_width(&ruby);
+            let breakable = match (current.last(), options.wrap) {
+                (Some((previous, _)), Some(max)) => {
+                    let previous_char = previous.base.chars().last().unwrap_or(' ');
+                    let next_char = ruby.base.chars().next().unwrap_or(' ');
+                    current_width + width > max as f32 && can_break(previous_char, next_char)
+                },
+                _ => false,
+            };
+            if breakable {
+                wrapped.push(std::mem::take(&mut current));
+                current_width = 0.0;
+            }
+            current_width += width;
+            current.push((ruby, width));
+        }
+        wrapped.push(current);
+    }
+
+    let widths: Vec<f32> = wrapped.iter().map(|line| line.iter().map(|(_ruby, width)| width).sum()).collect();
+    let width = widths.iter().copied().fold(0.0, f32::max);
+    let height = line_height * (wrapped.len() - 1) as f32 + glyphs_height;
 
-    // Loop through the glyphs in the text, positing each one on a line
-    for glyph in glyphs {
+    let mut glyphs = Vec::new();
+    for (idx, line) in wrapped.iter().enumerate() {
+        let top = line_height * idx as f32;
+        let mut x = options.align.offset(width - widths[idx]);
+        for (ruby, cell) in line.iter() {
+            // Each run is measured first, so it can be centered in its cell.
+            let base_width = fonts.layout_line(&ruby.base, scale, point(0.0, 0.0)).1;
+            let origin = point(x + (cell - base_width) / 2.0, top + ruby_height + v_metrics.ascent);
+            glyphs.extend(fonts.layout_line(&ruby.base, scale, origin).0);
+
+            let annotation_width = fonts.layout_line(&ruby.annotation, ruby_scale, point(0.0, 0.0)).1;
+            let origin = point(x + (cell - annotation_width) / 2.0, top + ruby_metrics.ascent);
+            glyphs.extend(fonts.layout_line(&ruby.annotation, ruby_scale, origin).0);
+            x += cell;
+        }
+    }
+
+    Layout { glyphs, width, height }
+}
+
+fn rasterize(layout: Layout, options: &RenderOptions) -> Result<RgbaImage, DrawError> {
+    // work out the image size. Spaces have no bounding box, so the layout counts instead,
+    // and glyphs may reach a little past the layout on any side.
+    let boxes: Vec<_> = layout.glyphs.iter().filter_map(|g| g.pixel_bounding_box()).collect();
+    let min_x = boxes.iter().map(|b| b.min.x).min().unwrap_or(0).min(0);
+    let max_x = boxes.iter().map(|b| b.max.x).max().unwrap_or(0).max(layout.width.ceil() as i32);
+    let min_y = boxes.iter().map(|b| b.min.y).min().unwrap_or(0).min(0);
+    let max_y = boxes.iter().map(|b| b.max.y).max().unwrap_or(0).max(layout.height.ceil() as i32);
+
+    let padding = options.padding as i32;
+    let width = (max_x - min_x + 2 * padding) as u32;
+    let height = (max_y - min_y + 2 * padding) as u32;
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return Err(DrawError::TooLarge(width, height));
+    }
+    let mut image = RgbaImage::from_pixel(width, height, options.background);
+
+    // Loop through the glyphs in the text, positing each one in its place
+    for glyph in layout.glyphs {
         if let Some(bounding_box) = glyph.pixel_bounding_box() {
             // Draw the glyph into the image per-pixel by using the draw closure
             glyph.draw(|x, y, v| {
-                image.put_pixel(
-                    // Offset the position by the glyph bounding box
-                    x + bounding_box.min.x as u32,
-                    y + bounding_box.min.y as u32,
-                    // Turn the coverage into an alpha value
-                    Rgba([colour.0, colour.1, colour.2, (v * 255.0) as u8]),
-                )
+                // Offset the position by the glyph bounding box
+                let x = (x as i32 + bounding_box.min.x - min_x + padding) as u32;
+                let y = (y as i32 + bounding_box.min.y - min_y + padding) as u32;
+                if x < width && y < height {
+                    let pixel = image.get_pixel_mut(x, y);
+                    *pixel = blend(*pixel, options.foreground, v);
+                }
             });
         }
     }
 
-    // Save the image to a png file
-    image.save("out.png").unwrap();
-    println!("Generated: out.png");
+    Ok(image)
+}
+
+/// Splits the text into lines at each newline, then wraps any line which `measure`s longer than `max`.
+fn break_lines(text: &str, max: Option<u32>, measure: impl Fn(&str) -> f32) -> Vec<String> {
+    text.split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .flat_map(|line| match max {
+            Some(max) => wrap(&segments(line), max as f32, &measure),
+            None => vec![line.to_string()],
+        })
+        .collect()
+}
+
+/// Fills lines with as many segments as fit. Spaces at the end of a wrapped line are dropped.
+///
+/// A segment too long to fit on a line by itself is broken between characters.
+fn wrap(segments: &[&str], max: f32, measure: impl Fn(&str) -> f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for segment in segments {
+        let candidate = format!("{}{}", line, segment);
+        if !line.is_empty() && measure(candidate.trim_end()) > max {
+            lines.push(line.trim_end().to_string());
+            line = segment.to_string();
+        } else {
+            line = candidate;
+        }
+
+        while measure(line.trim_end()) > max && line.trim_end().chars().count() > 1 {
+            let chars: Vec<char> = line.chars().collect();
+            let mut fits = 1;
+            while fits < chars.len() && measure(&chars[..=fits].iter().collect::<String>()) <= max {
+                fits += 1;
+            }
+            lines.push(chars[..fits].iter().collect());
+            line = chars[fits..].iter().collect();
+        }
+    }
+
+    lines.push(line);
+    lines
+}
+
+/// Splits a line into the pieces a wrapped line may break between.
+///
+/// Chinese may break between any two characters, and other text only after a space. Either way,
+/// closing punctuation stays with the character before it, and opening punctuation with the
+/// character after it, so that no line starts with `，` or ends with `「`.
+fn segments(line: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut previous: Option<char> = None;
+
+    for (idx, ch) in line.char_indices() {
+        if let Some(previous) = previous {
+            if can_break(previous, ch) {
+                segments.push(&line[start..idx]);
+                start = idx;
+            }
+        }
+        previous = Some(ch);
+    }
+
+    if start < line.len() {
+        segments.push(&line[start..]);
+    }
+    segments
+}
+
+fn can_break(previous: char, next: char) -> bool {
+    if next.is_whitespace() || is_closing(next) || is_opening(previous) {
+        return false;
+    }
+    previous.is_whitespace() || is_cjk(previous) || is_cjk(next)
+}
+
+fn is_cjk(ch: char) -> bool {
+    matches!(ch as u32,
+        0x2E80..=0x2FDF     // Radicals
+        | 0x3000..=0x30FF   // Punctuation, hiragana and katakana
+        | 0x3100..=0x312F   // Bopomofo
+        | 0x3400..=0x4DBF   // Extension A
+        | 0x4E00..=0x9FFF   // Unified ideographs
+        | 0xF900..=0xFAFF   // Compatibility ideographs
+        | 0xFF00..=0xFFEF   // Fullwidth forms
+        | 0x20000..=0x2FA1F // Extensions B and on
+    )
+}
+
+/// Punctuation which may not start a line.
+fn is_closing(ch: char) -> bool {
+    "，。、；：！？）」』】》〉”’…,.;:!?)]}%".contains(ch)
+}
+
+/// Punctuation which may not end a line.
+fn is_opening(ch: char) -> bool {
+    "（「『【《〈“‘([{".contains(ch)
+}
+
+/// Lays `colour` over `under`, with `coverage` from 0 to 1 scaling its opacity.
+fn blend(under: Rgba<u8>, colour: Rgba<u8>, coverage: f32) -> Rgba<u8> {
+    let top = coverage.clamp(0.0, 1.0) * colour.0[3] as f32 / 255.0;
+    let bottom = under.0[3] as f32 / 255.0;
+    let alpha = top + bottom * (1.0 - top);
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let mix = |channel: usize| {
+        let value = (colour.0[channel] as f32 * top + under.0[channel] as f32 * bottom * (1.0 - top)) / alpha;
+        value.round() as u8
+    };
+    Rgba([mix(0), mix(1), mix(2), (alpha * 255.0).round() as u8])
+}
+
+/// Draws `image` onto `canvas` with its top left corner at (`x`, `y`), blending by its transparency.
+/// Whatever falls outside the canvas is cut off.
+pub fn overlay(canvas: &mut RgbaImage, image: &RgbaImage, x: i64, y: i64) {
+    for (image_x, image_y, pixel) in image.enumerate_pixels() {
+        let (canvas_x, canvas_y) = (x + image_x as i64, y + image_y as i64);
+        if canvas_x >= 0 && canvas_y >= 0 && canvas_x < canvas.width() as i64 && canvas_y < canvas.height() as i64 {
+            let under = canvas.get_pixel_mut(canvas_x as u32, canvas_y as u32);
+            *under = blend(*under, *pixel, 1.0);
+        }
+    }
+}
+
+/// Fills a rectangle on `canvas`. Whatever falls outside the canvas is cut off.
+pub fn fill_rect(canvas: &mut RgbaImage, x: i64, y: i64, width: u32, height: u32, colour: Rgba<u8>) {
+    let (canvas_width, canvas_height) = (canvas.width() as i64, canvas.height() as i64);
+    for pixel_y in y.max(0)..(y + height as i64).min(canvas_height) {
+        for pixel_x in x.max(0)..(x + width as i64).min(canvas_width) {
+            let under = canvas.get_pixel_mut(pixel_x as u32, pixel_y as u32);
+            *under = blend(*under, colour, 1.0);
+        }
+    }
+}
+
+/// Fills a circle on `canvas`, with a softened edge.
+pub fn fill_circle(canvas: &mut RgbaImage, center_x: f32, center_y: f32, radius: f32, colour: Rgba<u8>) {
+    let (width, height) = canvas.dimensions();
+    let min_x = (center_x - radius).floor().max(0.0) as u32;
+    let min_y = (center_y - radius).floor().max(0.0) as u32;
+    let max_x = ((center_x + radius).ceil().max(0.0) as u32).min(width);
+    let max_y = ((center_y + radius).ceil().max(0.0) as u32).min(height);
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let coverage = circle_coverage(x, y, center_x, center_y, radius);
+            if coverage > 0.0 {
+                let under = canvas.get_pixel_mut(x, y);
+                *under = blend(*under, colour, coverage);
+            }
+        }
+    }
+}
+
+/// Cuts the largest circle that fits out of the middle of `image`, leaving the corners transparent.
+pub fn crop_circle(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let radius = width.min(height) as f32 / 2.0;
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+
+    let mut cropped = image.clone();
+    for (x, y, pixel) in cropped.enumerate_pixels_mut() {
+        let coverage = circle_coverage(x, y, center_x, center_y, radius);
+        pixel.0[3] = (pixel.0[3] as f32 * coverage).round() as u8;
+    }
+    cropped
+}
+
+/// How much of the pixel at (`x`, `y`) a circle covers, from 0 to 1.
+fn circle_coverage(x: u32, y: u32, center_x: f32, center_y: f32, radius: f32) -> f32 {
+    let distance = ((x as f32 + 0.5 - center_x).powi(2) + (y as f32 + 0.5 - center_y).powi(2)).sqrt();
+    (radius - distance + 0.5).clamp(0.0, 1.0)
+}
+
+/// Makes an image harder to read by machine: rows are shifted along a random wave, and the
+/// image is sprinkled with speckles and stray strokes in the same colour as the text.
+///
+/// The `seed` decides the wave and the noise, so the same seed always gives the same image.
+pub fn distort(image: &RgbaImage, seed: u64) -> RgbaImage {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (width, height) = image.dimensions();
+
+    // Leave room for the wave on either side.
+    let amplitude = rng.gen_range(4.0..10.0_f32);
+    let wavelength = rng.gen_range(40.0..90.0_f32);
+    let phase = rng.gen_range(0.0..std::f32::consts::TAU);
+    let margin = amplitude.ceil() as u32;
+
+    let mut distorted = RgbaImage::new(width + 2 * margin, height);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let shift = amplitude * ((y as f32 / wavelength) * std::f32::consts::TAU + phase).sin();
+        let new_x = (x as f32 + margin as f32 + shift).round() as u32;
+        if new_x < distorted.width() {
+            distorted.put_pixel(new_x, y, *pixel);
+        }
+    }
+
+    let colour = image
+        .pixels()
+        .max_by_key(|pixel| pixel.0[3])
+        .map(|pixel| Rgba([pixel.0[0], pixel.0[1], pixel.0[2], 255]))
+        .unwrap_or(Rgba([255, 0, 0, 255]));
+
+    let (width, height) = distorted.dimensions();
+    if width == 0 || height == 0 {
+        return distorted;
+    }
+
+    // Speckles
+    let speckles = (width * height) / 150;
+    for _ in 0..speckles {
+        let x = rng.gen_range(0..width);
+        let y = rng.gen_range(0..height);
+        let mut speckle = colour;
+        speckle.0[3] = rng.gen_range(64..=255);
+        distorted.put_pixel(x, y, speckle);
+    }
+
+    // Stray strokes across the text
+    for _ in 0..3 {
+        let (x0, y0) = (rng.gen_range(0..width) as f32, rng.gen_range(0..height) as f32);
+        let (x1, y1) = (rng.gen_range(0..width) as f32, rng.gen_range(0..height) as f32);
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil() as u32;
+        for step in 0..=steps {
+            let t = step as f32 / steps.max(1) as f32;
+            let x = (x0 + (x1 - x0) * t) as u32;
+            let y = (y0 + (y1 - y0) * t) as u32;
+            for dy in 0..3 {
+                if x < width && y + dy < height {
+                    distorted.put_pixel(x, y + dy, colour);
+                }
+            }
+        }
+    }
+
+    distorted
+}
+
+/// Encodes an image, ready to attach to a message.
+pub fn encode(image: &RgbaImage, format: OutputFormat) -> Result<Vec<u8>, DrawError> {
+    let mut bytes = Vec::new();
+    let result = match format {
+        OutputFormat::Png => DynamicImage::ImageRgba8(image.clone()).write_to(&mut bytes, ImageOutputFormat::Png),
+        OutputFormat::Jpeg(quality) => {
+            let white = Rgba([255, 255, 255, 255]);
+            let mut flattened = RgbaImage::from_pixel(image.width(), image.height(), white);
+            for (x, y, pixel) in image.enumerate_pixels() {
+                flattened.put_pixel(x, y, blend(white, *pixel, 1.0));
+            }
+            let rgb = DynamicImage::ImageRgba8(flattened).to_rgb8();
+            DynamicImage::ImageRgb8(rgb).write_to(&mut bytes, ImageOutputFormat::Jpeg(quality.clamp(1, 100)))
+        },
+    };
+    result.map_err(|e| DrawError::Encode(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+
+    fn fonts() -> &'static FontRegistry {
+        static FONTS: OnceLock<FontRegistry> = OnceLock::new();
+        FONTS.get_or_init(|| FontRegistry::load(FONT_DIR).unwrap())
+    }
+
+    fn render(text: &str, options: &RenderOptions) -> Result<RgbaImage, DrawError> {
+        fonts().render(text, options)
+    }
+
+    fn draw(text: &str, options: &RenderOptions) -> Result<Vec<u8>, DrawError> {
+        fonts().draw(text, options)
+    }
+
+    /// An empty directory to put fonts in.
+    fn font_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chairmanmao-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn distortion_is_seeded() {
+        let image = render("你好", &RenderOptions::default()).unwrap();
+        let distorted = distort(&image, 7);
+        assert!(distorted.width() > image.width());
+        assert_eq!(distorted.height(), image.height());
+        assert_eq!(distorted, distort(&image, 7));
+        assert_ne!(distorted, distort(&image, 8));
+    }
+
+    #[test]
+    fn too_large() {
+        let options = RenderOptions {
+            wrap: Some(1600),
+            ..RenderOptions::default()
+        };
+        let text = "我".repeat(2000);
+        assert!(matches!(render(&text, &options), Err(DrawError::TooLarge(_, _))));
+        assert!(render(&"我".repeat(20), &options).is_ok());
+    }
+
+    #[test]
+    fn formats() {
+        let options = RenderOptions::default();
+        let png = draw("你好", &options).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let options = RenderOptions { format: OutputFormat::Jpeg(80), ..options };
+        let jpeg = draw("你好", &options).unwrap();
+        assert_eq!(&jpeg[..3], b"\xff\xd8\xff");
+    }
+
+    #[test]
+    fn spaces_take_up_room() {
+        let options = RenderOptions::default();
+        let plain = render("你好", &options).unwrap();
+        let spaced = render(" 你好 ", &options).unwrap();
+        assert!(spaced.width() > plain.width());
+        assert_eq!(spaced.height(), plain.height());
+    }
+
+    #[test]
+    fn colours_and_padding() {
+        let options = RenderOptions {
+            foreground: Rgba([0, 0, 255, 255]),
+            background: Rgba([255, 255, 255, 255]),
+            padding: 20,
+            size: 32.0,
+            ..RenderOptions::default()
+        };
+        let image = render("好", &options).unwrap();
+        assert_eq!(*image.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert!(image.pixels().any(|pixel| *pixel == Rgba([0, 0, 255, 255])));
+        assert!(image.pixels().all(|pixel| pixel.0[3] == 255));
+    }
+
+    #[test]
+    fn segmenting() {
+        assert_eq!(segments("我爱你"), vec!["我", "爱", "你"]);
+        assert_eq!(segments("Hello  world"), vec!["Hello  ", "world"]);
+        assert_eq!(segments("你好，世界。"), vec!["你", "好，", "世", "界。"]);
+        assert_eq!(segments("他说「你好」"), vec!["他", "说", "「你", "好」"]);
+        assert_eq!(segments("I said 你好!"), vec!["I ", "said ", "你", "好!"]);
+        assert!(segments("").is_empty());
+    }
+
+    #[test]
+    fn wrapping() {
+        let count = |text: &str| text.chars().count() as f32;
+        assert_eq!(wrap(&segments("你好，世界。"), 3.0, count), vec!["你好，", "世界。"]);
+        assert_eq!(wrap(&segments("你好，世界。"), 2.0, count), vec!["你", "好，", "世", "界。"]);
+        assert_eq!(wrap(&segments("one two three"), 8.0, count), vec!["one two", "three"]);
+        assert_eq!(wrap(&segments("abcdef"), 4.0, count), vec!["abcd", "ef"]);
+        assert_eq!(wrap(&segments(""), 4.0, count), vec![""]);
+
+        let lines = break_lines("你好世界\r\n\n再见", Some(2), count);
+        assert_eq!(lines, vec!["你好", "世界", "", "再见"]);
+    }
+
+    #[test]
+    fn multiple_lines() {
+        let options = RenderOptions { size: 32.0, ..RenderOptions::default() };
+        let one = render("你好世界", &options).unwrap();
+        let two = render("你好\n世界", &options).unwrap();
+        assert!(two.height() > one.height());
+        assert!(two.width() < one.width());
+
+        let wrapped = RenderOptions { wrap: Some(70), ..options.clone() };
+        assert_eq!(render("你好世界", &wrapped).unwrap(), two);
+
+        let start = render("你\n你好", &options).unwrap();
+        let end = render("你\n你好", &RenderOptions { align: Align::End, ..options.clone() }).unwrap();
+        assert_eq!(start.dimensions(), end.dimensions());
+        assert_ne!(start, end);
+    }
+
+    #[test]
+    fn vertical() {
+        let options = RenderOptions { size: 32.0, direction: Direction::Vertical, ..RenderOptions::default() };
+        let column = render("你好世界", &options).unwrap();
+        assert!(column.height() > 3 * column.width());
+
+        let columns = render("你好\n世界", &options).unwrap();
+        assert!(columns.width() > column.width());
+        assert!(columns.height() < column.height());
+
+        let wrapped = RenderOptions { wrap: Some(64), ..options };
+        assert_eq!(render("你好世界", &wrapped).unwrap(), columns);
+    }
+
+    #[test]
+    fn errors() {
+        let options = RenderOptions::default();
+        assert_eq!(render("", &options), Err(DrawError::EmptyText));
+        assert_eq!(render("  ", &options), Err(DrawError::EmptyText));
+
+        let missing = RenderOptions { font: "kai".to_string(), ..RenderOptions::default() };
+        assert_eq!(render("你好", &missing), Err(DrawError::FontNotFound("kai".to_string())));
+
+        let tiny = RenderOptions { size: 0.0, ..RenderOptions::default() };
+        assert_eq!(render("你好", &tiny), Err(DrawError::InvalidSize(0.0)));
+
+        let cramped = RenderOptions { line_spacing: -1.0, ..RenderOptions::default() };
+        assert_eq!(render("你好", &cramped), Err(DrawError::InvalidLineSpacing(-1.0)));
+    }
+
+    #[test]
+    fn registry() {
+        assert_eq!(fonts().names(), vec![DEFAULT_FONT]);
+        assert!(fonts().get("ZCOOL_KuaiLe").is_some());
+        assert!(fonts().get("kai").is_none());
+
+        let dir = font_dir("registry");
+        std::fs::copy("data/ZCOOL_KuaiLe.ttf", dir.join("ZCOOL_KuaiLe.ttf")).unwrap();
+        std::fs::copy("data/ZCOOL_KuaiLe.ttf", dir.join("Kai.TTF")).unwrap();
+        std::fs::copy("data/ZCOOL_KuaiLe.ttf", dir.join("Bold.otf")).unwrap();
+        std::fs::write(dir.join("README.md"), "Not a font").unwrap();
+
+        let registry = FontRegistry::load(&dir).unwrap();
+        assert_eq!(registry.names(), vec![DEFAULT_FONT, "bold", "kai"]);
+
+        let chain = registry.chain("kai").unwrap();
+        assert!(std::ptr::eq(chain.fonts[0], registry.get("kai").unwrap()));
+        assert!(std::ptr::eq(chain.fonts[1], registry.get(DEFAULT_FONT).unwrap()));
+        assert_eq!(chain.fonts.len(), 3);
+
+        let options = RenderOptions { font: "kai".to_string(), size: 32.0, ..RenderOptions::default() };
+        assert_eq!(registry.render("你好", &options), render("你好", &RenderOptions { size: 32.0, ..RenderOptions::default() }));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fallback() {
+        let chain = fonts().chain(DEFAULT_FONT).unwrap();
+        let (idx, glyph) = chain.glyph('你');
+        assert_eq!(idx, 0);
+        assert_ne!(glyph.id().0, 0);
+
+        // Characters no font has are drawn as the placeholder glyph, rather than failing.
+        let (idx, glyph) = chain.glyph('\u{E000}');
+        assert_eq!(idx, 0);
+        assert_eq!(glyph.id().0, 0);
+        assert!(render("你\u{E000}好", &RenderOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn registry_errors() {
+        let dir = font_dir("empty");
+        assert!(matches!(FontRegistry::load(&dir), Err(DrawError::NoFonts(_))));
+
+        std::fs::write(dir.join("broken.ttf"), "Not a font").unwrap();
+        assert!(matches!(FontRegistry::load(&dir), Err(DrawError::InvalidFont(name)) if name == "broken"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(FontRegistry::load(&dir), Err(DrawError::Io(_))));
+    }
+
+    fn pairs(ruby: &[Ruby]) -> Vec<(&str, &str)> {
+        ruby.iter().map(|ruby| (ruby.base.as_str(), ruby.annotation.as_str())).collect()
+    }
+
+    #[test]
+    fn explicit_ruby() {
+        let none = Dictionary::default();
+        assert_eq!(pairs(&ruby("你好[ni3 hao3]!", &none)), vec![("你", "nǐ"), ("好", "hǎo"), ("!", "")]);
+        assert_eq!(pairs(&ruby("我[wǒ] 龘", &none)), vec![("我", "wǒ"), (" ", ""), ("龘", "")]);
+        assert_eq!(pairs(&ruby("a[b]", &none)), vec![("a[b]", "")]);
+        assert_eq!(pairs(&annotate("朋友", "peng2 you5")), vec![("朋", "péng"), ("友", "you")]);
+        assert_eq!(pairs(&annotate("东西", "dongxi")), vec![("东西", "dongxi")]);
+    }
+
+    #[test]
+    fn dictionary_ruby() {
+        let dictionary = Dictionary::from_exams(&crate::decks::load_exams());
+        let annotated = ruby("我是中国人。", &dictionary);
+        assert_eq!(pairs(&annotated), vec![
+            ("我", "wǒ"),
+            ("是", "shì"),
+            ("中", "zhōng"),
+            ("国", "guó"),
+            ("人", "rén"),
+            ("。", ""),
+        ]);
+    }
+
+    #[test]
+    fn ruby_layout() {
+        let options = RenderOptions { size: 32.0, ..RenderOptions::default() };
+        let plain = render("你好", &options).unwrap();
+        let annotated = fonts().render_ruby(&annotate("你好", "ni3 hao3"), &options).unwrap();
+        assert!(annotated.height() > plain.height());
+        assert!(annotated.width() >= plain.width());
+
+        let wrapped = RenderOptions { wrap: Some(40), ..options.clone() };
+        let two_lines = fonts().render_ruby(&annotate("你好", "ni3 hao3"), &wrapped).unwrap();
+        assert!(two_lines.height() > annotated.height());
+        assert!(two_lines.width() < annotated.width());
+
+        // The font has no tone marks, so the annotations fall back to tone numbers.
+        let marked = fonts().render_ruby(&annotate("你好", "ni3 hao3"), &options).unwrap();
+        let numbered = fonts().render_ruby(&[Ruby::new("你", "ni3"), Ruby::new("好", "hao3")], &options).unwrap();
+        assert_eq!(marked, numbered);
+
+        assert_eq!(fonts().render_ruby(&[Ruby::new(" ", "")], &options), Err(DrawError::EmptyText));
+    }
+
+    #[test]
+    fn shapes() {
+        let mut canvas = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        fill_rect(&mut canvas, -5, 8, 100, 100, Rgba([255, 0, 0, 255]));
+        assert_eq!(*canvas.get_pixel(0, 9), Rgba([255, 0, 0, 255]));
+        assert_eq!(*canvas.get_pixel(9, 7), Rgba([0, 0, 0, 255]));
+
+        fill_circle(&mut canvas, 5.0, 5.0, 3.0, Rgba([0, 255, 0, 255]));
+        assert_eq!(*canvas.get_pixel(5, 5), Rgba([0, 255, 0, 255]));
+        assert_eq!(*canvas.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+
+        let mut translucent = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 128]));
+        translucent.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        overlay(&mut canvas, &translucent, 8, -2);
+        assert_eq!(*canvas.get_pixel(9, 1), Rgba([0, 0, 128, 255]));
+        assert_eq!(*canvas.get_pixel(8, 0), Rgba([0, 0, 128, 255]));
+    }
+
+    #[test]
+    fn circles() {
+        let square = RgbaImage::from_pixel(20, 20, Rgba([255, 255, 255, 255]));
+        let circle = crop_circle(&square);
+        assert_eq!(circle.get_pixel(0, 0).0[3], 0);
+        assert_eq!(circle.get_pixel(19, 19).0[3], 0);
+        assert_eq!(circle.get_pixel(10, 10).0[3], 255);
+        assert_eq!(circle.get_pixel(1, 10).0[3], 255);
+    }
 }
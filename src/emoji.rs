@@ -0,0 +1,191 @@
+//! Custom emoji, drawn from text for `!emoji`.
+
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+
+use crate::draw::{encode, overlay, DrawError, FontRegistry, OutputFormat, RenderOptions};
+
+/// Emoji are drawn at this size, square. Discord shows them much smaller, but scales them down
+/// cleanly.
+pub const EMOJI_SIZE: u32 = 128;
+
+/// Discord refuses emoji larger than this, in bytes.
+pub const MAX_EMOJI_BYTES: usize = 256 * 1024;
+
+/// The space left around the text, in pixels.
+const EMOJI_PADDING: u32 = 4;
+
+/// The outline drawn around text over a template, so it can still be read.
+const OUTLINE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const OUTLINE_WIDTH: i64 = 2;
+
+#[derive(Debug)]
+pub enum EmojiError {
+    /// Discord emoji names are 2 to 32 letters, digits or underscores.
+    InvalidName(String),
+    TooLarge(usize),
+    Draw(DrawError),
+}
+
+impl std::fmt::Display for EmojiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmojiError::InvalidName(name) => write!(f, "Emoji names must be 2 to 32 letters, digits or underscores: {}", name),
+            EmojiError::TooLarge(size) => write!(f, "Emoji must be under {} KiB, but this one is {} KiB", MAX_EMOJI_BYTES / 1024, size / 1024),
+            EmojiError::Draw(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EmojiError {}
+
+impl From<DrawError> for EmojiError {
+    fn from(e: DrawError) -> EmojiError {
+        EmojiError::Draw(e)
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), EmojiError> {
+    let length = name.chars().count();
+    let valid = (2..=32).contains(&length) && name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(EmojiError::InvalidName(name.to_string()))
+    }
+}
+
+/// Draws `text` into a square emoji, scaled to fill it, over `template` if one is given.
+///
+/// Hanzi with no line breaks are stacked into a square, so 加油鸭子 comes out two by two.
+pub fn render_emoji(
+    fonts: &FontRegistry,
+    text: &str,
+    options: &RenderOptions,
+    template: Option<&RgbaImage>,
+) -> Result<RgbaImage, EmojiError> {
+    let text = square(text);
+    let options = RenderOptions {
+        padding: 0,
+        wrap: None,
+        ..options.clone()
+    };
+    let drawn = fit(&fonts.render(&text, &options)?);
+
+    let mut emoji = match template {
+        Some(template) => cover(template),
+        None => RgbaImage::new(EMOJI_SIZE, EMOJI_SIZE),
+    };
+    let x = (EMOJI_SIZE - drawn.width()) as i64 / 2;
+    let y = (EMOJI_SIZE - drawn.height()) as i64 / 2;
+
+    if template.is_some() {
+        let outline = RenderOptions {
+            foreground: OUTLINE,
+            ..options
+        };
+        let outline = fit(&fonts.render(&text, &outline)?);
+        for dx in -OUTLINE_WIDTH..=OUTLINE_WIDTH {
+            for dy in -OUTLINE_WIDTH..=OUTLINE_WIDTH {
+                overlay(&mut emoji, &outline, x + dx, y + dy);
+            }
+        }
+    }
+    overlay(&mut emoji, &drawn, x, y);
+
+    Ok(emoji)
+}
+
+/// Encodes an emoji as PNG, making sure Discord will take it.
+pub fn encode_emoji(emoji: &RgbaImage) -> Result<Vec<u8>, EmojiError> {
+    let png = encode(emoji, OutputFormat::Png)?;
+    if png.len() > MAX_EMOJI_BYTES {
+        return Err(EmojiError::TooLarge(png.len()));
+    }
+    Ok(png)
+}
+
+/// A PNG as a data URI, the way Discord's API expects emoji images.
+pub fn data_uri(png: &[u8]) -> String {
+    format!("data:image/png;base64,{}", base64::encode(png))
+}
+
+/// Breaks a run of hanzi into rows, so it's roughly as tall as it is wide.
+fn square(text: &str) -> String {
+    let text = text.trim();
+    let count = text.chars().count();
+    if count < 4 || !text.chars().all(crate::hanzi::is_hanzi) {
+        return text.to_string();
+    }
+
+    let per_row = (count as f64).sqrt().ceil() as usize;
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .chunks(per_row)
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Scales an image to cover the whole emoji, cropping whatever hangs over the sides.
+fn cover(image: &RgbaImage) -> RgbaImage {
+    let scale = EMOJI_SIZE as f32 / image.width().min(image.height()).max(1) as f32;
+    let width = ((image.width() as f32 * scale).round() as u32).max(EMOJI_SIZE);
+    let height = ((image.height() as f32 * scale).round() as u32).max(EMOJI_SIZE);
+    let scaled = image::imageops::resize(image, width, height, FilterType::Triangle);
+    let (x, y) = ((width - EMOJI_SIZE) / 2, (height - EMOJI_SIZE) / 2);
+    image::imageops::crop_imm(&scaled, x, y, EMOJI_SIZE, EMOJI_SIZE).to_image()
+}
+
+/// Scales an image to fit inside the emoji's padding, keeping its shape.
+fn fit(image: &RgbaImage) -> RgbaImage {
+    let inner = EMOJI_SIZE - 2 * EMOJI_PADDING;
+    let scale = inner as f32 / image.width().max(image.height()) as f32;
+    let width = ((image.width() as f32 * scale).round() as u32).clamp(1, inner);
+    let height = ((image.height() as f32 * scale).round() as u32).clamp(1, inner);
+    image::imageops::resize(image, width, height, FilterType::Triangle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::draw::FONT_DIR;
+
+    #[test]
+    fn names() {
+        assert!(validate_name("mao").is_ok());
+        assert!(validate_name("jia_you2").is_ok());
+        assert!(validate_name("x").is_err());
+        assert!(validate_name("has space").is_err());
+        assert!(validate_name("加油").is_err());
+        assert!(validate_name(&"a".repeat(33)).is_err());
+    }
+
+    #[test]
+    fn squares() {
+        assert_eq!(square("加油"), "加油");
+        assert_eq!(square("加油鸭子"), "加油\n鸭子");
+        assert_eq!(square("一二三四五"), "一二三\n四五");
+        assert_eq!(square("hello"), "hello");
+    }
+
+    #[test]
+    fn emoji() {
+        let fonts = FontRegistry::load(FONT_DIR).unwrap();
+        let options = RenderOptions::default();
+
+        let emoji = render_emoji(&fonts, "加油", &options, None).unwrap();
+        assert_eq!(emoji.dimensions(), (EMOJI_SIZE, EMOJI_SIZE));
+        assert_eq!(emoji.get_pixel(0, 0).0[3], 0);
+
+        let blue = RgbaImage::from_pixel(300, 200, Rgba([0, 0, 255, 255]));
+        let emoji = render_emoji(&fonts, "好", &options, Some(&blue)).unwrap();
+        assert_eq!(emoji.dimensions(), (EMOJI_SIZE, EMOJI_SIZE));
+        assert_eq!(*emoji.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+
+        let png = encode_emoji(&emoji).unwrap();
+        assert!(data_uri(&png).starts_with("data:image/png;base64,iVBORw0KGgo"));
+
+        assert!(matches!(render_emoji(&fonts, " ", &options, None), Err(EmojiError::Draw(DrawError::EmptyText))));
+    }
+}
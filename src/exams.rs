@@ -1,3 +1,12 @@
+use serde::{Serialize, Deserialize};
+
+use crate::clock::{Clock, SystemClock};
+use crate::pinyin::{self, Match};
+use crate::questions::{Card, QuestionType};
+
+/// How long to pause after a question times out, in milliseconds.
+const TIMEOUT_PAUSE: u64 = 1000;
+
 /// [TickResult] is the result you get from calling [Examiner::tick].
 #[derive(Debug)]
 pub enum TickResult {
@@ -15,43 +24,141 @@ pub enum TickResult {
     Finished(ExamScore),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Exam {
     pub name: String,
+    /// The words the exam is made from.
+    pub cards: Vec<Card>,
+    /// The kinds of question asked about each card.
+    pub modes: Vec<QuestionType>,
+    /// The questions, derived from `cards` and `modes` with [crate::questions::derive_deck].
     pub deck: Vec<Question>,
     pub num_questions: usize,
     pub max_wrong: Option<usize>,
     pub timelimit: usize,
     pub hsk_level: usize,
+    #[serde(default)]
+    pub prompt_style: PromptStyle,
+}
+
+/// How drivers show each question's prompt.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PromptStyle {
+    /// Plain text.
+    #[default]
+    Text,
+    /// Drawn as an image, so the prompt can't be pasted into a dictionary.
+    Image,
+    /// Drawn as an image with noise and distortion, to get in the way of OCR as well.
+    Distorted,
+}
+
+impl PromptStyle {
+    pub const ALL: [PromptStyle; 3] = [PromptStyle::Text, PromptStyle::Image, PromptStyle::Distorted];
+
+    /// The name used in deck files, like `distorted`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PromptStyle::Text => "text",
+            PromptStyle::Image => "image",
+            PromptStyle::Distorted => "distorted",
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        *self == PromptStyle::Text
+    }
+}
+
+impl std::str::FromStr for PromptStyle {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<PromptStyle, String> {
+        PromptStyle::ALL
+            .iter()
+            .find(|style| style.name() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown prompt style: {}", name))
+    }
+}
+
+/// Options which change how an [Examiner] administers an [Exam].
+#[derive(Debug, Clone, Default)]
+pub struct ExamOptions {
+    /// Practice mode follows its own rules:
+    ///
+    /// * Every card in the deck is asked, rather than just [Exam::num_questions].
+    /// * Each question gets 30 seconds, and there is no limit on wrong answers.
+    /// * The first timeout ends the exam, since the examinee has probably walked away.
+    /// * The exam can never be passed. It does not count towards HSK or credit.
+    ///
+    /// Drivers should show the correct answer as soon as a question is missed.
+    pub practice: bool,
+
+    /// When set, an answer with the right syllables but the wrong tones is recorded as
+    /// [Answer::WrongTone] and only counts as half a mistake. Otherwise, it's simply wrong.
+    pub tones_lenient: bool,
+
+    /// Overrides the time limit for each question, in milliseconds. This applies in practice
+    /// mode too.
+    pub timelimit: Option<usize>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Question {
     pub question: String,
     pub valid_answers: Vec<String>,
     pub meaning: String,
+    pub kind: QuestionType,
 }
 
 impl Question {
-    pub fn is_correct(&self, answer: &str) -> bool {
-        for valid_answer in self.valid_answers.iter() {
-            let answer_fixed = answer.to_lowercase().replace(" ", "").replace("5", "");
-            let valid_answer_fixed = valid_answer.to_lowercase().replace(" ", "").replace("5", "");
+    /// Checks an answer against each of the valid answers and returns the best match.
+    ///
+    /// When the question asks for pinyin, answers are compared with [pinyin::compare], so tone
+    /// marks and tone numbers are interchangeable. Anything else is compared ignoring case and spaces.
+    pub fn check(&self, answer: &str) -> Match {
+        let mut best = Match::NoMatch;
 
-            if answer_fixed == valid_answer_fixed {
-                return true;
+        for valid_answer in self.valid_answers.iter() {
+            let compared = if self.kind.answers_in_pinyin() {
+                pinyin::compare(answer, valid_answer)
+            } else {
+                None
+            };
+
+            let result = match compared {
+                Some(result) => result,
+                None => {
+                    let answer_fixed = answer.to_lowercase().replace(' ', "");
+                    let valid_answer_fixed = valid_answer.to_lowercase().replace(' ', "");
+                    if answer_fixed == valid_answer_fixed { Match::Exact } else { Match::NoMatch }
+                },
+            };
+
+            match result {
+                Match::Exact => return Match::Exact,
+                Match::WrongTone => best = Match::WrongTone,
+                Match::NoMatch => (),
             }
         }
 
-        false
+        best
+    }
+
+    pub fn is_correct(&self, answer: &str) -> bool {
+        self.check(answer) == Match::Exact
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Answer {
     Timeout,
     Quit,
     Correct(String),
+    /// The right syllables with the wrong tones. Only recorded when grading is tones lenient.
+    WrongTone(String),
     Incorrect(String),
 }
 
@@ -72,6 +179,10 @@ impl Answer {
         }
     }
 
+    pub fn is_wrong_tone(&self) -> bool {
+        matches!(self, Answer::WrongTone(_))
+    }
+
     fn is_timeout(&self) -> bool {
         if let Answer::Timeout = self {
             return true;
@@ -85,7 +196,8 @@ impl Answer {
 ///
 /// The general pattern for using it is to first call [Examiner::tick()] to get the first question.
 /// Then, subsequent calls to [Examiner::tick()] will progress the machine. This should be called
-/// every `millis_per_tick` seconds (as provided by [Examiner::make()]).
+/// frequently (every 100ms or so). All timing is read from the [Clock] given to [Examiner::make()],
+/// so it doesn't matter exactly how far apart the calls are.
 /// The return value of [Examiner::tick()] is a [TickResult]. It tells you the result of the
 /// tick and indicates what state change took place (if any).
 ///
@@ -93,30 +205,45 @@ impl Answer {
 /// The next call to [Examiner::tick()] will acknowledge the result.
 ///
 /// The exam ends once [Examiner::tick()] returns [TickResult::Finished].
-#[derive(Debug)]
+/// See [ExamResult] for how the outcome is decided.
+///
+/// An exam in progress can be saved by serializing the [Examiner]. This keeps the seed, the
+/// shuffled questions and the answers given so far, but not the clock or any timings in progress.
+/// After deserializing, call [Examiner::resume()].
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Examiner {
     // Constants
+    seed: u64,
     questions: Vec<Question>,
     max_wrong: Option<usize>,
     timelimit: usize,
     fail_on_timeout: bool,
-    millis_per_tick: usize,
-    //practice: bool,
+    #[serde(skip, default = "default_clock")]
+    clock: Box<dyn Clock>,
+    practice: bool,
+    tones_lenient: bool,
 
     // Variables
     current_question_index: isize,
-    current_question_time_left: usize,
+    #[serde(skip)]
+    current_question_asked_at: u64,
     answers_given: Vec<Answer>,
-    pause_time: usize,
+    question_millis: Vec<u64>,
+    #[serde(skip)]
+    pause_until: Option<u64>,
+}
+
+fn default_clock() -> Box<dyn Clock> {
+    Box::new(SystemClock::new())
 }
 
 impl Examiner {
-    pub fn make(exam: &Exam, millis_per_tick: usize, seed: u64) -> Examiner {
+    pub fn make(exam: &Exam, options: &ExamOptions, clock: Box<dyn Clock>, seed: u64) -> Examiner {
         use rand::rngs::StdRng;
         use rand::SeedableRng;
         let mut rng = StdRng::seed_from_u64(seed);
 
-        let practice = false;
+        let practice = options.practice;
 
         let mut questions = exam.deck.clone();
 
@@ -126,20 +253,26 @@ impl Examiner {
             questions.truncate(exam.num_questions);
         }
 
-        let timelimit = if !practice { exam.timelimit } else { 30000 };
+        let timelimit = match options.timelimit {
+            Some(timelimit) => timelimit,
+            None => if !practice { exam.timelimit } else { 30000 },
+        };
         let max_wrong = if !practice { exam.max_wrong } else { None };
 
         Examiner {
+            seed,
             questions,
-            millis_per_tick,
+            clock,
             max_wrong,
-            timelimit: timelimit,
+            timelimit,
             fail_on_timeout: practice,
-            //practice: practice,
+            practice,
+            tones_lenient: options.tones_lenient,
             current_question_index: -1,
-            current_question_time_left: timelimit,
+            current_question_asked_at: 0,
             answers_given: vec![],
-            pause_time: 0,
+            question_millis: vec![],
+            pause_until: None,
         }
     }
 
@@ -147,9 +280,53 @@ impl Examiner {
 //    # Queries
 //    ####################################################################
 
+    pub fn is_practice(&self) -> bool {
+        self.practice
+    }
+
+    /// The seed given to [Examiner::make()].
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The questions which will be asked, in order.
+    pub fn questions(&self) -> &[Question] {
+        &self.questions
+    }
+
+    /// The number of questions which will be asked, unless the exam ends early.
+    pub fn num_questions(&self) -> usize {
+        self.questions.len()
+    }
+
+    /// The number of questions answered, timed out or given up on so far.
+    pub fn num_answered(&self) -> usize {
+        self.answers_given.len()
+    }
+
+    /// The time limit for each question, in milliseconds.
+    pub fn timelimit(&self) -> u64 {
+        self.timelimit as u64
+    }
+
+    pub fn max_wrong(&self) -> Option<usize> {
+        self.max_wrong
+    }
+
+    /// The question waiting for an answer, if any.
+    /// After [Examiner::resume()], drivers should ask this question again.
+    pub fn open_question(&self) -> Option<&Question> {
+        if self.finished() || !self.ready_for_next_answer() {
+            None
+        } else {
+            Some(self.current_question())
+        }
+    }
+
     fn current_question(&self) -> &Question {
         assert!(self.current_question_index >= 0, "You must call tick() before the first question.");
-        // TODO Handle case where current_question_index > len(self.questions)
+        // tick() never moves past the last question, since the exam is finished once every
+        // question has an answer.
         &self.questions[self.current_question_index as usize]
     }
 
@@ -161,37 +338,66 @@ impl Examiner {
         self.current_question_index == self.answers_given.len() as isize
     }
 
-    fn number_wrong(&self) -> usize {
-        let questions = &self.questions[0..self.answers_given.len()];
-
-        let mut number_wrong = 0;
+    /// The number of mistakes made so far.
+    /// [Answer::Incorrect] and [Answer::Timeout] count as one mistake.
+    /// An [Answer::WrongTone] counts as half a mistake.
+    /// [Answer::Quit] is not a mistake, since the question was never attempted.
+    pub fn mistakes(&self) -> f32 {
+        let mut mistakes = 0.0;
 
-        for (_question, answer) in questions.iter().zip(self.answers_given.iter()) {
-            if !answer.is_correct() {
-                number_wrong += 1;
+        for answer in self.answers_given.iter() {
+            match answer {
+                Answer::Correct(_) | Answer::Quit => (),
+                Answer::WrongTone(_) => mistakes += 0.5,
+                Answer::Incorrect(_) | Answer::Timeout => mistakes += 1.0,
             }
         }
 
-        number_wrong
+        mistakes
     }
 
     fn score(&self) -> ExamScore {
-        let score = 1.0 - self.number_wrong() as f32 / self.answers_given.len() as f32;
-        let passed = self.passed();
-        let graded_questions = self.graded_questions();
         ExamScore {
-            score,
-            passed,
-            graded_questions,
+            result: self.result(),
+            graded_questions: self.graded_questions(),
         }
     }
 
-    fn passed(&self) -> bool {
+    fn result(&self) -> ExamResult {
         assert!(self.finished(), "Exam is not finished");
-        if let Some(max_wrong) = self.max_wrong {
-            self._finished_gave_up() && self.number_wrong() <= max_wrong
-        } else {
-            false
+
+        // The reasons are checked in order of precedence, so exactly one of them is true.
+        let gave_up = self._finished_gave_up();
+        let failed_too_many_wrong = !gave_up && self._finished_too_many_wrong();
+        let timed_out = !gave_up && !failed_too_many_wrong && self._finished_timeout();
+        let completed = !gave_up && !failed_too_many_wrong && !timed_out;
+
+        let num_questions = self.questions.len();
+        let num_answered = self.answers_given.iter().filter(|answer| !answer.is_quit()).count();
+        let num_correct = self.answers_given.iter().filter(|answer| answer.is_correct()).count();
+        let num_wrong_tone = self.answers_given.iter().filter(|answer| answer.is_wrong_tone()).count();
+
+        let percent_of_exam = |count: f32| {
+            if num_questions == 0 {
+                100.0
+            } else {
+                100.0 * count / num_questions as f32
+            }
+        };
+
+        ExamResult {
+            passed: completed && !self.practice,
+            completed,
+            failed_too_many_wrong,
+            gave_up,
+            timed_out,
+            num_questions,
+            num_answered,
+            num_correct,
+            mistakes: self.mistakes(),
+            percent_correct: percent_of_exam(num_correct as f32 + 0.5 * num_wrong_tone as f32),
+            percent_answered: percent_of_exam(num_answered as f32),
+            question_millis: self.question_millis.clone(),
         }
     }
 
@@ -203,17 +409,12 @@ impl Examiner {
     }
 
     fn _finished_gave_up(&self) -> bool {
-        for answer in self.answers_given.iter() {
-            if answer.is_quit() {
-                return true;
-            }
-        }
-        false
+        self.answers_given.iter().any(|answer| answer.is_quit())
     }
 
     fn _finished_too_many_wrong(&self) -> bool {
         if let Some(max_wrong) = self.max_wrong {
-            self.number_wrong() > max_wrong
+            self.mistakes() > max_wrong as f32
         } else {
             false
         }
@@ -228,30 +429,30 @@ impl Examiner {
     }
 
     fn _number_timeouts(&self) -> usize {
-        let mut number_timeout = 0;
-        for answer in self.answers_given.iter() {
-            if answer.is_timeout() {
-                number_timeout += 1
-            }
-        }
-        number_timeout
+        self.answers_given.iter().filter(|answer| answer.is_timeout()).count()
     }
 
     fn graded_questions(&self) -> Vec<(Question, Answer)> {
-        let num_questions_answered = self.answers_given.len();
-        let questions = self.questions[..num_questions_answered].to_owned();
-
-        let mut results = Vec::new();
+        self.questions
+            .iter()
+            .zip(self.answers_given.iter())
+            .map(|(question, answer)| (question.clone(), answer.clone()))
+            .collect()
+    }
 
-        for (question, answer) in questions.iter().zip(self.answers_given.iter()) {
-            results.push((question.clone(), answer.clone()));
-        }
+    /// How long the current question has been open for, capped at the time limit.
+    fn time_spent(&self) -> u64 {
+        let elapsed = self.clock.now().saturating_sub(self.current_question_asked_at);
+        elapsed.min(self.timelimit as u64)
+    }
 
-        results
+    /// How many milliseconds are left to answer the current question.
+    pub fn time_left(&self) -> u64 {
+        self.timelimit as u64 - self.time_spent()
     }
 
     fn timed_out(&self) -> bool {
-        self.current_question_time_left <= 0
+        self.time_left() == 0
     }
 
 //    ####################################################################
@@ -260,55 +461,129 @@ impl Examiner {
 //
     pub fn tick(&mut self) -> TickResult {
         if self.finished() {
-            TickResult::Finished(self.score())
-        } else if self.pause_time > 0 {
-            self.pause_time -= self.millis_per_tick;
-            TickResult::Pause
-        } else if self.ready_for_next_question() {
+            return TickResult::Finished(self.score());
+        }
+
+        if let Some(pause_until) = self.pause_until {
+            if self.clock.now() < pause_until {
+                return TickResult::Pause;
+            }
+            self.pause_until = None;
+        }
+
+        if self.ready_for_next_question() {
             self.current_question_index += 1;
-            self.current_question_time_left = self.timelimit;
+            self.current_question_asked_at = self.clock.now();
             TickResult::NextQuestion(self.current_question().clone())
         } else if self.timed_out() {
+            // The pause is measured from the deadline, so a late tick doesn't stretch it.
+            let deadline = self.current_question_asked_at + self.timelimit as u64;
             self.answers_given.push(Answer::Timeout);
-            self.pause_time = 1000;
+            self.question_millis.push(self.timelimit as u64);
+            self.pause_until = Some(deadline + TIMEOUT_PAUSE);
             TickResult::Timeout
         } else {
-            self.current_question_time_left -= self.millis_per_tick;
             TickResult::Nothing
         }
     }
 
+    /// Supplies the examinee's answer to the current question.
+    ///
+    /// Returns the question along with how the answer was graded.
+    /// Returns `None` if no question is waiting for an answer: before the first tick, after the
+    /// question has already been answered or timed out, or after the exam is finished.
+    /// An answer which arrives after the time limit is also refused, even if [Examiner::tick()]
+    /// hasn't recorded the timeout yet.
     pub fn answer(&mut self, answer: &str) -> Option<(Question, Answer)> {
-        if self.finished() || !self.ready_for_next_answer() || self.pause_time > 0 {
+        if self.finished() || !self.ready_for_next_answer() || self.timed_out() {
             return None;
         }
 
         let current_question = self.current_question().clone();
-        let correct = current_question.is_correct(answer);
 
-
-        let answer = if correct {
-            Answer::Correct(answer.to_string())
-        } else {
-            Answer::Incorrect(answer.to_string())
+        let answer = match current_question.check(answer) {
+            Match::Exact => Answer::Correct(answer.to_string()),
+            Match::WrongTone if self.tones_lenient => Answer::WrongTone(answer.to_string()),
+            _ => Answer::Incorrect(answer.to_string()),
         };
 
         self.answers_given.push(answer.clone());
+        self.question_millis.push(self.time_spent());
 
         Some((current_question, answer))
     }
 
+    /// Picks up a deserialized exam with the given clock.
+    ///
+    /// The open question (if any) gets a fresh time limit, and any pause after a timeout is skipped.
+    pub fn resume(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+        self.current_question_asked_at = self.clock.now();
+        self.pause_until = None;
+    }
+
+    /// Ends the exam early. The next call to [Examiner::tick()] returns [TickResult::Finished].
+    ///
+    /// This may be called at any time. The question currently being asked is recorded as
+    /// [Answer::Quit]. If no question is waiting for an answer (because the last one was just
+    /// answered, or during a pause), the next question is recorded as quit instead.
+    /// Once the exam is finished, this does nothing.
     pub fn give_up(&mut self) {
-        assert!(self.ready_for_next_answer(), "Can't give_up until the next tick.");
-        self.answers_given.push(Answer::Quit)
+        if self.finished() {
+            return;
+        }
+
+        let millis = if self.ready_for_next_answer() { self.time_spent() } else { 0 };
+        self.answers_given.push(Answer::Quit);
+        self.question_millis.push(millis);
+        self.pause_until = None;
     }
 }
 
+/// The outcome of a finished exam.
+///
+/// An exam ends for exactly one of four reasons. They are checked in this order:
+///
+/// * [ExamResult::gave_up]: the examinee quit.
+/// * [ExamResult::failed_too_many_wrong]: the examinee made more than [Exam::max_wrong] mistakes.
+///   This is checked after every answer, so the exam ends as soon as the limit is exceeded.
+/// * [ExamResult::timed_out]: a question timed out under practice rules.
+/// * [ExamResult::completed]: every question was answered or timed out.
+///
+/// An exam is passed if and only if it was completed, and it wasn't a practice exam.
+#[derive(Debug, Clone)]
+pub struct ExamResult {
+    pub passed: bool,
+    pub completed: bool,
+    pub failed_too_many_wrong: bool,
+    pub gave_up: bool,
+    pub timed_out: bool,
+
+    /// The number of questions in the exam, whether or not they were reached.
+    pub num_questions: usize,
+    /// The number of questions which were answered or timed out.
+    pub num_answered: usize,
+    /// The number of questions which were answered correctly.
+    pub num_correct: usize,
+    /// See [Examiner] for how mistakes are counted.
+    pub mistakes: f32,
+
+    /// The percentage of the exam answered correctly, from 0 to 100.
+    /// An [Answer::WrongTone] earns half credit. Questions which were never reached earn nothing.
+    /// An exam with no questions scores 100.
+    pub percent_correct: f32,
+    /// The percentage of the exam which was answered or timed out, from 0 to 100.
+    pub percent_answered: f32,
+
+    /// The answer latency for each question the examinee reached, in order.
+    /// This is how many milliseconds passed between the question being asked and the answer
+    /// arriving. A timeout counts as the whole time limit.
+    pub question_millis: Vec<u64>,
+}
 
 #[derive(Debug)]
 pub struct ExamScore {
-    pub passed: bool,
-    pub score: f32,
+    pub result: ExamResult,
 
     pub graded_questions: Vec<(Question, Answer)>,
 }
@@ -327,24 +602,49 @@ impl TickResult {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::questions::derive_deck;
+    use crate::clock::SimulatedClock;
+
+    fn start(exam: &Exam, options: &ExamOptions) -> (Examiner, SimulatedClock) {
+        let clock = SimulatedClock::new();
+        let examiner = Examiner::make(exam, options, Box::new(clock.clone()), 0);
+        (examiner, clock)
+    }
+
+    fn hanzi_exam(cards: Vec<Card>, num_questions: usize, max_wrong: Option<usize>, timelimit: usize) -> Exam {
+        let modes = vec![QuestionType::HanziToPinyin];
+        let deck = derive_deck(&cards, &modes, 0);
+
+        Exam {
+            name: "hsk1".to_string(),
+            cards,
+            modes,
+            deck,
+            num_questions,
+            max_wrong,
+            timelimit,
+            hsk_level: 1,
+            prompt_style: PromptStyle::Text,
+        }
+    }
 
     #[test]
     fn test1() {
         let name = "hsk1".to_string();
-        let deck = vec![
-            Question {
-                question: "hello".to_string(),
-                valid_answers: vec!["world".to_string()],
+        let cards = vec![
+            Card {
+                hanzi: "hello".to_string(),
+                pinyin: vec!["world".to_string()],
                 meaning: "Greeting".to_string(),
             },
-            Question {
-                question: "foo".to_string(),
-                valid_answers: vec!["bar".to_string()],
+            Card {
+                hanzi: "foo".to_string(),
+                pinyin: vec!["bar".to_string()],
                 meaning: "foobar".to_string(),
             },
-            Question {
-                question: "foobar".to_string(),
-                valid_answers: vec!["baz".to_string()],
+            Card {
+                hanzi: "foobar".to_string(),
+                pinyin: vec!["baz".to_string()],
                 meaning: "foobar~baz".to_string(),
             },
         ];
@@ -354,16 +654,22 @@ mod test {
         let timelimit = 5;
         let hsk_level = 1;
 
+        let modes = vec![QuestionType::HanziToPinyin];
+        let deck = derive_deck(&cards, &modes, 0);
+
         let exam = Exam {
             name,
+            cards,
+            modes,
             deck,
             num_questions,
             max_wrong,
             timelimit,
             hsk_level,
+            prompt_style: PromptStyle::Text,
         };
 
-        let mut examiner = Examiner::make(&exam, 100, 0);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
         let tick_result = examiner.tick();
         let question = tick_result.unwrap_next_question();
 
@@ -372,94 +678,566 @@ mod test {
         let tick_result = examiner.tick();
         dbg!(&tick_result);
     }
-}
 
-pub mod load {
-    use super::*;
-    use std::collections::HashMap;
-    use serde::{Deserialize};
-    use serde_json;
+    #[test]
+    fn tones_lenient() {
+        let cards = vec![
+            Card {
+                hanzi: "我".to_string(),
+                pinyin: vec!["wo3".to_string()],
+                meaning: "I".to_string(),
+            },
+            Card {
+                hanzi: "你".to_string(),
+                pinyin: vec!["ni3".to_string()],
+                meaning: "you".to_string(),
+            },
+        ];
+
+        let exam = hanzi_exam(cards, 2, Some(1), 5000);
+
+        let strict = ExamOptions::default();
+        let (mut examiner, _clock) = start(&exam, &strict);
+        let question = examiner.tick().unwrap_next_question();
+        let answer = if question.question == "我" { "wó" } else { "ní" };
+        let (_question, answer) = examiner.answer(answer).unwrap();
+        assert!(matches!(answer, Answer::Incorrect(_)));
+
+        let lenient = ExamOptions {
+            tones_lenient: true,
+            ..ExamOptions::default()
+        };
+        let (mut examiner, _clock) = start(&exam, &lenient);
+        let question = examiner.tick().unwrap_next_question();
+        let answer = if question.question == "我" { "wó" } else { "ní" };
+        let (_question, answer) = examiner.answer(answer).unwrap();
+        assert!(answer.is_wrong_tone());
+
+        // Half a mistake is still within max_wrong = 1.
+        assert!(matches!(examiner.tick(), TickResult::NextQuestion(_)));
+    }
+
+    #[test]
+    fn wrong_tones_add_up() {
+        let cards: Vec<Card> = [("我", "wo3"), ("你", "ni3"), ("他", "ta1"), ("好", "hao3")]
+            .iter()
+            .map(|(hanzi, pinyin)| Card {
+                hanzi: hanzi.to_string(),
+                pinyin: vec![pinyin.to_string()],
+                meaning: String::new(),
+            })
+            .collect();
+        let wrong_tone = |hanzi: &str| match hanzi {
+            "我" => "wó",
+            "你" => "ní",
+            "他" => "tá",
+            _ => "háo",
+        };
 
+        let exam = hanzi_exam(cards, 4, Some(1), 5000);
+        let lenient = ExamOptions {
+            tones_lenient: true,
+            ..ExamOptions::default()
+        };
+        let (mut examiner, _clock) = start(&exam, &lenient);
 
-    #[derive(Deserialize, Debug)]
-    struct JsonExam {
-        name: String,
+        // Two wrong tones make one mistake, which is allowed. The third makes one and a half.
+        for _ in 0..3 {
+            let question = examiner.tick().unwrap_next_question();
+            let (_question, answer) = examiner.answer(wrong_tone(&question.question)).unwrap();
+            assert!(answer.is_wrong_tone());
+        }
 
-        #[serde(rename = "numQuestions")]
-        num_questions: usize,
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.failed_too_many_wrong);
+        assert!(!result.passed);
+        assert_eq!(result.mistakes, 1.5);
+    }
 
-        #[serde(rename = "maxWrong")]
-        max_wrong: usize,
+    #[test]
+    fn practice_asks_whole_deck() {
+        let cards = vec![
+            Card {
+                hanzi: "hello".to_string(),
+                pinyin: vec!["world".to_string()],
+                meaning: "Greeting".to_string(),
+            },
+            Card {
+                hanzi: "foo".to_string(),
+                pinyin: vec!["bar".to_string()],
+                meaning: "foobar".to_string(),
+            },
+        ];
 
-        timelimit: usize,
+        let exam = hanzi_exam(cards, 1, Some(0), 5);
 
-        #[serde(rename = "hskLevel")]
-        hsk_level: usize,
+        let options = ExamOptions {
+            practice: true,
+            ..ExamOptions::default()
+        };
+        let (mut examiner, _clock) = start(&exam, &options);
+        assert!(examiner.is_practice());
 
-        deck: Vec<JsonCard>,
+        for _ in 0..exam.deck.len() {
+            examiner.tick().unwrap_next_question();
+            // Wrong answers don't end a practice exam.
+            examiner.answer("wrong");
+        }
+
+        match examiner.tick() {
+            TickResult::Finished(score) => {
+                assert_eq!(score.graded_questions.len(), 2);
+                assert!(!score.result.passed);
+                assert!(score.result.completed);
+            },
+            tick_result => panic!("Expected TickResult::Finished(_), but found {:?}", tick_result),
+        }
     }
 
-    #[derive(Deserialize, Debug)]
-    struct JsonCard {
-        question: String,
+    fn numbered_exam(num_questions: usize, max_wrong: Option<usize>, timelimit: usize) -> Exam {
+        let cards = (0..num_questions)
+            .map(|i| Card {
+                hanzi: format!("q{}", i),
+                pinyin: vec![format!("a{}", i)],
+                meaning: String::new(),
+            })
+            .collect();
 
-        #[serde(rename = "validAnswers")]
-        valid_answers: Vec<String>,
-        meaning: String,
+        hanzi_exam(cards, num_questions, max_wrong, timelimit)
     }
 
-    fn convert_card(json_card: &JsonCard) -> Question {
-        let JsonCard {
-            question,
-            valid_answers,
-            meaning,
-        } = json_card;
+    fn right(question: &Question) -> String {
+        question.valid_answers[0].clone()
+    }
 
-        Question {
-            question: question.clone(),
-            valid_answers: valid_answers.clone(),
-            meaning: meaning.clone(),
+    fn unwrap_finished(tick_result: TickResult) -> ExamScore {
+        if let TickResult::Finished(score) = tick_result {
+            score
+        } else {
+            panic!("Expected TickResult::Finished(_), but found {:?}", tick_result);
         }
     }
 
-    fn convert_exam(json_exam: &JsonExam) -> Exam {
-        let JsonExam {
-            name,
-            num_questions,
-            max_wrong,
-            timelimit,
-            hsk_level,
-            deck,
-        } = json_exam;
+    fn assert_percent(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.01, "Expected {}%, but found {}%", expected, actual);
+    }
 
-        let deck = deck.iter().map(|json_card| convert_card(json_card)).collect::<Vec<_>>();
-        let max_wrong = Some(*max_wrong);
-        let timelimit = *timelimit * 1000; // convert from s to ms
+    #[test]
+    fn passed_when_all_correct() {
+        let exam = numbered_exam(3, Some(0), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        for _ in 0..3 {
+            let question = examiner.tick().unwrap_next_question();
+            let (_question, answer) = examiner.answer(&right(&question)).unwrap();
+            assert!(answer.is_correct());
+        }
 
-        Exam {
-            name: name.to_owned(),
-            deck,
-            num_questions: *num_questions,
-            max_wrong,
-            timelimit,
-            hsk_level: *hsk_level,
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.passed);
+        assert!(result.completed);
+        assert!(!result.failed_too_many_wrong);
+        assert!(!result.gave_up);
+        assert!(!result.timed_out);
+        assert_eq!(result.num_questions, 3);
+        assert_eq!(result.num_answered, 3);
+        assert_eq!(result.num_correct, 3);
+        assert_eq!(result.mistakes, 0.0);
+        assert_percent(result.percent_correct, 100.0);
+        assert_percent(result.percent_answered, 100.0);
+    }
+
+    #[test]
+    fn passed_with_max_wrong_mistakes() {
+        let exam = numbered_exam(3, Some(1), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        examiner.answer("wrong").unwrap();
+        for _ in 0..2 {
+            let question = examiner.tick().unwrap_next_question();
+            examiner.answer(&right(&question)).unwrap();
         }
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.passed);
+        assert!(result.completed);
+        assert_eq!(result.mistakes, 1.0);
+        assert_percent(result.percent_correct, 200.0 / 3.0);
     }
 
+    #[test]
+    fn failed_as_soon_as_too_many_wrong() {
+        let exam = numbered_exam(3, Some(1), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        for _ in 0..2 {
+            examiner.tick().unwrap_next_question();
+            examiner.answer("wrong").unwrap();
+        }
 
-    pub fn load_exam(exam_name: &str) -> Exam {
-        let mut exams: HashMap<String, Exam> = HashMap::new();
+        let score = unwrap_finished(examiner.tick());
+        let result = score.result;
+        assert!(!result.passed);
+        assert!(result.failed_too_many_wrong);
+        assert!(!result.completed);
+        assert_eq!(result.num_answered, 2);
+        assert_percent(result.percent_correct, 0.0);
+        assert_percent(result.percent_answered, 200.0 / 3.0);
+        assert_eq!(score.graded_questions.len(), 2);
+    }
 
-        let exam_json = std::fs::read_to_string("data/exams.json").unwrap();
-        let exam_data: serde_json::Value = serde_json::from_str(&exam_json).unwrap();
-        for exam in exam_data["data"]["exams"].as_array().unwrap().iter() {
-            let json_exam: JsonExam = JsonExam::deserialize(exam).unwrap();
-            let exam_name = json_exam.name.clone();
-            println!("Exam found: {}", json_exam.name);
-            let exam = convert_exam(&json_exam);
-            exams.insert(exam_name, exam);
+    #[test]
+    fn failed_too_many_wrong_on_last_question() {
+        let exam = numbered_exam(2, Some(0), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        let question = examiner.tick().unwrap_next_question();
+        examiner.answer(&right(&question)).unwrap();
+        examiner.tick().unwrap_next_question();
+        examiner.answer("wrong").unwrap();
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(!result.passed);
+        assert!(result.failed_too_many_wrong);
+        assert!(!result.completed);
+    }
+
+    #[test]
+    fn passed_without_max_wrong() {
+        let exam = numbered_exam(2, None, 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        for _ in 0..2 {
+            examiner.tick().unwrap_next_question();
+            examiner.answer("wrong").unwrap();
         }
 
-        exams[exam_name].clone()
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.passed);
+        assert!(result.completed);
+        assert_eq!(result.mistakes, 2.0);
+        assert_percent(result.percent_correct, 0.0);
+    }
+
+    #[test]
+    fn gave_up_on_first_question() {
+        let exam = numbered_exam(3, Some(1), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        examiner.give_up();
+
+        let score = unwrap_finished(examiner.tick());
+        let result = score.result;
+        assert!(!result.passed);
+        assert!(result.gave_up);
+        assert!(!result.completed);
+        assert_eq!(result.num_answered, 0);
+        assert_eq!(result.mistakes, 0.0);
+        assert_percent(result.percent_correct, 0.0);
+        assert_percent(result.percent_answered, 0.0);
+        assert!(matches!(score.graded_questions[..], [(_, Answer::Quit)]));
+    }
+
+    #[test]
+    fn gave_up_between_questions() {
+        let exam = numbered_exam(3, Some(1), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        let question = examiner.tick().unwrap_next_question();
+        examiner.answer(&right(&question)).unwrap();
+        examiner.give_up();
+
+        let score = unwrap_finished(examiner.tick());
+        assert!(score.result.gave_up);
+        assert_eq!(score.result.num_answered, 1);
+        assert_percent(score.result.percent_correct, 100.0 / 3.0);
+        assert!(matches!(score.graded_questions[..], [(_, Answer::Correct(_)), (_, Answer::Quit)]));
+    }
+
+    #[test]
+    fn gave_up_before_first_question() {
+        let exam = numbered_exam(3, Some(1), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        examiner.give_up();
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.gave_up);
+        assert_eq!(result.question_millis, vec![0]);
+    }
+
+    #[test]
+    fn give_up_after_finished_does_nothing() {
+        let exam = numbered_exam(1, Some(0), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        let question = examiner.tick().unwrap_next_question();
+        examiner.answer(&right(&question)).unwrap();
+        examiner.give_up();
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.passed);
+        assert!(!result.gave_up);
+    }
+
+    #[test]
+    fn timeout_is_a_mistake() {
+        let exam = numbered_exam(2, Some(1), 250);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        for _ in 0..2 {
+            clock.advance(100);
+            assert!(matches!(examiner.tick(), TickResult::Nothing));
+        }
+        clock.advance(49);
+        assert!(matches!(examiner.tick(), TickResult::Nothing));
+        assert_eq!(examiner.time_left(), 1);
+        clock.advance(1);
+        assert!(matches!(examiner.tick(), TickResult::Timeout));
+
+        // No answers are accepted during the pause.
+        assert!(examiner.answer("a0").is_none());
+        assert!(examiner.answer("a1").is_none());
+        clock.advance(999);
+        assert!(matches!(examiner.tick(), TickResult::Pause));
+        clock.advance(1);
+
+        let question = examiner.tick().unwrap_next_question();
+        examiner.answer(&right(&question)).unwrap();
+
+        let score = unwrap_finished(examiner.tick());
+        let result = score.result;
+        assert!(result.passed);
+        assert_eq!(result.mistakes, 1.0);
+        assert_eq!(result.num_answered, 2);
+        assert_eq!(result.question_millis, vec![250, 0]);
+        assert!(matches!(score.graded_questions[0], (_, Answer::Timeout)));
+    }
+
+    #[test]
+    fn gave_up_during_pause() {
+        let exam = numbered_exam(2, None, 100);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        clock.advance(100);
+        assert!(matches!(examiner.tick(), TickResult::Timeout));
+        examiner.give_up();
+
+        let score = unwrap_finished(examiner.tick());
+        assert!(score.result.gave_up);
+        assert!(matches!(score.graded_questions[..], [(_, Answer::Timeout), (_, Answer::Quit)]));
+    }
+
+    #[test]
+    fn practice_ends_on_timeout() {
+        let exam = numbered_exam(3, Some(0), 5000);
+        let options = ExamOptions {
+            practice: true,
+            ..ExamOptions::default()
+        };
+        let (mut examiner, clock) = start(&exam, &options);
+
+        examiner.tick().unwrap_next_question();
+        clock.advance(29999);
+        assert!(matches!(examiner.tick(), TickResult::Nothing));
+        clock.advance(1);
+        assert!(matches!(examiner.tick(), TickResult::Timeout));
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(!result.passed);
+        assert!(result.timed_out);
+        assert!(!result.completed);
+        assert_eq!(result.question_millis, vec![30000]);
+    }
+
+    #[test]
+    fn answers_only_accepted_while_asking() {
+        let exam = numbered_exam(1, Some(0), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        assert!(examiner.answer("a0").is_none());
+
+        let question = examiner.tick().unwrap_next_question();
+        assert!(examiner.answer(&right(&question)).is_some());
+        assert!(examiner.answer(&right(&question)).is_none());
+
+        unwrap_finished(examiner.tick());
+        assert!(examiner.answer(&right(&question)).is_none());
+    }
+
+    #[test]
+    fn late_answers_refused() {
+        let exam = numbered_exam(1, Some(0), 5000);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+
+        let question = examiner.tick().unwrap_next_question();
+        clock.advance(5000);
+
+        // The deadline passed before the answer arrived, even though tick() hasn't noticed yet.
+        assert!(examiner.answer(&right(&question)).is_none());
+        assert!(matches!(examiner.tick(), TickResult::Timeout));
+    }
+
+    #[test]
+    fn late_ticks_do_not_drift() {
+        let exam = numbered_exam(2, None, 1000);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        clock.advance(1500);
+        assert!(matches!(examiner.tick(), TickResult::Timeout));
+
+        // The pause started at the deadline, not when the timeout was noticed.
+        clock.advance(400);
+        assert!(matches!(examiner.tick(), TickResult::Pause));
+        clock.advance(100);
+        examiner.tick().unwrap_next_question();
+        assert_eq!(examiner.time_left(), 1000);
+    }
+
+    #[test]
+    fn finished_is_final() {
+        let exam = numbered_exam(1, Some(0), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        examiner.answer("wrong").unwrap();
+
+        for _ in 0..3 {
+            let result = unwrap_finished(examiner.tick()).result;
+            assert!(result.failed_too_many_wrong);
+        }
+    }
+
+    #[test]
+    fn empty_exam_is_completed() {
+        let exam = numbered_exam(0, Some(0), 5000);
+        let (mut examiner, _clock) = start(&exam, &ExamOptions::default());
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.passed);
+        assert!(result.completed);
+        assert_percent(result.percent_correct, 100.0);
+        assert_percent(result.percent_answered, 100.0);
+    }
+
+    #[test]
+    fn question_millis() {
+        let exam = numbered_exam(2, Some(0), 5000);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+
+        let question = examiner.tick().unwrap_next_question();
+        clock.advance(120);
+        examiner.tick();
+        clock.advance(80);
+        examiner.answer(&right(&question)).unwrap();
+
+        let question = examiner.tick().unwrap_next_question();
+        examiner.answer(&right(&question)).unwrap();
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert_eq!(result.question_millis, vec![200, 0]);
+    }
+
+    #[test]
+    fn resume_after_serializing() {
+        let exam = numbered_exam(3, Some(0), 5000);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+        let (mut uninterrupted, _clock) = start(&exam, &ExamOptions::default());
+
+        let question = examiner.tick().unwrap_next_question();
+        examiner.answer(&right(&question)).unwrap();
+        let question = examiner.tick().unwrap_next_question();
+        clock.advance(4000);
+
+        let json = serde_json::to_string(&examiner).unwrap();
+        let mut resumed: Examiner = serde_json::from_str(&json).unwrap();
+        let clock = SimulatedClock::new();
+        resumed.resume(Box::new(clock.clone()));
+
+        assert_eq!(resumed.seed(), 0);
+        assert_eq!(resumed.open_question().unwrap().question, question.question);
+        assert_eq!(resumed.time_left(), 5000);
+
+        resumed.answer(&right(&question)).unwrap();
+        let question = resumed.tick().unwrap_next_question();
+        assert!(resumed.open_question().is_some());
+        resumed.answer(&right(&question)).unwrap();
+        assert!(resumed.open_question().is_none());
+
+        // The shuffled order survives.
+        for _ in 0..3 {
+            let question = uninterrupted.tick().unwrap_next_question();
+            uninterrupted.answer(&right(&question)).unwrap();
+        }
+        let questions = |score: ExamScore| -> Vec<String> {
+            score.graded_questions.into_iter().map(|(question, _answer)| question.question).collect()
+        };
+        let resumed_score = unwrap_finished(resumed.tick());
+        assert!(resumed_score.result.passed);
+        assert_eq!(questions(resumed_score), questions(unwrap_finished(uninterrupted.tick())));
+    }
+
+    #[test]
+    fn resume_skips_pause() {
+        let exam = numbered_exam(2, None, 100);
+        let (mut examiner, clock) = start(&exam, &ExamOptions::default());
+
+        examiner.tick().unwrap_next_question();
+        clock.advance(100);
+        assert!(matches!(examiner.tick(), TickResult::Timeout));
+
+        let json = serde_json::to_string(&examiner).unwrap();
+        let mut resumed: Examiner = serde_json::from_str(&json).unwrap();
+        resumed.resume(Box::new(SimulatedClock::new()));
+        assert!(resumed.open_question().is_none());
+        resumed.tick().unwrap_next_question();
+    }
+
+    #[test]
+    fn timelimit_override() {
+        let exam = numbered_exam(2, None, 5000);
+        for practice in [false, true] {
+            let options = ExamOptions {
+                practice,
+                timelimit: Some(1000),
+                ..ExamOptions::default()
+            };
+            let (mut examiner, clock) = start(&exam, &options);
+            assert_eq!(examiner.timelimit(), 1000);
+
+            examiner.tick().unwrap_next_question();
+            clock.advance(1000);
+            assert!(matches!(examiner.tick(), TickResult::Timeout));
+            assert_eq!(examiner.num_answered(), 1);
+            assert_eq!(examiner.mistakes(), 1.0);
+        }
+    }
+
+    #[test]
+    fn wrong_tone_is_half_credit() {
+        let cards = vec![Card {
+            hanzi: "我".to_string(),
+            pinyin: vec!["wo3".to_string()],
+            meaning: "I".to_string(),
+        }];
+        let exam = hanzi_exam(cards, 1, Some(1), 5000);
+        let options = ExamOptions {
+            tones_lenient: true,
+            ..ExamOptions::default()
+        };
+        let (mut examiner, _clock) = start(&exam, &options);
+
+        examiner.tick().unwrap_next_question();
+        examiner.answer("wo2").unwrap();
+
+        let result = unwrap_finished(examiner.tick()).result;
+        assert!(result.passed);
+        assert_eq!(result.mistakes, 0.5);
+        assert_percent(result.percent_correct, 50.0);
     }
 }
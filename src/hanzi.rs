@@ -0,0 +1,71 @@
+use crate::questions::Card;
+
+/// Returns true if `ch` is a CJK unified ideograph (ie, a hanzi).
+///
+/// CJK punctuation (such as `。` or `，`) and kana are not counted.
+pub fn is_hanzi(ch: char) -> bool {
+    matches!(ch as u32,
+        0x4E00..=0x9FFF     // CJK Unified Ideographs
+        | 0x3400..=0x4DBF   // Extension A
+        | 0x20000..=0x2CEAF // Extensions B - E
+        | 0xF900..=0xFAFF   // Compatibility Ideographs
+        | 0x2F800..=0x2FA1F // Compatibility Ideographs Supplement
+    )
+}
+
+/// Counts the number of hanzi in `text`, including repeats.
+pub fn count_hanzi(text: &str) -> usize {
+    text.chars().filter(|ch| is_hanzi(*ch)).count()
+}
+
+/// Returns each hanzi in `text` once, in order of first appearance.
+pub fn distinct_hanzi(text: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for ch in text.chars().filter(|ch| is_hanzi(*ch)) {
+        let ch = ch.to_string();
+        if !result.contains(&ch) {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/// Computes how many of the distinct hanzi used in the words of `cards` appear in `known`.
+///
+/// Returns `(known, total)`.
+pub fn coverage(known: &[String], cards: &[Card]) -> (usize, usize) {
+    let deck_text: String = cards.iter().map(|card| card.hanzi.as_str()).collect();
+    let deck_hanzi = distinct_hanzi(&deck_text);
+    let known_count = deck_hanzi.iter().filter(|hanzi| known.contains(hanzi)).count();
+    (known_count, deck_hanzi.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn count_mixed_text() {
+        assert_eq!(count_hanzi("我是美国人。I am American."), 5);
+        assert_eq!(count_hanzi("ひらがな and カタカナ"), 0);
+        assert_eq!(count_hanzi(""), 0);
+    }
+
+    #[test]
+    fn distinct_in_order() {
+        assert_eq!(distinct_hanzi("你好，你好！hi 好人"), vec!["你", "好", "人"]);
+    }
+
+    #[test]
+    fn coverage_of_deck() {
+        let card = |hanzi: &str| Card {
+            hanzi: hanzi.to_string(),
+            pinyin: vec![],
+            meaning: String::new(),
+        };
+        let deck = vec![card("你好"), card("好"), card("中国")];
+        let known = vec!["好".to_string(), "国".to_string(), "猫".to_string()];
+        assert_eq!(coverage(&known, &deck), (2, 4));
+        assert_eq!(coverage(&[], &[]), (0, 0));
+    }
+}
@@ -0,0 +1,589 @@
+//! Keeping the event log and the store in step.
+//!
+//! The log (the `events` stream) is the record of what happened, and the store is a projection of
+//! it. Each event is appended to the log first and then applied to the store, and the store keeps a
+//! checkpoint of how far through the log it has got. If the server dies, or the store fails, after
+//! an event is appended but before it is applied, [Journal::catch_up] applies it later.
+//!
+//! Events are recorded concurrently, so events which arrive together may be applied in either
+//! order, and an event which had to wait for catching up is applied after ones logged later. The
+//! store makes sure concurrent changes to one profile, deck or review card don't overwrite each
+//! other.
+//!
+//! Events are applied at least once. A projection skips events it has already applied, so the only
+//! way one is applied twice is if the server dies after applying it but before marking it applied.
+//! Closing that gap in general would take transactions across the two writes, so a change which
+//! mustn't be made twice, like a change to credit, has to note its event in the same write.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use ulid::Ulid;
+
+/// How many entries [Journal::catch_up] reads from the log at a time.
+const CATCH_UP_BATCH: usize = 100;
+
+/// An event, as it is written to the log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedEvent {
+    pub id: Ulid,
+    pub event_type: String,
+    /// The event itself, as JSON.
+    pub event: String,
+    /// The key a client sent to make its request idempotent, if any.
+    pub idempotency_key: Option<String>,
+}
+
+#[async_trait]
+pub trait EventLog: Send + Sync {
+    /// Appends an event, returning its position in the log.
+    async fn append(&self, event: &LoggedEvent) -> Result<String, String>;
+
+    /// Up to `count` entries after `position`, or from the start of the log if it is `None`,
+    /// oldest first. Entries which aren't events the store applies come back as `None`, so the
+    /// checkpoint can still move past them.
+    async fn read_after(&self, position: Option<&str>, count: usize) -> Result<Vec<(String, Option<LoggedEvent>)>, String>;
+
+    /// The position of the newest entry in the log.
+    async fn last_position(&self) -> Result<Option<String>, String>;
+}
+
+#[async_trait]
+pub trait Projection: Send + Sync {
+    /// Applies an event. Returns false, changing nothing, if the event was already applied, or
+    /// another event with the same idempotency key was.
+    async fn apply(&self, event: &LoggedEvent) -> Result<bool, String>;
+
+    /// The position in the log of the last event applied.
+    async fn checkpoint(&self) -> Result<Option<String>, String>;
+
+    async fn save_checkpoint(&self, position: &str) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recorded {
+    Applied,
+    /// The projection had already applied an event with the same ID or idempotency key.
+    Duplicate,
+    /// The event is in the log, but couldn't be applied yet. [Journal::catch_up] will apply it.
+    Pending(String),
+}
+
+/// The event applied for an earlier request with the same idempotency key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EarlierRequest {
+    pub event_id: String,
+    pub event_type: String,
+}
+
+/// What to tell the client who asked for an event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// With the ID of the event which happened: the request's own, or an earlier request's.
+    Succeeded(String),
+    Failed(String),
+}
+
+impl Reply {
+    /// The reply to a retry of `earlier`. A key can't be used again for a different type of event.
+    pub fn replay(earlier: &EarlierRequest, event_type: &str) -> Reply {
+        if earlier.event_type != event_type {
+            return Reply::Failed(format!("Idempotency key was already used for a {}", earlier.event_type));
+        }
+        Reply::Succeeded(earlier.event_id.clone())
+    }
+
+    /// The reply once [Journal::record] is done with an event. `earlier` is what was applied under
+    /// the event's idempotency key, if anything was.
+    pub fn recorded(event: &LoggedEvent, recorded: &Result<Recorded, String>, earlier: Option<&EarlierRequest>) -> Reply {
+        match recorded {
+            Err(e) => Reply::Failed(format!("Could not record event: {}", e)),
+            // A pending event is logged, so it happened. It's applied when the store next catches up.
+            Ok(Recorded::Applied) | Ok(Recorded::Pending(_)) => Reply::Succeeded(event.id.to_string()),
+            Ok(Recorded::Duplicate) => match earlier {
+                // A request with the same key got in first.
+                Some(earlier) => Reply::replay(earlier, &event.event_type),
+                None => Reply::Failed(format!("Duplicate event ID: {}", event.id)),
+            },
+        }
+    }
+}
+
+/// An event which has been claimed for recording, and isn't applied yet.
+struct Claim {
+    idempotency_key: Option<String>,
+    /// Its recorder gave up on applying it, and left it to [Journal::catch_up].
+    pending: bool,
+}
+
+/// Holds a [Claim] while its event is recorded, and gives it up however recording ends, even if
+/// the recorder panics.
+struct ClaimGuard<'a> {
+    claims: &'a Mutex<HashMap<Ulid, Claim>>,
+    id: Ulid,
+    appended: bool,
+    applied: bool,
+}
+
+impl Drop for ClaimGuard<'_> {
+    fn drop(&mut self) {
+        let mut claims = self.claims.lock().unwrap_or_else(|e| e.into_inner());
+        if self.appended && !self.applied {
+            // The event is in the log, so it's left for catching up to apply.
+            if let Some(claim) = claims.get_mut(&self.id) {
+                claim.pending = true;
+            }
+        } else {
+            claims.remove(&self.id);
+        }
+    }
+}
+
+pub struct Journal<L, P> {
+    log: L,
+    projection: P,
+    claims: Mutex<HashMap<Ulid, Claim>>,
+    catching_up: tokio::sync::Mutex<()>,
+}
+
+impl<L: EventLog + 'static, P: Projection + 'static> Journal<L, P> {
+    pub fn new(log: L, projection: P) -> Journal<L, P> {
+        Journal {
+            log,
+            projection,
+            claims: Mutex::new(HashMap::new()),
+            catching_up: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Appends an event to the log and applies it.
+    ///
+    /// Returns an error if the event couldn't be appended, in which case nothing has changed, or
+    /// if an event with the same ID or idempotency key is still being recorded.
+    ///
+    /// Recording runs on a task of its own, so it is seen through even if the returned future is
+    /// dropped, say because the client went away.
+    pub async fn record(self: &Arc<Self>, event: &LoggedEvent) -> Result<Recorded, String> {
+        let journal = Arc::clone(self);
+        let event = event.clone();
+        tokio::spawn(async move { journal.record_now(&event).await })
+            .await
+            .map_err(|e| format!("Recording stopped part way: {}", e))?
+    }
+
+    async fn record_now(&self, event: &LoggedEvent) -> Result<Recorded, String> {
+        let mut claim = self.claim(event)?;
+
+        self.log.append(event).await?;
+        claim.appended = true;
+
+        let recorded = match self.projection.apply(event).await {
+            Ok(true) => Recorded::Applied,
+            Ok(false) => Recorded::Duplicate,
+            Err(e) => return Ok(Recorded::Pending(e)),
+        };
+        claim.applied = true;
+        Ok(recorded)
+    }
+
+    /// Applies every event in the log after the projection's checkpoint, and moves the checkpoint
+    /// up. Returns how many were applied.
+    ///
+    /// Catching up stops short of any event still being recorded, and leaves the rest for next
+    /// time. A projection with no checkpoint yet starts from the end of the log, since everything
+    /// before it was applied before checkpoints were kept.
+    pub async fn catch_up(&self) -> Result<usize, String> {
+        let _catching_up = self.catching_up.lock().await;
+
+        let mut position = match self.projection.checkpoint().await? {
+            Some(position) => Some(position),
+            None => {
+                let last = self.log.last_position().await?;
+                if let Some(last) = &last {
+                    self.projection.save_checkpoint(last).await?;
+                }
+                last
+            },
+        };
+
+        let mut applied = 0;
+        loop {
+            let entries = self.log.read_after(position.as_deref(), CATCH_UP_BATCH).await?;
+            if entries.is_empty() {
+                return Ok(applied);
+            }
+
+            for (entry_position, event) in entries.into_iter() {
+                if let Some(event) = event {
+                    if self.is_recording(event.id) {
+                        return Ok(applied);
+                    }
+                    if self.projection.apply(&event).await? {
+                        applied += 1;
+                    }
+                    self.release(event.id);
+                }
+                self.projection.save_checkpoint(&entry_position).await?;
+                position = Some(entry_position);
+            }
+        }
+    }
+
+    /// Claims an event for recording. Only one event with a given ID or idempotency key is recorded
+    /// at a time, so the projection never has two copies of one to apply at once.
+    fn claim(&self, event: &LoggedEvent) -> Result<ClaimGuard<'_>, String> {
+        let mut claims = self.claims.lock().unwrap();
+        let taken = claims.contains_key(&event.id) || claims.values().any(|claim| {
+            claim.idempotency_key.is_some() && claim.idempotency_key == event.idempotency_key
+        });
+        if taken {
+            return Err("An event with the same ID or idempotency key is still being recorded".to_string());
+        }
+
+        claims.insert(event.id, Claim {
+            idempotency_key: event.idempotency_key.clone(),
+            pending: false,
+        });
+        Ok(ClaimGuard {
+            claims: &self.claims,
+            id: event.id,
+            appended: false,
+            applied: false,
+        })
+    }
+
+    fn release(&self, id: Ulid) {
+        self.claims.lock().unwrap().remove(&id);
+    }
+
+    /// True if the event's recorder is still working on it.
+    fn is_recording(&self, id: Ulid) -> bool {
+        matches!(self.claims.lock().unwrap().get(&id), Some(claim) if !claim.pending)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct FakeLog {
+        entries: Mutex<Vec<(String, Option<LoggedEvent>)>>,
+        fail_append: AtomicBool,
+    }
+
+    #[async_trait]
+    impl EventLog for FakeLog {
+        async fn append(&self, event: &LoggedEvent) -> Result<String, String> {
+            if self.fail_append.load(Ordering::SeqCst) {
+                return Err("Redis is down".to_string());
+            }
+            let mut entries = self.entries.lock().unwrap();
+            let position = format!("{}-0", entries.len());
+            entries.push((position.clone(), Some(event.clone())));
+            Ok(position)
+        }
+
+        async fn read_after(&self, position: Option<&str>, count: usize) -> Result<Vec<(String, Option<LoggedEvent>)>, String> {
+            let entries = self.entries.lock().unwrap();
+            let start = match position {
+                Some(position) => entries.iter().position(|(p, _)| p == position).unwrap() + 1,
+                None => 0,
+            };
+            Ok(entries.iter().skip(start).take(count).cloned().collect())
+        }
+
+        async fn last_position(&self) -> Result<Option<String>, String> {
+            Ok(self.entries.lock().unwrap().last().map(|(position, _)| position.clone()))
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeProjection {
+        applied: Mutex<Vec<LoggedEvent>>,
+        checkpoint: Mutex<Option<String>>,
+        /// Held to keep events from being applied until it's let go.
+        hold: tokio::sync::Mutex<()>,
+        /// Applying these events fails, as if the server died between appending and applying them.
+        failing: Mutex<Vec<Ulid>>,
+        fail_checkpoints: AtomicBool,
+    }
+
+    impl FakeProjection {
+        fn applied(&self) -> Vec<Ulid> {
+            self.applied.lock().unwrap().iter().map(|event| event.id).collect()
+        }
+
+        fn saved_checkpoint(&self) -> Option<String> {
+            self.checkpoint.lock().unwrap().clone()
+        }
+
+        fn fail(&self, events: &[&LoggedEvent]) {
+            *self.failing.lock().unwrap() = events.iter().map(|event| event.id).collect();
+        }
+    }
+
+    #[async_trait]
+    impl Projection for FakeProjection {
+        async fn apply(&self, event: &LoggedEvent) -> Result<bool, String> {
+            let _hold = self.hold.lock().await;
+            if self.failing.lock().unwrap().contains(&event.id) {
+                return Err("Mongo is down".to_string());
+            }
+            let mut applied = self.applied.lock().unwrap();
+            let duplicate = applied.iter().any(|applied| {
+                applied.id == event.id || (applied.idempotency_key.is_some() && applied.idempotency_key == event.idempotency_key)
+            });
+            if duplicate {
+                return Ok(false);
+            }
+            applied.push(event.clone());
+            Ok(true)
+        }
+
+        async fn checkpoint(&self) -> Result<Option<String>, String> {
+            Ok(self.saved_checkpoint())
+        }
+
+        async fn save_checkpoint(&self, position: &str) -> Result<(), String> {
+            if self.fail_checkpoints.load(Ordering::SeqCst) {
+                return Err("Mongo is down".to_string());
+            }
+            *self.checkpoint.lock().unwrap() = Some(position.to_string());
+            Ok(())
+        }
+    }
+
+    fn event(millis: u64) -> LoggedEvent {
+        LoggedEvent {
+            id: Ulid::from_parts(millis, 0),
+            event_type: "ComradeHonored".to_string(),
+            event: "{}".to_string(),
+            idempotency_key: None,
+        }
+    }
+
+    fn keyed(millis: u64, key: &str) -> LoggedEvent {
+        LoggedEvent {
+            idempotency_key: Some(key.to_string()),
+            ..event(millis)
+        }
+    }
+
+    fn started() -> Arc<Journal<FakeLog, FakeProjection>> {
+        let projection = FakeProjection {
+            checkpoint: Mutex::new(Some("0-0".to_string())),
+            ..FakeProjection::default()
+        };
+        let log = FakeLog {
+            entries: Mutex::new(vec![("0-0".to_string(), None)]),
+            ..FakeLog::default()
+        };
+        Arc::new(Journal::new(log, projection))
+    }
+
+    #[tokio::test]
+    async fn records() {
+        let journal = started();
+        assert_eq!(journal.record(&event(1)).await, Ok(Recorded::Applied));
+        assert_eq!(journal.projection.applied(), vec![event(1).id]);
+
+        // Only catching up moves the checkpoint, since events may be applied out of order.
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("0-0"));
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("1-0"));
+    }
+
+    #[tokio::test]
+    async fn append_fails() {
+        let journal = started();
+        journal.log.fail_append.store(true, Ordering::SeqCst);
+        assert!(journal.record(&event(1)).await.is_err());
+        assert_eq!(journal.log.entries.lock().unwrap().len(), 1);
+        assert!(journal.projection.applied().is_empty());
+        assert_eq!(journal.catch_up().await, Ok(0));
+
+        // The event can be recorded once the log is back.
+        journal.log.fail_append.store(false, Ordering::SeqCst);
+        assert_eq!(journal.record(&event(1)).await, Ok(Recorded::Applied));
+    }
+
+    #[tokio::test]
+    async fn apply_fails() {
+        let journal = started();
+        journal.projection.fail(&[&event(1)]);
+        let recorded = journal.record(&event(1)).await;
+        assert!(matches!(recorded, Ok(Recorded::Pending(_))));
+        assert_eq!(journal.log.entries.lock().unwrap().len(), 2);
+        assert!(journal.projection.applied().is_empty());
+
+        // Catching up keeps trying, and the event is applied exactly once.
+        assert!(journal.catch_up().await.is_err());
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("0-0"));
+        journal.projection.fail(&[]);
+        assert_eq!(journal.catch_up().await, Ok(1));
+        assert_eq!(journal.projection.applied(), vec![event(1).id]);
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("1-0"));
+        assert_eq!(journal.catch_up().await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn retried_while_pending() {
+        // A client retries a request whose event is logged, but not yet applied.
+        let journal = started();
+        journal.projection.fail(&[&keyed(1, "key")]);
+        assert!(matches!(journal.record(&keyed(1, "key")).await, Ok(Recorded::Pending(_))));
+        assert!(journal.record(&keyed(2, "key")).await.is_err());
+
+        journal.projection.fail(&[]);
+        assert_eq!(journal.catch_up().await, Ok(1));
+        assert_eq!(journal.record(&keyed(3, "key")).await, Ok(Recorded::Duplicate));
+        assert_eq!(journal.projection.applied(), vec![keyed(1, "key").id]);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_fails() {
+        let journal = started();
+        assert_eq!(journal.record(&event(1)).await, Ok(Recorded::Applied));
+        journal.projection.fail_checkpoints.store(true, Ordering::SeqCst);
+        assert!(journal.catch_up().await.is_err());
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("0-0"));
+
+        // Catching up finds the event already applied, and only moves the checkpoint.
+        journal.projection.fail_checkpoints.store(false, Ordering::SeqCst);
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert_eq!(journal.projection.applied(), vec![event(1).id]);
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("1-0"));
+    }
+
+    #[tokio::test]
+    async fn catch_up_fails_part_way() {
+        // Three events were appended while the store was down.
+        let journal = started();
+        for millis in 1..=3 {
+            journal.log.append(&event(millis)).await.unwrap();
+        }
+
+        // Catching up gets through the first, then stops rather than skip ahead.
+        journal.projection.fail(&[&event(2)]);
+        assert!(journal.catch_up().await.is_err());
+        assert_eq!(journal.projection.applied(), vec![event(1).id]);
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("1-0"));
+
+        journal.projection.fail(&[]);
+        assert_eq!(journal.catch_up().await, Ok(2));
+        assert_eq!(journal.projection.applied(), vec![event(1).id, event(2).id, event(3).id]);
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("3-0"));
+    }
+
+    #[tokio::test]
+    async fn catch_up_waits_for_recorders() {
+        // One event is appended but still being applied by its recorder, and a later one is done.
+        let journal = started();
+        let claim = journal.claim(&event(1)).unwrap();
+        journal.log.append(&event(1)).await.unwrap();
+        assert_eq!(journal.record(&event(2)).await, Ok(Recorded::Applied));
+
+        // Catching up leaves the first to its recorder, and the checkpoint stays before it.
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert_eq!(journal.projection.applied(), vec![event(2).id]);
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("0-0"));
+
+        journal.projection.apply(&event(1)).await.unwrap();
+        drop(claim);
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("2-0"));
+    }
+
+    #[tokio::test]
+    async fn dropped_while_recording() {
+        // The client goes away while its event is being applied.
+        let journal = started();
+        let hold = journal.projection.hold.lock().await;
+        let event = keyed(1, "key");
+        let recording = journal.record(&event);
+        assert!(tokio::time::timeout(Duration::from_millis(10), recording).await.is_err());
+
+        // Recording carries on without it, and gives up its claim once it's done.
+        drop(hold);
+        while !journal.claims.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(journal.projection.applied(), vec![event.id]);
+
+        // So the client's retry isn't turned away, and catching up isn't held up.
+        assert_eq!(journal.record(&keyed(2, "key")).await, Ok(Recorded::Duplicate));
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("2-0"));
+    }
+
+    #[tokio::test]
+    async fn retried_request() {
+        // A client retries a request which was applied, but whose reply was lost.
+        let journal = started();
+        let first = keyed(1, "key");
+        let recorded = journal.record(&first).await;
+        assert_eq!(Reply::recorded(&first, &recorded, None), Reply::Succeeded(first.id.to_string()));
+
+        let retry = keyed(2, "key");
+        let recorded = journal.record(&retry).await;
+        assert_eq!(recorded, Ok(Recorded::Duplicate));
+        let earlier = EarlierRequest {
+            event_id: first.id.to_string(),
+            event_type: first.event_type.clone(),
+        };
+        assert_eq!(Reply::recorded(&retry, &recorded, Some(&earlier)), Reply::Succeeded(first.id.to_string()));
+        assert_eq!(journal.projection.applied(), vec![first.id]);
+    }
+
+    #[test]
+    fn replies() {
+        let event = keyed(1, "key");
+        let earlier = EarlierRequest {
+            event_id: Ulid::from_parts(0, 0).to_string(),
+            event_type: "ComradeDishonored".to_string(),
+        };
+        assert_eq!(
+            Reply::recorded(&event, &Ok(Recorded::Duplicate), Some(&earlier)),
+            Reply::Failed("Idempotency key was already used for a ComradeDishonored".to_string()),
+        );
+        assert_eq!(
+            Reply::recorded(&event, &Ok(Recorded::Duplicate), None),
+            Reply::Failed(format!("Duplicate event ID: {}", event.id)),
+        );
+        assert_eq!(
+            Reply::recorded(&event, &Ok(Recorded::Pending("Mongo is down".to_string())), None),
+            Reply::Succeeded(event.id.to_string()),
+        );
+        assert!(matches!(Reply::recorded(&event, &Err("Redis is down".to_string()), None), Reply::Failed(_)));
+    }
+
+    #[tokio::test]
+    async fn duplicate() {
+        let journal = started();
+        journal.record(&event(1)).await.unwrap();
+        assert_eq!(journal.record(&event(1)).await, Ok(Recorded::Duplicate));
+        assert_eq!(journal.projection.applied().len(), 1);
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("2-0"));
+    }
+
+    #[tokio::test]
+    async fn new_projection() {
+        // Everything already in the log was applied before there were checkpoints.
+        let log = FakeLog::default();
+        log.append(&event(1)).await.unwrap();
+        log.entries.lock().unwrap().push(("1-0".to_string(), None));
+        let journal = Arc::new(Journal::new(log, FakeProjection::default()));
+
+        assert_eq!(journal.catch_up().await, Ok(0));
+        assert!(journal.projection.applied().is_empty());
+        assert_eq!(journal.projection.saved_checkpoint().as_deref(), Some("1-0"));
+
+        assert_eq!(journal.record(&event(3)).await, Ok(Recorded::Applied));
+        assert_eq!(journal.projection.applied(), vec![event(3).id]);
+    }
+}
@@ -4,3 +4,18 @@ pub mod api;
 pub mod command_parser;
 pub mod messages;
 pub mod draw;
+pub mod hanzi;
+pub mod dictionary;
+pub mod review;
+pub mod pinyin;
+pub mod clock;
+pub mod questions;
+pub mod decks;
+pub mod race;
+pub mod stats;
+pub mod profile;
+pub mod credit;
+pub mod charts;
+pub mod emoji;
+pub mod journal;
+pub mod versioned;
@@ -1,7 +1,11 @@
 use serenity::prelude::*;
 use serenity::model::prelude::*;
 //use serenity::builder::CreateMessage;
-use crate::exams::{Exam, ExamScore};
+use crate::exams::{Exam, ExamScore, PromptStyle, Question};
+use crate::dictionary::Dictionary;
+use crate::draw::{FontRegistry, Ruby};
+use crate::race::RaceResult;
+use crate::questions::{QuestionType, BLANK};
 
 pub async fn comrade_honored(
     ctx: &Context,
@@ -101,6 +105,109 @@ pub async fn exam_start(
     }).await
 }
 
+/// Asks a question in the style the exam calls for.
+///
+/// For the image styles, only the first line of the prompt (the hanzi, pinyin or meaning) is
+/// drawn. Anything after it, like the options of a multiple choice question, stays as text.
+pub async fn ask_question(
+    ctx: &Context,
+    channel_id: ChannelId,
+    fonts: &FontRegistry,
+    question: &Question,
+    style: PromptStyle,
+) -> Result<Message, SerenityError> {
+    let mut lines = question.question.lines();
+    let prompt = lines.next().unwrap_or_default();
+    let rest: Vec<&str> = lines.collect();
+
+    if style.is_text() {
+        return channel_id.say(&ctx, &question.question).await;
+    }
+
+    // Questions which can't be drawn are still worth asking.
+    let options = crate::draw::RenderOptions::default();
+    let image = match fonts.render(prompt, &options) {
+        Ok(image) => image,
+        Err(_e) => return channel_id.say(&ctx, &question.question).await,
+    };
+    let image = match style {
+        PromptStyle::Distorted => crate::draw::distort(&image, rand::random()),
+        _ => image,
+    };
+    let png = match crate::draw::encode(&image, options.format) {
+        Ok(png) => png,
+        Err(_e) => return channel_id.say(&ctx, &question.question).await,
+    };
+
+    channel_id.send_message(&ctx, |m| {
+        if !rest.is_empty() {
+            m.content(rest.join("\n"));
+        }
+        m.add_file((png.as_slice(), "question.png"))
+    }).await
+}
+
+/// Shows the answer to a question, with the word it was about drawn with pinyin above each hanzi.
+///
+/// Falls back to just the text if there's nothing to draw.
+pub async fn reveal_answer(
+    ctx: &Context,
+    channel_id: ChannelId,
+    fonts: &FontRegistry,
+    dictionary: &Dictionary,
+    question: &Question,
+    text: String,
+) -> Result<Message, SerenityError> {
+    let options = crate::draw::RenderOptions {
+        size: 64.0,
+        ..crate::draw::RenderOptions::default()
+    };
+    let png = answer_ruby(question, dictionary).and_then(|ruby| fonts.draw_ruby(&ruby, &options).ok());
+
+    channel_id.send_message(&ctx, |m| {
+        m.content(text);
+        if let Some(png) = png.as_ref() {
+            m.add_file((png.as_slice(), "answer.png"));
+        }
+        m
+    }).await
+}
+
+/// The word a question is about, paired with its pinyin.
+fn answer_ruby(question: &Question, dictionary: &Dictionary) -> Option<Vec<Ruby>> {
+    let prompt = question.question.lines().next().unwrap_or_default();
+    let answer = question.valid_answers.first()?;
+
+    let ruby = match question.kind {
+        QuestionType::HanziToPinyin => crate::draw::annotate(prompt, answer),
+        QuestionType::PinyinToHanzi => crate::draw::annotate(answer, prompt),
+        QuestionType::HanziToMeaning | QuestionType::MultipleChoice => crate::draw::ruby(prompt, dictionary),
+        QuestionType::MeaningToHanzi => crate::draw::ruby(answer, dictionary),
+        QuestionType::FillInTheBlank => crate::draw::ruby(&prompt.replace(BLANK, answer), dictionary),
+    };
+    Some(ruby)
+}
+
+/// Discord limits embed descriptions to 4096 characters. This leaves room for the "...and N more".
+const MAX_DESCRIPTION_LENGTH: usize = 4000;
+
+/// Joins lines for an embed description, leaving off as many as it takes to fit, and saying how
+/// many were left off.
+fn description_lines(lines: &[String]) -> String {
+    let mut description = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if description.chars().count() + line.chars().count() + 1 > MAX_DESCRIPTION_LENGTH {
+            description.push_str(&format!("...and {} more", lines.len() - i));
+            break;
+        }
+        description.push_str(line);
+        description.push('\n');
+    }
+
+    description
+}
+
 pub async fn exam_results(
     ctx: &Context,
     channel_id: ChannelId,
@@ -109,8 +216,13 @@ pub async fn exam_results(
     let mut lines = Vec::<String>::new();
 
     for (question, answer) in score.graded_questions.iter() {
-        let correct = answer.is_correct();
-        let emoji = if correct { "✅" } else { "❌" };
+        let emoji = if answer.is_correct() {
+            "✅"
+        } else if answer.is_wrong_tone() {
+            "🟨"
+        } else {
+            "❌"
+        };
         let _correct_answer = question.valid_answers[0].clone();
         let question_str = question.question.to_string(); // ljust(longest_answer + 2 "  ");
         // answer_str = answer if correct else f"{answer} → {correct_answer}"
@@ -125,9 +237,140 @@ pub async fn exam_results(
         lines.push(line);
     }
 
+    let result = &score.result;
+    let outcome = if result.passed {
+        "Passed"
+    } else if result.gave_up {
+        "Gave up"
+    } else if result.timed_out {
+        "Timed out"
+    } else if result.failed_too_many_wrong {
+        "Too many wrong"
+    } else {
+        "Finished"
+    };
+    let title = format!("{}: {:.0}%", outcome, result.percent_correct);
+
     channel_id.send_message(&ctx, |m| {
         m.add_embed(|e| {
+            e.title(title);
+            e.description(description_lines(&lines))
+        })
+    }).await
+}
+
+pub async fn race_start(
+    ctx: &Context,
+    channel_id: ChannelId,
+    exam: &Exam,
+    credit: &[u32],
+) -> Result<Message, SerenityError> {
+    channel_id.send_message(&ctx, |m| {
+        m.add_embed(|e| {
+            e.color(0xFFA500u32);
+            e.title("Race is beginning");
+            e.description("First correct answer wins each question. Answer as many times as you like.");
+            e.field("Exam", &exam.name, true);
+            e.field("Questions", exam.num_questions.to_string(), true);
+            let timelimit = format!("{} seconds", (exam.timelimit / 1000));
+            e.field("Time Limit", timelimit, false);
+
+            if !credit.is_empty() {
+                let prizes: Vec<String> = credit.iter().map(|amount| amount.to_string()).collect();
+                e.field("Social credit", prizes.join(" / "), true);
+            }
+
+            e
+        })
+    }).await
+}
+
+pub async fn race_results(
+    ctx: &Context,
+    channel_id: ChannelId,
+    result: &RaceResult,
+) -> Result<Message, SerenityError> {
+    const MEDALS: [&str; 3] = ["🥇", "🥈", "🥉"];
+
+    let mut lines = Vec::<String>::new();
+    for standing in result.standings.iter() {
+        let place = match MEDALS.get(standing.place - 1) {
+            Some(medal) if standing.points > 0 => medal.to_string(),
+            _ => format!("{}.", standing.place),
+        };
+        lines.push(format!(
+            "{} <@{}>　{} points　({} won, best streak {})",
+            place,
+            standing.player,
+            standing.points,
+            standing.wins,
+            standing.best_streak,
+        ));
+    }
+
+    if lines.is_empty() {
+        lines.push("Nobody answered.".to_string());
+    }
+
+    let title = if result.stopped {
+        format!("Race stopped after {} of {} questions", result.num_asked, result.num_questions)
+    } else {
+        "Race finished".to_string()
+    };
+
+    channel_id.send_message(&ctx, |m| {
+        m.add_embed(|e| {
+            e.title(title);
+            e.color(0xFFA500u32);
             e.description(lines.join("\n"))
         })
     }).await
 }
+
+pub async fn mined_words(
+    ctx: &Context,
+    channel_id: ChannelId,
+    words: &[String],
+    dictionary: &Dictionary,
+) -> Result<Message, SerenityError> {
+    let lines: Vec<String> = words.iter().map(|word| match dictionary.lookup(word) {
+        Some(entry) => format!("{}　{}　*{}*", word, entry.pinyin.join(", "), entry.meaning),
+        None => word.to_string(),
+    }).collect();
+    let mut description = description_lines(&lines);
+
+    if words.is_empty() {
+        description.push_str("No words mined yet. Use `!mine <word>` to add one.");
+    }
+
+    channel_id.send_message(&ctx, |m| {
+        m.add_embed(|e| {
+            e
+                .title(format!("Mined Words ({})", words.len()))
+                .description(description)
+                .color(0xFFA500u32)
+        })
+    }).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_descriptions() {
+        let lines: Vec<String> = (0..2500).map(|i| format!("✅　我 {}　*me*", i)).collect();
+        let description = description_lines(&lines);
+        assert!(description.chars().count() <= 4096);
+        assert!(description.ends_with("more"));
+
+        let shown = description.lines().count() - 1;
+        assert_eq!(description.lines().last(), Some(format!("...and {} more", lines.len() - shown).as_str()));
+    }
+
+    #[test]
+    fn short_descriptions() {
+        let lines = vec!["我".to_string(), "你".to_string()];
+        assert_eq!(description_lines(&lines), "我\n你\n");
+    }
+}
@@ -0,0 +1,457 @@
+//! Pinyin normalization.
+//!
+//! Pinyin can be written with tone numbers (`ni3 hao3`) or with tone marks (`nǐhǎo`).
+//! The neutral tone may be written as `5`, `0`, or left off entirely.
+//! The letter `ü` may be written `ü`, `v`, or `u:`.
+//! Syllables may be separated by spaces, apostrophes (`xi'an`), hyphens, or nothing at all.
+//!
+//! Internally, `ü` is always represented as `v`, and the neutral tone as `5`.
+
+/// A tone from 1 to 4, or 5 for the neutral tone.
+pub type Tone = u8;
+
+pub const NEUTRAL_TONE: Tone = 5;
+
+const TONE_MARKS: [(char, char, Tone); 24] = [
+    ('ā', 'a', 1), ('á', 'a', 2), ('ǎ', 'a', 3), ('à', 'a', 4),
+    ('ē', 'e', 1), ('é', 'e', 2), ('ě', 'e', 3), ('è', 'e', 4),
+    ('ī', 'i', 1), ('í', 'i', 2), ('ǐ', 'i', 3), ('ì', 'i', 4),
+    ('ō', 'o', 1), ('ó', 'o', 2), ('ǒ', 'o', 3), ('ò', 'o', 4),
+    ('ū', 'u', 1), ('ú', 'u', 2), ('ǔ', 'u', 3), ('ù', 'u', 4),
+    ('ǖ', 'v', 1), ('ǘ', 'v', 2), ('ǚ', 'v', 3), ('ǜ', 'v', 4),
+];
+
+/// A single pinyin syllable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Syllable {
+    /// The letters of the syllable, without tone, and without any erhua `r`.
+    pub letters: String,
+    pub tone: Tone,
+    /// Whether the syllable takes an erhua `r` suffix.
+    pub erhua: bool,
+}
+
+/// The result of comparing an answer against an expected pinyin reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Match {
+    /// The syllables and tones all match.
+    Exact,
+    /// The syllables match, but at least one tone is wrong.
+    WrongTone,
+    /// The answer doesn't match.
+    NoMatch,
+}
+
+fn split_tone_mark(ch: char) -> Option<(char, Tone)> {
+    TONE_MARKS
+        .iter()
+        .find(|(marked, _, _)| *marked == ch)
+        .map(|(_, base, tone)| (*base, *tone))
+}
+
+fn tone_mark(base: char, tone: Tone) -> char {
+    TONE_MARKS
+        .iter()
+        .find(|(_, b, t)| *b == base && *t == tone)
+        .map(|(marked, _, _)| *marked)
+        .unwrap_or(base)
+}
+
+fn is_vowel(ch: char) -> bool {
+    matches!(ch, 'a' | 'e' | 'i' | 'o' | 'u' | 'v')
+}
+
+/// A string of pinyin broken down into letters, and the positions of everything else.
+#[derive(Debug, Default)]
+struct Parsed {
+    letters: Vec<char>,
+    /// Tone marks, as (index of the marked letter, tone).
+    marks: Vec<(usize, Tone)>,
+    /// Tone numbers, as (number of letters before the digit, tone).
+    digits: Vec<(usize, Tone)>,
+    /// Explicit syllable breaks, as the number of letters before the break.
+    breaks: Vec<usize>,
+}
+
+/// Parses a pinyin string. Returns `None` if it contains anything which isn't pinyin.
+fn parse(text: &str) -> Option<Parsed> {
+    let mut parsed = Parsed::default();
+    let mut chars = text.trim().chars().flat_map(char::to_lowercase).peekable();
+
+    while let Some(ch) = chars.next() {
+        let position = parsed.letters.len();
+        match ch {
+            ' ' | '\'' | '’' | '-' => parsed.breaks.push(position),
+            '0'..='5' => {
+                let tone = ch.to_digit(10).unwrap() as Tone;
+                let tone = if tone == 0 { NEUTRAL_TONE } else { tone };
+                parsed.digits.push((position, tone));
+            },
+            'ü' => parsed.letters.push('v'),
+            'u' if chars.peek() == Some(&':') => {
+                chars.next();
+                parsed.letters.push('v');
+            },
+            'a'..='z' => parsed.letters.push(ch),
+            // Combining diacritics, for input methods which don't compose.
+            '\u{0304}' | '\u{0301}' | '\u{030C}' | '\u{0300}' => {
+                let tone = match ch {
+                    '\u{0304}' => 1,
+                    '\u{0301}' => 2,
+                    '\u{030C}' => 3,
+                    _ => 4,
+                };
+                parsed.marks.push((position.checked_sub(1)?, tone));
+            },
+            '\u{0308}' => {
+                let last = parsed.letters.last_mut()?;
+                if *last != 'u' {
+                    return None;
+                }
+                *last = 'v';
+            },
+            _ => {
+                let (base, tone) = split_tone_mark(ch)?;
+                parsed.marks.push((position, tone));
+                parsed.letters.push(base);
+            },
+        }
+    }
+
+    if parsed.letters.is_empty() {
+        None
+    } else {
+        Some(parsed)
+    }
+}
+
+/// Finds where a syllable containing a tone mark ends.
+///
+/// The syllable runs through the rest of its vowels, and then takes a final `n`, `ng`, or erhua `r`
+/// unless that letter begins the next syllable.
+fn marked_syllable_end(letters: &[char], mark: usize, end: usize) -> usize {
+    let mut idx = mark + 1;
+    while idx < end && is_vowel(letters[idx]) {
+        idx += 1;
+    }
+
+    let next_is_vowel = |idx: usize| idx < end && is_vowel(letters[idx]);
+
+    if idx < end && letters[idx] == 'n' {
+        let followed_by_g = idx + 1 < end && letters[idx + 1] == 'g';
+        if followed_by_g && !next_is_vowel(idx + 2) {
+            // "ng" closes the syllable.
+            idx += 2;
+        } else if followed_by_g || !next_is_vowel(idx + 1) {
+            // "n" closes the syllable. In dàngāo, the "g" starts the next one.
+            idx += 1;
+        }
+        // Otherwise, the "n" starts the next syllable, as in fǎnàn.
+    }
+
+    if idx < end && letters[idx] == 'r' && !next_is_vowel(idx + 1) {
+        idx += 1;
+    }
+
+    idx
+}
+
+/// Splits parsed pinyin into syllables.
+fn syllables(parsed: &Parsed) -> Vec<Syllable> {
+    let mut boundaries: Vec<usize> = parsed.breaks.clone();
+    boundaries.extend(parsed.digits.iter().map(|(position, _)| *position));
+
+    // Syllables with tone marks can be delimited by looking at the letters after the mark.
+    for (mark, _) in parsed.marks.iter() {
+        let chunk_end = boundaries
+            .iter()
+            .copied()
+            .filter(|boundary| boundary > mark)
+            .min()
+            .unwrap_or(parsed.letters.len());
+        boundaries.push(marked_syllable_end(&parsed.letters, *mark, chunk_end));
+    }
+
+    boundaries.push(parsed.letters.len());
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut result: Vec<Syllable> = Vec::new();
+    let mut start = 0;
+
+    for end in boundaries {
+        if end <= start {
+            continue;
+        }
+
+        let letters: String = parsed.letters[start..end].iter().collect();
+        let digit = parsed.digits.iter().find(|(position, _)| *position == end);
+        let mark = parsed.marks.iter().find(|(mark, _)| *mark >= start && *mark < end);
+        let tone = digit.or(mark).map(|(_, tone)| *tone).unwrap_or(NEUTRAL_TONE);
+
+        // A lone "r" is the erhua suffix of the previous syllable.
+        if letters == "r" && !result.is_empty() {
+            result.last_mut().unwrap().erhua = true;
+        } else {
+            result.push(Syllable {
+                letters,
+                tone,
+                erhua: false,
+            });
+        }
+
+        start = end;
+    }
+
+    // An "r" tacked on to the end of a syllable (other than "er" itself) is also erhua.
+    for syllable in result.iter_mut() {
+        if syllable.letters.len() > 1 && syllable.letters.ends_with('r') && syllable.letters != "er" {
+            syllable.letters.pop();
+            syllable.erhua = true;
+        }
+    }
+
+    result
+}
+
+/// Splits a pinyin string into syllables.
+///
+/// Returns `None` if the text isn't pinyin.
+pub fn to_syllables(text: &str) -> Option<Vec<Syllable>> {
+    parse(text).map(|parsed| syllables(&parsed))
+}
+
+/// Converts pinyin to tone numbers, with syllables separated by spaces.
+///
+/// `nǐhǎo` becomes `ni3 hao3`. The neutral tone is written as `5`.
+/// Text which isn't pinyin is returned unchanged.
+pub fn to_tone_numbers(text: &str) -> String {
+    match to_syllables(text) {
+        Some(syllables) => syllables
+            .iter()
+            .map(|syllable| {
+                let erhua = if syllable.erhua { " r5" } else { "" };
+                format!("{}{}{}", syllable.letters, syllable.tone, erhua)
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => text.to_string(),
+    }
+}
+
+/// Converts pinyin to tone marks, with syllables run together.
+///
+/// `ni3 hao3` becomes `nǐhǎo`. Apostrophes are added before syllables starting with a vowel.
+/// Text which isn't pinyin is returned unchanged.
+pub fn to_tone_marks(text: &str) -> String {
+    let syllables = match to_syllables(text) {
+        Some(syllables) => syllables,
+        None => return text.to_string(),
+    };
+
+    let mut result = String::new();
+    for syllable in syllables.iter() {
+        let letters: Vec<char> = syllable.letters.chars().collect();
+
+        if !result.is_empty() && letters.first().copied().map(is_vowel).unwrap_or(false) {
+            result.push('\'');
+        }
+
+        // The mark goes on "a" or "e" if there is one, on the "o" of "ou",
+        // and otherwise on the last vowel.
+        let mark_position = letters.iter().position(|ch| *ch == 'a' || *ch == 'e')
+            .or_else(|| syllable.letters.find("ou"))
+            .or_else(|| letters.iter().rposition(|ch| is_vowel(*ch)));
+
+        for (idx, ch) in letters.iter().enumerate() {
+            let ch = if Some(idx) == mark_position && syllable.tone != NEUTRAL_TONE {
+                tone_mark(*ch, syllable.tone)
+            } else {
+                *ch
+            };
+            result.push(if ch == 'v' { 'ü' } else { ch });
+        }
+
+        if syllable.erhua {
+            result.push('r');
+        }
+    }
+    result
+}
+
+/// Lines up the letters of an answer against the expected syllables.
+///
+/// Returns the span of letters for each syllable. Erhua is optional: the answer may include or
+/// leave off the `r` of any syllable which takes it.
+fn align(letters: &[char], expected: &[Syllable], start: usize) -> Option<Vec<(usize, usize)>> {
+    let syllable = match expected.first() {
+        Some(syllable) => syllable,
+        None => return if start == letters.len() { Some(Vec::new()) } else { None },
+    };
+
+    let syllable_letters: Vec<char> = syllable.letters.chars().collect();
+    let end = start + syllable_letters.len();
+    if end > letters.len() || letters[start..end] != syllable_letters[..] {
+        return None;
+    }
+
+    let mut candidates = Vec::new();
+    if syllable.erhua && letters.get(end) == Some(&'r') {
+        candidates.push(end + 1);
+    }
+    candidates.push(end);
+
+    for candidate in candidates {
+        if let Some(mut rest) = align(letters, &expected[1..], candidate) {
+            rest.insert(0, (start, candidate));
+            return Some(rest);
+        }
+    }
+    None
+}
+
+/// Compares an answer against the expected pinyin.
+///
+/// Returns `None` if either isn't pinyin.
+pub fn compare(answer: &str, expected: &str) -> Option<Match> {
+    let answer = parse(answer)?;
+    let expected = to_syllables(expected)?;
+
+    let spans = match align(&answer.letters, &expected, 0) {
+        Some(spans) => spans,
+        None => return Some(Match::NoMatch),
+    };
+
+    // For erhua, the answer may put a break or a tone number before the "r", as in dian3 r5.
+    let boundaries = |idx: usize| {
+        let (start, end) = spans[idx];
+        let has_r = end - start > expected[idx].letters.len();
+        (start, end, if has_r { end - 1 } else { end })
+    };
+
+    // Any explicit break in the answer must fall between syllables.
+    for position in answer.breaks.iter() {
+        let between = (0..spans.len()).any(|idx| {
+            let (start, end, before_r) = boundaries(idx);
+            *position == start || *position == end || *position == before_r
+        });
+        if !between {
+            return Some(Match::NoMatch);
+        }
+    }
+
+    let mut tones: Vec<Option<Tone>> = vec![None; spans.len()];
+
+    for (mark, tone) in answer.marks.iter() {
+        let idx = spans.iter().position(|(start, end)| mark >= start && mark < end).unwrap();
+        if tones[idx].is_some() {
+            return Some(Match::NoMatch);
+        }
+        tones[idx] = Some(*tone);
+    }
+
+    // A tone number goes at the end of its syllable.
+    for (position, tone) in answer.digits.iter() {
+        let idx = (0..spans.len()).find(|idx| {
+            let (_, end, before_r) = boundaries(*idx);
+            *position == end || *position == before_r
+        });
+
+        match idx {
+            Some(idx) => match tones[idx] {
+                None => tones[idx] = Some(*tone),
+                // The neutral "r5" after a toned erhua syllable.
+                Some(_) if *tone == NEUTRAL_TONE => (),
+                Some(_) => return Some(Match::NoMatch),
+            },
+            None => return Some(Match::NoMatch),
+        }
+    }
+
+    let tones_match = expected
+        .iter()
+        .zip(tones.iter())
+        .all(|(syllable, tone)| syllable.tone == tone.unwrap_or(NEUTRAL_TONE));
+
+    if tones_match {
+        Some(Match::Exact)
+    } else {
+        Some(Match::WrongTone)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tone_marks_and_numbers() {
+        assert_eq!(to_tone_marks("ni3 hao3"), "nǐhǎo");
+        assert_eq!(to_tone_marks("lv3 you2"), "lǚyóu");
+        assert_eq!(to_tone_marks("xi1 an1"), "xī'ān");
+        assert_eq!(to_tone_marks("de5"), "de");
+        assert_eq!(to_tone_marks("yi4 dian3 r5"), "yìdiǎnr");
+        assert_eq!(to_tone_marks("zou3 lu4"), "zǒulù");
+
+        assert_eq!(to_tone_numbers("nǐhǎo"), "ni3 hao3");
+        assert_eq!(to_tone_numbers("zhōngguó"), "zhong1 guo2");
+        assert_eq!(to_tone_numbers("dàngāo"), "dan4 gao1");
+        assert_eq!(to_tone_numbers("Xī'ān"), "xi1 an1");
+        assert_eq!(to_tone_numbers("nǚ"), "nv3");
+        assert_eq!(to_tone_numbers("yìdiǎnr"), "yi4 dian3 r5");
+        assert_eq!(to_tone_numbers("érzi"), "er2 zi5");
+    }
+
+    #[test]
+    fn compare_exact() {
+        let exact = Some(Match::Exact);
+        assert_eq!(compare("wo3", "wo3"), exact);
+        assert_eq!(compare("wǒ", "wo3"), exact);
+        assert_eq!(compare("WO3", "wo3"), exact);
+        assert_eq!(compare("de", "de5"), exact);
+        assert_eq!(compare("de0", "de5"), exact);
+        assert_eq!(compare("nv3", "nv3"), exact);
+        assert_eq!(compare("nü3", "nv3"), exact);
+        assert_eq!(compare("nu:3", "nv3"), exact);
+        assert_eq!(compare("nǚ", "nv3"), exact);
+        assert_eq!(compare("ni3hao3", "ni3 hao3"), exact);
+        assert_eq!(compare("nǐ hǎo", "ni3 hao3"), exact);
+        assert_eq!(compare("xī'ān", "xi1 an1"), exact);
+        assert_eq!(compare("xi1'an1", "xi1 an1"), exact);
+        assert_eq!(compare("ba4ba", "ba4 ba5"), exact);
+    }
+
+    #[test]
+    fn compare_erhua() {
+        let exact = Some(Match::Exact);
+        assert_eq!(compare("yi4dian3r", "yi4 dian3 r5"), exact);
+        assert_eq!(compare("yi4dianr3", "yi4 dian3 r5"), exact);
+        assert_eq!(compare("yi4 dian3 r5", "yi4 dian3 r5"), exact);
+        assert_eq!(compare("yìdiǎnr", "yi4 dian3 r5"), exact);
+        assert_eq!(compare("yi4dian3", "yi4 dian3 r5"), exact);
+        assert_eq!(compare("nar3", "na3 r5"), exact);
+        assert_eq!(compare("zhèr", "zhe4 r5"), exact);
+    }
+
+    #[test]
+    fn compare_wrong_tone() {
+        let wrong_tone = Some(Match::WrongTone);
+        assert_eq!(compare("wo2", "wo3"), wrong_tone);
+        assert_eq!(compare("wó", "wo3"), wrong_tone);
+        assert_eq!(compare("wo", "wo3"), wrong_tone);
+        assert_eq!(compare("ni3hao2", "ni3 hao3"), wrong_tone);
+        assert_eq!(compare("de1", "de5"), wrong_tone);
+        assert_eq!(compare("xian1", "xi1 an1"), wrong_tone);
+    }
+
+    #[test]
+    fn compare_no_match() {
+        let no_match = Some(Match::NoMatch);
+        assert_eq!(compare("ni3", "wo3"), no_match);
+        assert_eq!(compare("xia n1", "xi1 an1"), no_match);
+        assert_eq!(compare("ni3hao3ma", "ni3 hao3"), no_match);
+        assert_eq!(compare("nu3", "nv3"), no_match);
+        assert_eq!(compare("n3i", "ni3"), no_match);
+        assert_eq!(compare("你好", "ni3 hao3"), None);
+    }
+}
@@ -0,0 +1,244 @@
+//! Profile cards: a comrade's standing, drawn as an image for `!profile`.
+
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+use crate::draw::{crop_circle, fill_circle, fill_rect, overlay, DrawError, FontRegistry, RenderOptions};
+
+/// A profile as the server reports it, with just what the card shows.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub user_id: String,
+    pub display_name: String,
+    pub roles: Vec<String>,
+    pub credit: i64,
+    pub yuan: i64,
+    /// When the profile was registered, in RFC 3339.
+    pub created: String,
+    pub hsk: Option<u8>,
+    pub hanzi_count: usize,
+    pub hsk_coverage: Vec<HskCoverage>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HskCoverage {
+    pub hsk_level: u8,
+    pub known: usize,
+    pub total: usize,
+    pub percent: f64,
+}
+
+pub const CARD_WIDTH: u32 = 800;
+pub const CARD_HEIGHT: u32 = 400;
+
+/// The credit at which the gauge is full. Everyone starts halfway, at 1000.
+pub const CREDIT_GAUGE_MAX: i64 = 2000;
+const STARTING_CREDIT: i64 = 1000;
+
+const AVATAR_SIZE: u32 = 160;
+const MARGIN: i64 = 40;
+
+pub(crate) const BACKGROUND: Rgba<u8> = Rgba([43, 45, 49, 255]);
+pub(crate) const PANEL: Rgba<u8> = Rgba([30, 31, 34, 255]);
+pub(crate) const RED: Rgba<u8> = Rgba([222, 41, 16, 255]);
+pub(crate) const GOLD: Rgba<u8> = Rgba([255, 222, 0, 255]);
+pub(crate) const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+pub(crate) const MUTED: Rgba<u8> = Rgba([170, 172, 180, 255]);
+
+/// How full the credit gauge is, from 0 to 1.
+pub fn credit_fraction(credit: i64) -> f32 {
+    (credit as f32 / CREDIT_GAUGE_MAX as f32).clamp(0.0, 1.0)
+}
+
+/// Red for comrades in trouble, gold for those slipping, and green for those in good standing.
+fn credit_colour(credit: i64) -> Rgba<u8> {
+    if credit < STARTING_CREDIT / 2 {
+        RED
+    } else if credit < STARTING_CREDIT {
+        GOLD
+    } else {
+        Rgba([67, 181, 129, 255])
+    }
+}
+
+/// Draws the profile card. Without an avatar, the first letter of the comrade's name stands in for it.
+pub fn render_card(fonts: &FontRegistry, profile: &Profile, avatar: Option<&RgbaImage>) -> Result<RgbaImage, DrawError> {
+    let mut card = RgbaImage::from_pixel(CARD_WIDTH, CARD_HEIGHT, BACKGROUND);
+    fill_rect(&mut card, 0, 0, 12, CARD_HEIGHT, RED);
+
+    let display_name = match profile.display_name.trim() {
+        "" => "Comrade",
+        name => name,
+    };
+
+    // Avatar, in a gold ring
+    let radius = AVATAR_SIZE as f32 / 2.0;
+    let (center_x, center_y) = (MARGIN as f32 + radius, MARGIN as f32 + radius);
+    fill_circle(&mut card, center_x, center_y, radius + 4.0, GOLD);
+    match avatar {
+        Some(avatar) => {
+            let avatar = image::imageops::resize(avatar, AVATAR_SIZE, AVATAR_SIZE, FilterType::Triangle);
+            overlay(&mut card, &crop_circle(&avatar), MARGIN, MARGIN);
+        },
+        None => {
+            fill_circle(&mut card, center_x, center_y, radius, RED);
+            let initial: String = display_name.chars().take(1).collect();
+            let initial = text(fonts, &initial.to_uppercase(), 96.0, GOLD)?;
+            let (x, y) = centered(&initial, center_x, center_y);
+            overlay(&mut card, &initial, x, y);
+        },
+    }
+
+    // Name, roles and how long they've been around
+    let left = MARGIN + AVATAR_SIZE as i64 + 32;
+    overlay(&mut card, &text(fonts, display_name, 56.0, WHITE)?, left, MARGIN);
+    if !profile.roles.is_empty() {
+        overlay(&mut card, &text(fonts, &profile.roles.join("  "), 26.0, MUTED)?, left, MARGIN + 76);
+    }
+    if let Some(date) = profile.created.get(..10) {
+        overlay(&mut card, &text(fonts, &format!("Since {}", date), 22.0, MUTED)?, left, MARGIN + 114);
+    }
+
+    // HSK badge
+    let (badge_width, badge_height) = (130, 64);
+    let badge_x = CARD_WIDTH as i64 - MARGIN - badge_width as i64;
+    let (badge, badge_colour, label_colour) = match profile.hsk {
+        Some(level) => (format!("HSK {}", level), RED, GOLD),
+        None => ("HSK ?".to_string(), PANEL, MUTED),
+    };
+    fill_rect(&mut card, badge_x, MARGIN, badge_width, badge_height, badge_colour);
+    let badge = text(fonts, &badge, 36.0, label_colour)?;
+    let (x, y) = centered(&badge, badge_x as f32 + badge_width as f32 / 2.0, MARGIN as f32 + badge_height as f32 / 2.0);
+    overlay(&mut card, &badge, x, y);
+
+    // Credit gauge, with a tick where everyone starts
+    let gauge_y = 270;
+    let gauge_width = CARD_WIDTH - 2 * MARGIN as u32;
+    overlay(&mut card, &text(fonts, "Social credit", 26.0, MUTED)?, MARGIN, gauge_y - 42);
+    let credit = text(fonts, &profile.credit.to_string(), 30.0, WHITE)?;
+    overlay(&mut card, &credit, MARGIN + gauge_width as i64 - credit.width() as i64, gauge_y - 46);
+    fill_rect(&mut card, MARGIN, gauge_y, gauge_width, 24, PANEL);
+    let filled = (gauge_width as f32 * credit_fraction(profile.credit)).round() as u32;
+    fill_rect(&mut card, MARGIN, gauge_y, filled, 24, credit_colour(profile.credit));
+    let tick_x = MARGIN + (gauge_width as f32 * credit_fraction(STARTING_CREDIT)) as i64;
+    fill_rect(&mut card, tick_x - 1, gauge_y - 4, 2, 32, WHITE);
+
+    // Hanzi, yuan, and how much of each HSK level they know
+    let stats_y = 326;
+    let stats = format!("Hanzi {}   Yuan {}", profile.hanzi_count, profile.yuan);
+    overlay(&mut card, &text(fonts, &stats, 30.0, WHITE)?, MARGIN, stats_y);
+
+    let mut x = CARD_WIDTH as i64 - MARGIN;
+    for coverage in profile.hsk_coverage.iter().rev() {
+        let bar_width = 56;
+        x -= bar_width as i64;
+        let label = text(fonts, &format!("HSK{}", coverage.hsk_level), 18.0, MUTED)?;
+        overlay(&mut card, &label, x, stats_y);
+        fill_rect(&mut card, x, stats_y + 28, bar_width, 10, PANEL);
+        let filled = (bar_width as f64 * (coverage.percent / 100.0).clamp(0.0, 1.0)).round() as u32;
+        fill_rect(&mut card, x, stats_y + 28, filled, 10, GOLD);
+        x -= 12;
+    }
+
+    Ok(card)
+}
+
+pub(crate) fn text(fonts: &FontRegistry, text: &str, size: f32, colour: Rgba<u8>) -> Result<RgbaImage, DrawError> {
+    let options = RenderOptions {
+        size,
+        foreground: colour,
+        padding: 0,
+        ..RenderOptions::default()
+    };
+    fonts.render(text, &options)
+}
+
+/// Where to put `image` so it's centered on a point.
+pub(crate) fn centered(image: &RgbaImage, center_x: f32, center_y: f32) -> (i64, i64) {
+    let x = center_x - image.width() as f32 / 2.0;
+    let y = center_y - image.height() as f32 / 2.0;
+    (x.round() as i64, y.round() as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::draw::FONT_DIR;
+
+    fn profile() -> Profile {
+        Profile {
+            user_id: "1".to_string(),
+            display_name: "毛泽东".to_string(),
+            roles: vec!["Party".to_string(), "Learner".to_string()],
+            credit: 1250,
+            yuan: 30,
+            created: "2022-01-23T10:00:00Z".to_string(),
+            hsk: Some(2),
+            hanzi_count: 321,
+            hsk_coverage: (1..=6)
+                .map(|level| HskCoverage {
+                    hsk_level: level,
+                    known: 10,
+                    total: 100,
+                    percent: 100.0 / level as f64,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn cards() {
+        let fonts = FontRegistry::load(FONT_DIR).unwrap();
+        let profile = profile();
+
+        let blue = RgbaImage::from_pixel(64, 64, Rgba([0, 0, 255, 255]));
+        let card = render_card(&fonts, &profile, Some(&blue)).unwrap();
+        assert_eq!(card.dimensions(), (CARD_WIDTH, CARD_HEIGHT));
+        let middle = (MARGIN as u32 + AVATAR_SIZE / 2, MARGIN as u32 + AVATAR_SIZE / 2);
+        assert_eq!(*card.get_pixel(middle.0, middle.1), Rgba([0, 0, 255, 255]));
+
+        let card = render_card(&fonts, &profile, None).unwrap();
+        assert_eq!(card.dimensions(), (CARD_WIDTH, CARD_HEIGHT));
+        assert_eq!(*card.get_pixel(MARGIN as u32 + 8, MARGIN as u32 + AVATAR_SIZE / 2), RED);
+
+        let nobody = Profile {
+            display_name: " ".to_string(),
+            roles: vec![],
+            hsk: None,
+            hsk_coverage: vec![],
+            created: String::new(),
+            ..profile
+        };
+        assert!(render_card(&fonts, &nobody, None).is_ok());
+    }
+
+    #[test]
+    fn credit_gauge() {
+        assert_eq!(credit_fraction(-50), 0.0);
+        assert_eq!(credit_fraction(1000), 0.5);
+        assert_eq!(credit_fraction(5000), 1.0);
+        assert_eq!(credit_colour(100), RED);
+        assert_eq!(credit_colour(999), GOLD);
+    }
+
+    #[test]
+    fn deserialize() {
+        let json = serde_json::json!({
+            "userId": "1",
+            "displayName": "Mao",
+            "roles": ["Party"],
+            "credit": 1000,
+            "yuan": 0,
+            "created": "2022-01-23T10:00:00Z",
+            "hsk": null,
+            "hanziCount": 0,
+            "hskCoverage": [{ "hskLevel": 1, "known": 0, "total": 150, "percent": 0.0 }],
+        });
+        let profile: Profile = serde_json::from_value(json).unwrap();
+        assert_eq!(profile.hsk, None);
+        assert_eq!(profile.hsk_coverage[0].total, 150);
+    }
+}
@@ -0,0 +1,323 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Serialize, Deserialize};
+
+use crate::exams::Question;
+use crate::pinyin;
+
+/// The number of options in a [QuestionType::MultipleChoice] question.
+const CHOICES: usize = 4;
+const CHOICE_LABELS: [&str; CHOICES] = ["A", "B", "C", "D"];
+
+pub const BLANK: &str = "＿";
+
+/// A single word in a deck. Every [QuestionType] is generated from the same card.
+///
+/// In deck files, cards keep the field names from the original HSK exams.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Card {
+    #[serde(rename = "question")]
+    pub hanzi: String,
+    #[serde(rename = "validAnswers")]
+    pub pinyin: Vec<String>,
+    pub meaning: String,
+}
+
+/// Which way round a [Card] is asked.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum QuestionType {
+    /// Show the hanzi, answer with the pinyin. This is the classic HSK exam question.
+    #[default]
+    HanziToPinyin,
+    /// Show the hanzi, answer with the meaning.
+    HanziToMeaning,
+    /// Show the meaning, answer with the hanzi.
+    MeaningToHanzi,
+    /// Show the pinyin (with tone marks), answer with the hanzi.
+    PinyinToHanzi,
+    /// Show the hanzi along with several meanings. Answer with the letter or the meaning itself.
+    MultipleChoice,
+    /// Show a word with one hanzi blanked out, along with its meaning. Answer with the missing hanzi.
+    /// Only words of two or more hanzi get this question.
+    FillInTheBlank,
+}
+
+impl QuestionType {
+    pub const ALL: [QuestionType; 6] = [
+        QuestionType::HanziToPinyin,
+        QuestionType::HanziToMeaning,
+        QuestionType::MeaningToHanzi,
+        QuestionType::PinyinToHanzi,
+        QuestionType::MultipleChoice,
+        QuestionType::FillInTheBlank,
+    ];
+
+    /// The name used in deck files, like `hanziToPinyin`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuestionType::HanziToPinyin => "hanziToPinyin",
+            QuestionType::HanziToMeaning => "hanziToMeaning",
+            QuestionType::MeaningToHanzi => "meaningToHanzi",
+            QuestionType::PinyinToHanzi => "pinyinToHanzi",
+            QuestionType::MultipleChoice => "multipleChoice",
+            QuestionType::FillInTheBlank => "fillInTheBlank",
+        }
+    }
+
+    /// Whether answers should be compared as pinyin.
+    pub fn answers_in_pinyin(&self) -> bool {
+        *self == QuestionType::HanziToPinyin
+    }
+}
+
+impl std::str::FromStr for QuestionType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<QuestionType, String> {
+        QuestionType::ALL
+            .iter()
+            .find(|kind| kind.name() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown question type: {}", name))
+    }
+}
+
+/// Generates the questions for a deck: one for each card in each mode, in that order.
+///
+/// Cards which don't suit a mode are skipped (for instance, single hanzi can't be a
+/// [QuestionType::FillInTheBlank]). The `seed` decides multiple choice options and blanks.
+pub fn derive_deck(cards: &[Card], modes: &[QuestionType], seed: u64) -> Vec<Question> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut deck = Vec::new();
+
+    for mode in modes.iter() {
+        for card in cards.iter() {
+            if let Some(question) = derive_question(cards, card, *mode, &mut rng) {
+                deck.push(question);
+            }
+        }
+    }
+
+    deck
+}
+
+fn derive_question(cards: &[Card], card: &Card, kind: QuestionType, rng: &mut StdRng) -> Option<Question> {
+    let (question, valid_answers) = match kind {
+        QuestionType::HanziToPinyin => (card.hanzi.clone(), card.pinyin.clone()),
+        QuestionType::HanziToMeaning => (card.hanzi.clone(), meaning_answers(&card.meaning)),
+        QuestionType::MeaningToHanzi => {
+            // Synonyms in the deck are just as good.
+            let answers = distinct(cards.iter().filter(|other| other.meaning == card.meaning).map(|other| &other.hanzi));
+            (card.meaning.clone(), answers)
+        },
+        QuestionType::PinyinToHanzi => {
+            let reading = card.pinyin.first()?;
+            let prompt = pinyin::to_tone_marks(reading);
+            // Homophones in the deck are just as good.
+            let answers = distinct(cards.iter().filter(|other| same_reading(other, card)).map(|other| &other.hanzi));
+            (prompt, answers)
+        },
+        QuestionType::MultipleChoice => {
+            let mut others: Vec<&Card> = cards.iter().filter(|other| other.meaning != card.meaning).collect();
+            if others.is_empty() {
+                return None;
+            }
+            others.shuffle(rng);
+            others.truncate(CHOICES - 1);
+
+            let mut choices: Vec<&Card> = others;
+            choices.push(card);
+            choices.shuffle(rng);
+
+            let correct = choices.iter().position(|choice| choice.hanzi == card.hanzi).unwrap();
+            let mut lines = vec![card.hanzi.clone()];
+            for (label, choice) in CHOICE_LABELS.iter().zip(choices.iter()) {
+                lines.push(format!("{}. {}", label, choice.meaning));
+            }
+
+            let mut answers = vec![CHOICE_LABELS[correct].to_string()];
+            answers.extend(meaning_answers(&card.meaning));
+            (lines.join("\n"), answers)
+        },
+        QuestionType::FillInTheBlank => {
+            let hanzi: Vec<char> = card.hanzi.chars().collect();
+            if hanzi.len() < 2 {
+                return None;
+            }
+
+            let blank = rng.gen_range(0..hanzi.len());
+            let prompt: String = hanzi
+                .iter()
+                .enumerate()
+                .map(|(idx, ch)| if idx == blank { BLANK.to_string() } else { ch.to_string() })
+                .collect();
+            (format!("{}\n{}", prompt, card.meaning), vec![hanzi[blank].to_string()])
+        },
+    };
+
+    Some(Question {
+        question,
+        valid_answers,
+        meaning: card.meaning.clone(),
+        kind,
+    })
+}
+
+fn distinct<'a>(words: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for word in words {
+        if !result.contains(word) {
+            result.push(word.clone());
+        }
+    }
+    result
+}
+
+fn same_reading(card: &Card, other: &Card) -> bool {
+    let normalize = |reading: &String| pinyin::to_tone_numbers(reading);
+    card.pinyin
+        .iter()
+        .any(|reading| other.pinyin.iter().any(|other_reading| normalize(reading) == normalize(other_reading)))
+}
+
+/// The answers accepted for a meaning.
+///
+/// Deck meanings are written for people, like `"〜's [possessive]"` or `"to eat; food"`.
+/// Notes in brackets are dropped, alternatives separated by `;`, `,` or `/` are each accepted,
+/// and a leading "to " is optional.
+pub fn meaning_answers(meaning: &str) -> Vec<String> {
+    let mut stripped = String::new();
+    let mut depth = 0;
+    for ch in meaning.chars() {
+        match ch {
+            '[' | '(' => depth += 1,
+            ']' | ')' if depth > 0 => depth -= 1,
+            '〜' => (),
+            _ if depth == 0 => stripped.push(ch),
+            _ => (),
+        }
+    }
+
+    let mut answers = vec![meaning.to_string()];
+    for alternative in stripped.split(&[';', ',', '/'][..]) {
+        let alternative = alternative.trim();
+        if alternative.is_empty() {
+            continue;
+        }
+        answers.push(alternative.to_string());
+        if let Some(verb) = alternative.strip_prefix("to ") {
+            answers.push(verb.trim().to_string());
+        }
+    }
+
+    distinct(answers.iter())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card(hanzi: &str, pinyin: &str, meaning: &str) -> Card {
+        Card {
+            hanzi: hanzi.to_string(),
+            pinyin: vec![pinyin.to_string()],
+            meaning: meaning.to_string(),
+        }
+    }
+
+    fn cards() -> Vec<Card> {
+        vec![
+            card("是", "shi4", "is"),
+            card("事", "shi4", "matter"),
+            card("中国", "zhong1guo2", "China"),
+            card("吃", "chi1", "to eat; food"),
+            card("的", "de5", "〜's [possessive]"),
+        ]
+    }
+
+    #[test]
+    fn one_question_per_card_per_mode() {
+        let cards = cards();
+        let deck = derive_deck(&cards, &[QuestionType::HanziToPinyin, QuestionType::HanziToMeaning], 0);
+        assert_eq!(deck.len(), 10);
+        assert_eq!(deck[0].question, "是");
+        assert_eq!(deck[0].valid_answers, vec!["shi4"]);
+        assert_eq!(deck[5].kind, QuestionType::HanziToMeaning);
+    }
+
+    #[test]
+    fn pinyin_to_hanzi_accepts_homophones() {
+        let cards = cards();
+        let deck = derive_deck(&cards, &[QuestionType::PinyinToHanzi], 0);
+        assert_eq!(deck[0].question, "shì");
+        assert_eq!(deck[0].valid_answers, vec!["是", "事"]);
+        assert!(deck[0].is_correct("事"));
+        assert_eq!(deck[2].question, "zhōngguó");
+    }
+
+    #[test]
+    fn meaning_questions() {
+        let cards = cards();
+        let deck = derive_deck(&cards, &[QuestionType::MeaningToHanzi, QuestionType::HanziToMeaning], 0);
+        assert_eq!(deck[0].question, "is");
+        assert!(deck[0].is_correct("是"));
+
+        let eat = &deck[8];
+        assert_eq!(eat.question, "吃");
+        assert!(eat.is_correct("to eat"));
+        assert!(eat.is_correct("Eat"));
+        assert!(eat.is_correct("food"));
+        assert!(!eat.is_correct("drink"));
+
+        assert!(deck[9].is_correct("'s"));
+    }
+
+    #[test]
+    fn multiple_choice() {
+        let cards = cards();
+        let deck = derive_deck(&cards, &[QuestionType::MultipleChoice], 0);
+        assert_eq!(deck.len(), cards.len());
+
+        let question = &deck[2];
+        let lines: Vec<&str> = question.question.lines().collect();
+        assert_eq!(lines.len(), 1 + CHOICES);
+        assert_eq!(lines[0], "中国");
+
+        let label = &question.valid_answers[0];
+        assert!(lines.contains(&format!("{}. China", label).as_str()));
+        assert!(question.is_correct(label));
+        assert!(question.is_correct(&label.to_lowercase()));
+        assert!(question.is_correct("china"));
+    }
+
+    #[test]
+    fn fill_in_the_blank() {
+        let cards = cards();
+        let deck = derive_deck(&cards, &[QuestionType::FillInTheBlank], 0);
+        assert_eq!(deck.len(), 1);
+
+        let question = &deck[0];
+        assert!(question.question == "中＿\nChina" || question.question == "＿国\nChina");
+        let missing = if question.question.starts_with('中') { "国" } else { "中" };
+        assert!(question.is_correct(missing));
+    }
+
+    #[test]
+    fn derivation_is_seeded() {
+        let cards = cards();
+        let modes = [QuestionType::MultipleChoice];
+        let questions = |seed| -> Vec<String> {
+            derive_deck(&cards, &modes, seed).into_iter().map(|question| question.question).collect()
+        };
+        assert_eq!(questions(7), questions(7));
+    }
+
+    #[test]
+    fn meanings() {
+        assert_eq!(meaning_answers("〜's [possessive]"), vec!["〜's [possessive]", "'s"]);
+        assert_eq!(meaning_answers("to eat; food"), vec!["to eat; food", "to eat", "eat", "food"]);
+        assert_eq!(meaning_answers("I"), vec!["I"]);
+    }
+}
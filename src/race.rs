@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use serenity::model::id::UserId;
+
+use crate::clock::Clock;
+use crate::exams::{Exam, Question};
+use crate::pinyin::Match;
+
+/// Points for being first to answer a question correctly.
+pub const POINTS_PER_WIN: u32 = 10;
+/// Extra points for each question won in a row, after the first.
+pub const STREAK_BONUS: u32 = 5;
+pub const MAX_STREAK_BONUS: u32 = 20;
+
+/// How long to wait after a question is won or times out before asking the next one.
+const RACE_PAUSE: u64 = 2000;
+
+/// A race through an [Exam] in which every question goes to the whole channel.
+///
+/// The first player to answer correctly wins the question. Wrong answers cost nothing, and players
+/// may answer as often as they like until someone gets it right or time runs out.
+/// Like [crate::exams::Examiner], a race is driven by calling [Race::tick()].
+#[derive(Debug)]
+pub struct Race {
+    questions: Vec<Question>,
+    timelimit: u64,
+    clock: Box<dyn Clock>,
+
+    current_question_index: isize,
+    current_question_asked_at: u64,
+    open: bool,
+    pause_until: Option<u64>,
+    stopped: bool,
+
+    players: HashMap<UserId, PlayerScore>,
+    /// Players in the order they first answered. Used to break ties.
+    joined: Vec<UserId>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RaceTick {
+    Nothing,
+    Pause,
+    NextQuestion(Question),
+    /// Nobody answered the question in time.
+    Timeout(Question),
+    Finished(RaceResult),
+}
+
+/// How an answer to the open question went.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RaceAnswer {
+    /// The player was first, and scored `points`. `streak` counts the questions they've won in a row.
+    Won { points: u32, streak: u32 },
+    /// The right syllables with the wrong tones. Races need exact answers, so this doesn't score.
+    WrongTone,
+    Incorrect,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PlayerScore {
+    points: u32,
+    wins: u32,
+    streak: u32,
+    best_streak: u32,
+}
+
+/// Where a player finished.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Standing {
+    /// Starts at 1. Players with the same points share a place.
+    pub place: usize,
+    pub player: UserId,
+    pub points: u32,
+    pub wins: u32,
+    pub best_streak: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaceResult {
+    /// Everyone who answered at least once, best first.
+    pub standings: Vec<Standing>,
+    pub num_questions: usize,
+    pub num_asked: usize,
+    /// Whether the race was ended early with [Race::stop()].
+    pub stopped: bool,
+}
+
+impl RaceResult {
+    /// The players who placed first, second or third. Only players who scored make the podium.
+    pub fn podium(&self) -> Vec<&Standing> {
+        self.standings
+            .iter()
+            .filter(|standing| standing.place <= 3 && standing.points > 0)
+            .collect()
+    }
+}
+
+impl Race {
+    /// Starts a race through [Exam::num_questions] questions from the exam's deck, shuffled by `seed`.
+    pub fn make(exam: &Exam, clock: Box<dyn Clock>, seed: u64) -> Race {
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
+        use rand::SeedableRng;
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut questions = exam.deck.clone();
+        questions.shuffle(&mut rng);
+        questions.truncate(exam.num_questions);
+
+        Race {
+            questions,
+            timelimit: exam.timelimit as u64,
+            clock,
+            current_question_index: -1,
+            current_question_asked_at: 0,
+            open: false,
+            pause_until: None,
+            stopped: false,
+            players: HashMap::new(),
+            joined: vec![],
+        }
+    }
+
+    pub fn tick(&mut self) -> RaceTick {
+        let now = self.clock.now();
+
+        if self.stopped {
+            return RaceTick::Finished(self.result());
+        }
+
+        if let Some(pause_until) = self.pause_until {
+            if now < pause_until {
+                return RaceTick::Pause;
+            }
+            self.pause_until = None;
+        }
+
+        if self.open {
+            let deadline = self.current_question_asked_at + self.timelimit;
+            if now >= deadline {
+                self.open = false;
+                for score in self.players.values_mut() {
+                    score.streak = 0;
+                }
+                self.pause_until = Some(deadline + RACE_PAUSE);
+                return RaceTick::Timeout(self.current_question().clone());
+            }
+            return RaceTick::Nothing;
+        }
+
+        let next_index = (self.current_question_index + 1) as usize;
+        if next_index >= self.questions.len() {
+            return RaceTick::Finished(self.result());
+        }
+
+        self.current_question_index += 1;
+        self.current_question_asked_at = now;
+        self.open = true;
+        RaceTick::NextQuestion(self.current_question().clone())
+    }
+
+    /// Checks a player's answer against the open question.
+    ///
+    /// Returns `None` if no question is waiting for an answer, for instance because someone
+    /// else already won it.
+    pub fn answer(&mut self, player: UserId, answer: &str) -> Option<RaceAnswer> {
+        if !self.open {
+            return None;
+        }
+
+        if let Entry::Vacant(entry) = self.players.entry(player) {
+            entry.insert(PlayerScore::default());
+            self.joined.push(player);
+        }
+
+        match self.current_question().check(answer) {
+            Match::Exact => (),
+            Match::WrongTone => return Some(RaceAnswer::WrongTone),
+            Match::NoMatch => return Some(RaceAnswer::Incorrect),
+        }
+
+        self.open = false;
+        self.pause_until = Some(self.clock.now() + RACE_PAUSE);
+
+        for (other, score) in self.players.iter_mut() {
+            if *other != player {
+                score.streak = 0;
+            }
+        }
+
+        let score = self.players.get_mut(&player).unwrap();
+        score.streak += 1;
+        score.best_streak = score.best_streak.max(score.streak);
+        score.wins += 1;
+        let points = POINTS_PER_WIN + (STREAK_BONUS * (score.streak - 1)).min(MAX_STREAK_BONUS);
+        score.points += points;
+
+        Some(RaceAnswer::Won {
+            points,
+            streak: score.streak,
+        })
+    }
+
+    /// Ends the race early. The next tick reports the standings so far.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn time_left(&self) -> u64 {
+        if !self.open {
+            return 0;
+        }
+        let elapsed = self.clock.now().saturating_sub(self.current_question_asked_at);
+        self.timelimit.saturating_sub(elapsed)
+    }
+
+    pub fn num_players(&self) -> usize {
+        self.players.len()
+    }
+
+    fn current_question(&self) -> &Question {
+        &self.questions[self.current_question_index as usize]
+    }
+
+    pub fn result(&self) -> RaceResult {
+        let mut players: Vec<(usize, UserId)> = self.joined.iter().copied().enumerate().collect();
+        players.sort_by_key(|(joined, player)| {
+            let score = &self.players[player];
+            (std::cmp::Reverse(score.points), std::cmp::Reverse(score.wins), *joined)
+        });
+
+        let mut standings: Vec<Standing> = Vec::new();
+        for (idx, (_joined, player)) in players.into_iter().enumerate() {
+            let score = &self.players[&player];
+            let place = match standings.last() {
+                Some(previous) if previous.points == score.points => previous.place,
+                _ => idx + 1,
+            };
+            standings.push(Standing {
+                place,
+                player,
+                points: score.points,
+                wins: score.wins,
+                best_streak: score.best_streak,
+            });
+        }
+
+        RaceResult {
+            standings,
+            num_questions: self.questions.len(),
+            num_asked: (self.current_question_index + 1) as usize,
+            stopped: self.stopped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::exams::PromptStyle;
+    use crate::questions::{derive_deck, Card, QuestionType};
+
+    const ALICE: UserId = UserId(1);
+    const BOB: UserId = UserId(2);
+    const CAROL: UserId = UserId(3);
+
+    fn race(num_questions: usize) -> (Race, SimulatedClock) {
+        let cards: Vec<Card> = (0..num_questions)
+            .map(|i| Card {
+                hanzi: format!("{}", i),
+                pinyin: vec![format!("a{}", i)],
+                meaning: format!("{}", i),
+            })
+            .collect();
+        let modes = vec![QuestionType::HanziToMeaning];
+        let exam = Exam {
+            name: "race".to_string(),
+            deck: derive_deck(&cards, &modes, 0),
+            cards,
+            modes,
+            num_questions,
+            max_wrong: None,
+            timelimit: 5000,
+            hsk_level: 1,
+            prompt_style: PromptStyle::Text,
+        };
+        let clock = SimulatedClock::new();
+        (Race::make(&exam, Box::new(clock.clone()), 0), clock)
+    }
+
+    fn next_question(race: &mut Race, clock: &SimulatedClock) -> Question {
+        clock.advance(RACE_PAUSE);
+        match race.tick() {
+            RaceTick::NextQuestion(question) => question,
+            tick => panic!("Expected RaceTick::NextQuestion(_), but found {:?}", tick),
+        }
+    }
+
+    fn finish(race: &mut Race, clock: &SimulatedClock) -> RaceResult {
+        clock.advance(RACE_PAUSE);
+        match race.tick() {
+            RaceTick::Finished(result) => result,
+            tick => panic!("Expected RaceTick::Finished(_), but found {:?}", tick),
+        }
+    }
+
+    #[test]
+    fn first_correct_answer_wins() {
+        let (mut race, clock) = race(1);
+        let question = next_question(&mut race, &clock);
+
+        assert_eq!(race.answer(BOB, "nope"), Some(RaceAnswer::Incorrect));
+        assert_eq!(race.answer(ALICE, &question.question), Some(RaceAnswer::Won { points: POINTS_PER_WIN, streak: 1 }));
+        assert_eq!(race.answer(BOB, &question.question), None);
+
+        let result = finish(&mut race, &clock);
+        assert_eq!(result.standings.len(), 2);
+        assert_eq!(result.standings[0].player, ALICE);
+        assert_eq!(result.standings[1].player, BOB);
+        assert_eq!(result.podium().len(), 1);
+    }
+
+    #[test]
+    fn streaks() {
+        let (mut race, clock) = race(6);
+
+        let mut points = vec![];
+        for _ in 0..6 {
+            let question = next_question(&mut race, &clock);
+            if let Some(RaceAnswer::Won { points: won, .. }) = race.answer(ALICE, &question.question) {
+                points.push(won);
+            }
+        }
+        assert_eq!(points, vec![10, 15, 20, 25, 30, 30]);
+
+        let result = finish(&mut race, &clock);
+        assert_eq!(result.standings[0].best_streak, 6);
+        assert_eq!(result.standings[0].points, 130);
+    }
+
+    #[test]
+    fn streaks_are_broken() {
+        let (mut race, clock) = race(4);
+
+        let question = next_question(&mut race, &clock);
+        race.answer(ALICE, &question.question);
+        let question = next_question(&mut race, &clock);
+        race.answer(BOB, &question.question);
+        let question = next_question(&mut race, &clock);
+        assert_eq!(race.answer(ALICE, &question.question), Some(RaceAnswer::Won { points: 10, streak: 1 }));
+
+        // Nobody answers in time.
+        next_question(&mut race, &clock);
+        clock.advance(5000);
+        assert!(matches!(race.tick(), RaceTick::Timeout(_)));
+
+        let result = finish(&mut race, &clock);
+        assert_eq!(result.num_asked, 4);
+        assert_eq!(result.standings[0].player, ALICE);
+        assert_eq!(result.standings[0].best_streak, 1);
+    }
+
+    #[test]
+    fn ties_share_a_place() {
+        let (mut race, clock) = race(3);
+
+        let question = next_question(&mut race, &clock);
+        race.answer(ALICE, &question.question);
+        let question = next_question(&mut race, &clock);
+        race.answer(BOB, &question.question);
+        let question = next_question(&mut race, &clock);
+        assert_eq!(race.answer(CAROL, &format!("{}?", question.question)), Some(RaceAnswer::Incorrect));
+        race.stop();
+
+        let result = finish(&mut race, &clock);
+        assert!(result.stopped);
+        assert_eq!(result.num_asked, 3);
+        let places: Vec<(UserId, usize)> = result.standings.iter().map(|s| (s.player, s.place)).collect();
+        assert_eq!(places, vec![(ALICE, 1), (BOB, 1), (CAROL, 3)]);
+        assert_eq!(result.podium().len(), 2);
+    }
+
+    #[test]
+    fn pause_between_questions() {
+        let (mut race, clock) = race(2);
+        let question = next_question(&mut race, &clock);
+        race.answer(ALICE, &question.question);
+        assert!(matches!(race.tick(), RaceTick::Pause));
+        assert_eq!(race.answer(BOB, "anything"), None);
+        next_question(&mut race, &clock);
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Serialize, Deserialize};
+
+use crate::dictionary::Dictionary;
+use crate::exams::{Answer, Exam, PromptStyle, Question};
+use crate::questions::{Card, QuestionType};
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// The number of cards in a single `!review` session.
+pub const REVIEW_SIZE: usize = 20;
+
+/// The scheduling state of a single card for a single user, following the SM-2 algorithm.
+///
+/// Reviews drill pronunciation, so every card is asked as [QuestionType::HanziToPinyin].
+///
+/// See: https://www.supermemo.com/en/archives1990-2015/english/ol/sm2
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReviewCard {
+    pub question: String,
+    pub valid_answers: Vec<String>,
+    pub meaning: String,
+
+    /// How easy the card is to remember. Starts at 2.5 and never drops below 1.3.
+    pub easiness: f64,
+    /// The number of days to wait after the last review.
+    pub interval: i64,
+    /// The number of times in a row the card has been recalled correctly.
+    pub repetitions: u32,
+    /// When the card is next due, in milliseconds since the epoch.
+    pub due: i64,
+}
+
+impl ReviewCard {
+    /// Creates a card which has never been reviewed. It is due immediately.
+    pub fn new(question: &Question, now: i64) -> ReviewCard {
+        ReviewCard {
+            question: question.question.clone(),
+            valid_answers: question.valid_answers.clone(),
+            meaning: question.meaning.clone(),
+            easiness: 2.5,
+            interval: 0,
+            repetitions: 0,
+            due: now,
+        }
+    }
+
+    /// Reschedules the card after a review.
+    ///
+    /// The `grade` is the SM-2 quality of the response, from 0 (total blackout) to 5 (perfect).
+    /// Anything below 3 counts as a lapse and the card starts over.
+    pub fn review(&mut self, grade: u8, now: i64) {
+        assert!(grade <= 5, "Grade must be between 0 and 5");
+
+        if grade < 3 {
+            self.repetitions = 0;
+            self.interval = 1;
+        } else {
+            self.interval = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (self.interval as f64 * self.easiness).round() as i64,
+            };
+            self.repetitions += 1;
+        }
+
+        let q = (5 - grade) as f64;
+        self.easiness = (self.easiness + 0.1 - q * (0.08 + q * 0.02)).max(1.3);
+        self.due = now + self.interval * MILLIS_PER_DAY;
+    }
+
+    pub fn is_due(&self, now: i64) -> bool {
+        self.due <= now
+    }
+
+    pub fn to_question(&self) -> Question {
+        Question {
+            question: self.question.clone(),
+            valid_answers: self.valid_answers.clone(),
+            meaning: self.meaning.clone(),
+            kind: QuestionType::HanziToPinyin,
+        }
+    }
+}
+
+/// Converts an exam answer into an SM-2 grade.
+///
+/// Returns `None` for [Answer::Quit], since the question was never really attempted.
+pub fn grade(answer: &Answer) -> Option<u8> {
+    match answer {
+        Answer::Correct(_) => Some(4),
+        Answer::WrongTone(_) => Some(3),
+        Answer::Incorrect(_) => Some(1),
+        Answer::Timeout => Some(0),
+        Answer::Quit => None,
+    }
+}
+
+/// Picks the questions for a review session.
+///
+/// Cards which are due come first, most overdue first.
+/// Then come mined words which have never been reviewed, as long as the dictionary knows how to
+/// pronounce them.
+pub fn select_review_deck(
+    cards: &[ReviewCard],
+    mined_words: &[String],
+    dictionary: &Dictionary,
+    now: i64,
+    limit: usize,
+) -> Vec<Question> {
+    let mut due_cards: Vec<&ReviewCard> = cards.iter().filter(|card| card.is_due(now)).collect();
+    due_cards.sort_by_key(|card| card.due);
+
+    let mut deck: Vec<Question> = due_cards.iter().map(|card| card.to_question()).collect();
+
+    for word in mined_words.iter() {
+        let scheduled = cards.iter().any(|card| &card.question == word);
+        if scheduled {
+            continue;
+        }
+
+        if let Some(entry) = dictionary.lookup(word) {
+            deck.push(Question {
+                question: entry.word.clone(),
+                valid_answers: entry.pinyin.clone(),
+                meaning: entry.meaning.clone(),
+                kind: QuestionType::HanziToPinyin,
+            });
+        }
+    }
+
+    deck.truncate(limit);
+    deck
+}
+
+/// Wraps a review deck in an [Exam] so it can be administered by an [crate::exams::Examiner].
+pub fn review_exam(deck: Vec<Question>) -> Exam {
+    let cards = deck
+        .iter()
+        .map(|question| Card {
+            hanzi: question.question.clone(),
+            pinyin: question.valid_answers.clone(),
+            meaning: question.meaning.clone(),
+        })
+        .collect();
+
+    Exam {
+        name: "review".to_string(),
+        cards,
+        modes: vec![QuestionType::HanziToPinyin],
+        num_questions: deck.len(),
+        deck,
+        max_wrong: None,
+        timelimit: 15000,
+        hsk_level: 0,
+        prompt_style: PromptStyle::Text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card(question: &str) -> ReviewCard {
+        let question = Question {
+            question: question.to_string(),
+            valid_answers: vec!["ni3hao3".to_string()],
+            meaning: "hello".to_string(),
+            kind: QuestionType::HanziToPinyin,
+        };
+        ReviewCard::new(&question, 0)
+    }
+
+    #[test]
+    fn sm2_intervals() {
+        let mut card = card("你好");
+        assert!(card.is_due(0));
+
+        card.review(4, 0);
+        assert_eq!(card.interval, 1);
+        assert_eq!(card.due, MILLIS_PER_DAY);
+
+        card.review(4, card.due);
+        assert_eq!(card.interval, 6);
+
+        card.review(5, card.due);
+        assert_eq!(card.interval, 15);
+        assert_eq!(card.repetitions, 3);
+        assert!((card.easiness - 2.6).abs() < 1e-9);
+
+        card.review(1, card.due);
+        assert_eq!(card.interval, 1);
+        assert_eq!(card.repetitions, 0);
+    }
+
+    #[test]
+    fn easiness_floor() {
+        let mut card = card("你好");
+        for _ in 0..10 {
+            card.review(0, 0);
+        }
+        assert_eq!(card.easiness, 1.3);
+    }
+
+    #[test]
+    fn select_due_then_new() {
+        let mut later = card("以后");
+        later.review(5, 0);
+        let mut overdue = card("以前");
+        overdue.due = -10;
+        let cards = vec![card("现在"), later, overdue];
+
+        let dictionary = Dictionary::default();
+        let deck = select_review_deck(&cards, &["现在".to_string()], &dictionary, 0, 10);
+        let questions: Vec<&str> = deck.iter().map(|q| q.question.as_str()).collect();
+        assert_eq!(questions, vec!["以前", "现在"]);
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::exams::{Answer, Exam, ExamScore};
+use crate::questions::QuestionType;
+
+/// How a single question went, without the text of the answer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Outcome {
+    Correct,
+    WrongTone,
+    Incorrect,
+    Timeout,
+    Quit,
+}
+
+impl Outcome {
+    pub fn of(answer: &Answer) -> Outcome {
+        match answer {
+            Answer::Correct(_) => Outcome::Correct,
+            Answer::WrongTone(_) => Outcome::WrongTone,
+            Answer::Incorrect(_) => Outcome::Incorrect,
+            Answer::Timeout => Outcome::Timeout,
+            Answer::Quit => Outcome::Quit,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Correct => "correct",
+            Outcome::WrongTone => "wrongTone",
+            Outcome::Incorrect => "incorrect",
+            Outcome::Timeout => "timeout",
+            Outcome::Quit => "quit",
+        }
+    }
+
+    /// How much the outcome counts as a mistake, weighted the same way as in an exam.
+    pub fn mistakes(&self) -> f64 {
+        match self {
+            Outcome::Correct | Outcome::Quit => 0.0,
+            Outcome::WrongTone => 0.5,
+            Outcome::Incorrect | Outcome::Timeout => 1.0,
+        }
+    }
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Outcome, String> {
+        [Outcome::Correct, Outcome::WrongTone, Outcome::Incorrect, Outcome::Timeout, Outcome::Quit]
+            .iter()
+            .find(|outcome| outcome.name() == name)
+            .copied()
+            .ok_or_else(|| format!("Unknown outcome: {}", name))
+    }
+}
+
+/// A single graded question from an [ExamRecord].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GradedAnswer {
+    pub question: String,
+    pub kind: QuestionType,
+    pub outcome: Outcome,
+    /// How long the examinee took to answer.
+    pub millis: u64,
+}
+
+/// Everything worth keeping about an exam once it's over.
+///
+/// The exam's settings are copied in, so records stay meaningful after a deck is edited.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExamRecord {
+    pub exam_name: String,
+    pub practice: bool,
+    pub num_questions: usize,
+    pub max_wrong: Option<usize>,
+    /// In milliseconds.
+    pub timelimit: usize,
+    pub passed: bool,
+    pub completed: bool,
+    pub answers: Vec<GradedAnswer>,
+}
+
+impl ExamRecord {
+    pub fn new(exam: &Exam, practice: bool, score: &ExamScore) -> ExamRecord {
+        let answers = score.graded_questions
+            .iter()
+            .enumerate()
+            .map(|(idx, (question, answer))| GradedAnswer {
+                question: question.question.clone(),
+                kind: question.kind,
+                outcome: Outcome::of(answer),
+                millis: score.result.question_millis.get(idx).copied().unwrap_or(0),
+            })
+            .collect();
+
+        ExamRecord {
+            exam_name: exam.name.clone(),
+            practice,
+            num_questions: exam.num_questions,
+            max_wrong: exam.max_wrong,
+            timelimit: exam.timelimit,
+            passed: score.result.passed,
+            completed: score.result.completed,
+            answers,
+        }
+    }
+}
+
+/// How often a card is missed. The same card asked in a different [QuestionType] is counted separately.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardStats {
+    pub question: String,
+    pub kind: QuestionType,
+    /// The number of times the card was answered or timed out. Quitting doesn't count.
+    pub attempts: usize,
+    pub correct: usize,
+    pub wrong_tone: usize,
+    pub incorrect: usize,
+    pub timeouts: usize,
+    /// Mistakes per attempt, from 0 to 1. A wrong tone is half a mistake.
+    pub error_rate: f64,
+    pub average_millis: u64,
+}
+
+impl CardStats {
+    fn mistakes(&self) -> f64 {
+        self.error_rate * self.attempts as f64
+    }
+}
+
+/// How often a particular configuration of a deck is passed. Practice runs aren't counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassRate {
+    pub exam_name: String,
+    pub num_questions: usize,
+    pub max_wrong: Option<usize>,
+    pub timelimit: usize,
+    pub attempts: usize,
+    pub passes: usize,
+    /// Exams which ended because the examinee gave up.
+    pub abandoned: usize,
+    /// From 0 to 1.
+    pub pass_rate: f64,
+}
+
+/// Totals up every graded answer in the records, per card. The hardest cards come first.
+///
+/// Cards with fewer than `min_attempts` attempts are left out, since a couple of misses say
+/// little about a card.
+pub fn card_stats(records: &[ExamRecord], min_attempts: usize) -> Vec<CardStats> {
+    let mut totals: HashMap<(&str, QuestionType), (Vec<Outcome>, u64)> = HashMap::new();
+
+    for record in records.iter() {
+        for answer in record.answers.iter() {
+            if answer.outcome == Outcome::Quit {
+                continue;
+            }
+            let (outcomes, millis) = totals.entry((&answer.question, answer.kind)).or_default();
+            outcomes.push(answer.outcome);
+            *millis += answer.millis;
+        }
+    }
+
+    let mut stats: Vec<CardStats> = totals
+        .into_iter()
+        .filter(|(_key, (outcomes, _millis))| outcomes.len() >= min_attempts.max(1))
+        .map(|((question, kind), (outcomes, millis))| {
+            let count = |outcome: Outcome| outcomes.iter().filter(|o| **o == outcome).count();
+            let attempts = outcomes.len();
+            let mistakes: f64 = outcomes.iter().map(|outcome| outcome.mistakes()).sum();
+            CardStats {
+                question: question.to_string(),
+                kind,
+                attempts,
+                correct: count(Outcome::Correct),
+                wrong_tone: count(Outcome::WrongTone),
+                incorrect: count(Outcome::Incorrect),
+                timeouts: count(Outcome::Timeout),
+                error_rate: mistakes / attempts as f64,
+                average_millis: millis / attempts as u64,
+            }
+        })
+        .collect();
+
+    stats.sort_by(|a, b| {
+        b.error_rate
+            .partial_cmp(&a.error_rate)
+            .unwrap()
+            .then(b.attempts.cmp(&a.attempts))
+            .then(a.question.cmp(&b.question))
+    });
+    stats
+}
+
+/// The cards missed most often, most mistakes first. Only cards which were missed at least once
+/// are included.
+///
+/// Pass the records of a single user to find their weaknesses.
+pub fn most_missed(records: &[ExamRecord]) -> Vec<CardStats> {
+    let mut stats: Vec<CardStats> = card_stats(records, 1)
+        .into_iter()
+        .filter(|card| card.mistakes() > 0.0)
+        .collect();
+
+    stats.sort_by(|a, b| {
+        b.mistakes()
+            .partial_cmp(&a.mistakes())
+            .unwrap()
+            .then(b.error_rate.partial_cmp(&a.error_rate).unwrap())
+            .then(a.question.cmp(&b.question))
+    });
+    stats
+}
+
+/// The pass rate of each deck, for each combination of settings it was taken with.
+/// Sorted by deck name, then settings.
+pub fn pass_rates(records: &[ExamRecord]) -> Vec<PassRate> {
+    let mut rates: HashMap<(&str, usize, Option<usize>, usize), PassRate> = HashMap::new();
+
+    for record in records.iter().filter(|record| !record.practice) {
+        let key = (record.exam_name.as_str(), record.num_questions, record.max_wrong, record.timelimit);
+        let rate = rates.entry(key).or_insert_with(|| PassRate {
+            exam_name: record.exam_name.clone(),
+            num_questions: record.num_questions,
+            max_wrong: record.max_wrong,
+            timelimit: record.timelimit,
+            attempts: 0,
+            passes: 0,
+            abandoned: 0,
+            pass_rate: 0.0,
+        });
+
+        rate.attempts += 1;
+        if record.passed {
+            rate.passes += 1;
+        }
+        if record.answers.iter().any(|answer| answer.outcome == Outcome::Quit) {
+            rate.abandoned += 1;
+        }
+    }
+
+    let mut rates: Vec<PassRate> = rates
+        .into_values()
+        .map(|mut rate| {
+            rate.pass_rate = rate.passes as f64 / rate.attempts as f64;
+            rate
+        })
+        .collect();
+
+    rates.sort_by(|a, b| {
+        (&a.exam_name, a.num_questions, a.max_wrong, a.timelimit)
+            .cmp(&(&b.exam_name, b.num_questions, b.max_wrong, b.timelimit))
+    });
+    rates
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(exam_name: &str, timelimit: usize, passed: bool, answers: &[(&str, Outcome)]) -> ExamRecord {
+        ExamRecord {
+            exam_name: exam_name.to_string(),
+            practice: false,
+            num_questions: answers.len(),
+            max_wrong: Some(2),
+            timelimit,
+            passed,
+            completed: true,
+            answers: answers
+                .iter()
+                .map(|(question, outcome)| GradedAnswer {
+                    question: question.to_string(),
+                    kind: QuestionType::HanziToPinyin,
+                    outcome: *outcome,
+                    millis: 1000,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn error_rates() {
+        let records = vec![
+            record("hsk1", 5000, true, &[("是", Outcome::Correct), ("的", Outcome::WrongTone), ("吃", Outcome::Quit)]),
+            record("hsk1", 5000, false, &[("是", Outcome::Incorrect), ("的", Outcome::Correct)]),
+            record("hsk1", 5000, false, &[("是", Outcome::Timeout)]),
+        ];
+
+        let stats = card_stats(&records, 1);
+        let questions: Vec<&str> = stats.iter().map(|card| card.question.as_str()).collect();
+        assert_eq!(questions, vec!["是", "的"]);
+
+        assert_eq!(stats[0].attempts, 3);
+        assert_eq!(stats[0].timeouts, 1);
+        assert!((stats[0].error_rate - 2.0 / 3.0).abs() < 1e-9);
+        assert!((stats[1].error_rate - 0.25).abs() < 1e-9);
+
+        assert_eq!(card_stats(&records, 3).len(), 1);
+    }
+
+    #[test]
+    fn most_missed_by_mistakes() {
+        let records = vec![
+            record("hsk1", 5000, true, &[("是", Outcome::Incorrect), ("的", Outcome::Incorrect), ("吃", Outcome::Correct)]),
+            record("hsk1", 5000, true, &[("是", Outcome::Correct), ("的", Outcome::Incorrect)]),
+            record("hsk1", 5000, true, &[("是", Outcome::Correct)]),
+        ];
+
+        let missed = most_missed(&records);
+        let questions: Vec<&str> = missed.iter().map(|card| card.question.as_str()).collect();
+        assert_eq!(questions, vec!["的", "是"]);
+    }
+
+    #[test]
+    fn pass_rates_per_setting() {
+        let mut practice = record("hsk1", 5000, false, &[("是", Outcome::Incorrect)]);
+        practice.practice = true;
+        let records = vec![
+            record("hsk1", 5000, true, &[("是", Outcome::Correct)]),
+            record("hsk1", 5000, false, &[("是", Outcome::Quit)]),
+            record("hsk1", 10000, true, &[("是", Outcome::Correct)]),
+            record("hsk2", 5000, false, &[("的", Outcome::Incorrect)]),
+            practice,
+        ];
+
+        let rates = pass_rates(&records);
+        let summary: Vec<(&str, usize, usize, usize, usize)> = rates
+            .iter()
+            .map(|rate| (rate.exam_name.as_str(), rate.timelimit, rate.attempts, rate.passes, rate.abandoned))
+            .collect();
+        assert_eq!(summary, vec![
+            ("hsk1", 5000, 2, 1, 1),
+            ("hsk1", 10000, 1, 1, 0),
+            ("hsk2", 5000, 1, 0, 0),
+        ]);
+        assert_eq!(rates[0].pass_rate, 0.5);
+    }
+
+    #[test]
+    fn outcome_names() {
+        assert_eq!("wrongTone".parse::<Outcome>(), Ok(Outcome::WrongTone));
+        assert_eq!(serde_json::to_string(&Outcome::WrongTone).unwrap(), "\"wrongTone\"");
+        assert!("nope".parse::<Outcome>().is_err());
+    }
+}
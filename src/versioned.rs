@@ -0,0 +1,101 @@
+//! Saving documents which carry a version, without losing changes made at the same time.
+//!
+//! A document is saved only if it is still at the version it was loaded at, and each save bumps the
+//! version. If someone else saved it in between, the save matches nothing, and the change is made
+//! again, to their version.
+
+use std::future::Future;
+
+/// How many times [update] starts over when the document keeps changing underneath it.
+pub const UPDATE_ATTEMPTS: usize = 10;
+
+/// How one attempt at loading, changing and saving a document went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    Saved,
+    /// Someone else saved the document after it was loaded, so nothing was saved.
+    Conflict,
+    /// There is no such document.
+    Missing,
+}
+
+/// Makes `attempt` until the document is saved, starting over on a conflict. Returns false if
+/// there is no such document. `name` describes the document in the error given if it never saves.
+pub async fn update<F, Fut>(name: &str, mut attempt: F) -> Result<bool, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Attempt, String>>,
+{
+    for _ in 0..UPDATE_ATTEMPTS {
+        match attempt().await? {
+            Attempt::Saved => return Ok(true),
+            Attempt::Missing => return Ok(false),
+            Attempt::Conflict => (),
+        }
+    }
+    Err(format!("{} kept changing while it was being updated", name))
+}
+
+/// Checks that a change made to version `expected` of a document can still go ahead, now that the
+/// document is at version `current`. A change which doesn't say what version it was made to can.
+pub fn check_version(name: &str, expected: Option<u64>, current: u64) -> Result<(), String> {
+    match expected {
+        Some(expected) if expected != current => {
+            Err(format!("{} has changed since version {}. It is now at version {}", name, expected, current))
+        },
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Runs [update] with attempts which go as `attempts` says, and returns how many were made.
+    async fn run(attempts: &[Attempt]) -> (Result<bool, String>, usize) {
+        let mut made = 0;
+        let result = update("Deck hsk1", || {
+            let attempt = attempts[made.min(attempts.len() - 1)];
+            made += 1;
+            async move { Ok(attempt) }
+        }).await;
+        (result, made)
+    }
+
+    #[tokio::test]
+    async fn retries_conflicts() {
+        let (result, made) = run(&[Attempt::Conflict, Attempt::Conflict, Attempt::Saved]).await;
+        assert_eq!(result, Ok(true));
+        assert_eq!(made, 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let (result, made) = run(&[Attempt::Conflict]).await;
+        assert_eq!(result, Err("Deck hsk1 kept changing while it was being updated".to_string()));
+        assert_eq!(made, UPDATE_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn missing() {
+        let (result, made) = run(&[Attempt::Conflict, Attempt::Missing]).await;
+        assert_eq!(result, Ok(false));
+        assert_eq!(made, 2);
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let result = update("Deck hsk1", || async { Err::<Attempt, String>("Mongo is down".to_string()) }).await;
+        assert_eq!(result, Err("Mongo is down".to_string()));
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(check_version("Deck", None, 3), Ok(()));
+        assert_eq!(check_version("Deck", Some(3), 3), Ok(()));
+        assert_eq!(
+            check_version("Deck", Some(2), 3),
+            Err("Deck has changed since version 2. It is now at version 3".to_string()),
+        );
+    }
+}